@@ -1,10 +1,169 @@
 //! Astraliminal game.
 
-use bevy::prelude::App;
+use std::{env, path::Path, process::ExitCode};
 
-use astral_core::AstraliminalPlugins;
+use bevy::prelude::*;
+
+use astral_core::{
+    prelude::{
+        run_replay, AssetGroups, LevelManifest, Replay, ReplayFinished, ReplayMode, RoomGraph,
+    },
+    AstraliminalHeadlessPlugins, AstraliminalPlugins,
+};
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if let Some(path) = flag_value(&args, "--validate-rooms") {
+        return validate_rooms(path);
+    }
+    if let Some(path) = flag_value(&args, "--validate-level") {
+        let assets = flag_value(&args, "--assets").unwrap_or("assets");
+        return validate_level(path, flag_value(&args, "--groups"), assets);
+    }
 
-fn main() {
     let mut app = App::new();
-    app.add_plugins(AstraliminalPlugins).run();
+    if let Some(path) = flag_value(&args, "--record") {
+        app.insert_resource(ReplayMode::Record(path.into()));
+    }
+    let replay = flag_value(&args, "--replay");
+    if let Some(path) = replay {
+        match Replay::load(path) {
+            Ok(replay) => app.insert_resource(ReplayMode::Play(replay)),
+            Err(err) => {
+                eprintln!("{}: {}", path, err);
+                return ExitCode::FAILURE;
+            }
+        };
+    }
+    let expected_hash = match flag_value(&args, "--expect-hash").map(parse_hash) {
+        Some(Err(err)) => {
+            eprintln!("--expect-hash: {}", err);
+            return ExitCode::FAILURE;
+        }
+        Some(Ok(hash)) => Some(hash),
+        None => None,
+    };
+
+    if !args.iter().any(|arg| arg == "--headless") {
+        // A replay in a window is for watching; its hash is only logged.
+        app.add_plugins(AstraliminalPlugins).run();
+        return ExitCode::SUCCESS;
+    }
+
+    app.add_plugins(AstraliminalHeadlessPlugins);
+    match replay {
+        Some(path) => check_replay(path, run_replay(&mut app), expected_hash),
+        None => {
+            app.run();
+            ExitCode::SUCCESS
+        }
+    }
+}
+
+/// The value following `flag` on the command line, if given.
+fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|index| args.get(index + 1))
+        .map(String::as_str)
+}
+
+/// Parse a world-state hash written in hex, as printed at the end of a replay.
+fn parse_hash(hash: &str) -> Result<u64, std::num::ParseIntError> {
+    u64::from_str_radix(hash.trim_start_matches("0x"), 16)
+}
+
+/// Compare the final world-state hash of a replay with `expected`, or the hash it was recorded
+/// with.
+fn check_replay(path: &str, result: Option<ReplayFinished>, expected: Option<u64>) -> ExitCode {
+    let Some(result) = result else {
+        eprintln!("{}: replay did not finish", path);
+        return ExitCode::FAILURE;
+    };
+
+    match expected.or(result.expected) {
+        Some(expected) if expected != result.hash => {
+            eprintln!(
+                "{}: world state {:016x} does not match {:016x}",
+                path, result.hash, expected
+            );
+            ExitCode::FAILURE
+        }
+        Some(_) => {
+            println!("{}: world state {:016x}, ok", path, result.hash);
+            ExitCode::SUCCESS
+        }
+        None => {
+            println!("{}: world state {:016x}", path, result.hash);
+            ExitCode::SUCCESS
+        }
+    }
+}
+
+/// Check a `.rooms.ron` file for broken seams without opening a window.
+fn validate_rooms(path: &str) -> ExitCode {
+    let graph = match RoomGraph::load(path) {
+        Ok(graph) => graph,
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let issues = graph.validate();
+    for issue in &issues {
+        eprintln!("{}: {}", path, issue);
+    }
+
+    if issues.is_empty() {
+        println!(
+            "{}: {} rooms, {} seams, ok",
+            path,
+            graph.rooms.len(),
+            graph.seams.len()
+        );
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Check that everything a `.level.ron` manifest lists exists under `assets`, without opening a
+/// window. Groups are read from the `.groups.ron` file given with `--groups`.
+fn validate_level(path: &str, groups_path: Option<&str>, assets: &str) -> ExitCode {
+    let manifest = match LevelManifest::load(path) {
+        Ok(manifest) => manifest,
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let groups = match groups_path {
+        Some(groups_path) => match AssetGroups::load(groups_path) {
+            Ok(groups) => groups,
+            Err(err) => {
+                eprintln!("{}: {}", groups_path, err);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => AssetGroups::default(),
+    };
+
+    let issues = manifest.validate(&groups, Path::new(assets));
+    for issue in &issues {
+        eprintln!("{}: {}", path, issue);
+    }
+
+    if issues.is_empty() {
+        println!(
+            "{}: level {}, {} assets, ok",
+            path,
+            manifest.id,
+            manifest.asset_paths(&groups).len()
+        );
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
 }
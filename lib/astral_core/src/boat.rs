@@ -0,0 +1,206 @@
+//! Astraliminal's Boat plugin.
+//!
+//! A `Rowing` boat is a `Buoyant` dynamic body that is rowed along a path of waypoints: it surges
+//! forward on every oar stroke and glides between them, turning towards the next waypoint, while
+//! the water takes care of bobbing and tilting. `BoatArrived` is sent at the last waypoint, e.g.
+//! to play the timeline at the docks.
+//!
+//! Bodies resting on a `Deck`, and the player, move along with it every step. They only turn
+//! with its heading, so the waves don't tip them over and the player can walk around on a moving
+//! boat without sliding off.
+
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+
+use crate::{
+    mover::carry,
+    plate::{resting_bodies, BodyQuery},
+    player::Held,
+    schedule::AstralSet,
+};
+
+/// How fast a boat's velocity follows the rowing, per second.
+const RESPONSE: f32 = 2.0;
+/// Angular velocity, in radians per second, a boat turns with per radian off its heading.
+const TURN_GAIN: f32 = 1.5;
+/// How much faster than average a boat is at the height of a stroke.
+const SURGE: f32 = 0.6;
+
+/// A boat rowed along a path. Its forward is its local -Z.
+#[derive(Component, Reflect, Debug, Clone, PartialEq)]
+#[reflect(Component)]
+pub struct Rowing {
+    /// World-space waypoints, rowed through in order.
+    pub path: Vec<Vec3>,
+    /// Average speed.
+    pub speed: f32,
+    /// Seconds between oar strokes.
+    pub stroke_seconds: f32,
+    /// Fastest turn, in radians per second.
+    pub turn_rate: f32,
+    /// How close on the XZ plane the boat must get to a waypoint to go on to the next one.
+    pub arrive_distance: f32,
+    /// Whether the rowers are rowing. The boat drifts otherwise.
+    pub active: bool,
+}
+
+impl Default for Rowing {
+    fn default() -> Self {
+        Self {
+            path: Vec::new(),
+            speed: 2.0,
+            stroke_seconds: 2.5,
+            turn_rate: 0.4,
+            arrive_distance: 2.0,
+            active: true,
+        }
+    }
+}
+
+impl Rowing {
+    /// Forward speed `elapsed` seconds into rowing.
+    pub fn speed_at(&self, elapsed: f32) -> f32 {
+        let stroke = elapsed / self.stroke_seconds.max(f32::EPSILON);
+        self.speed * (1.0 + SURGE * (TAU * stroke).sin())
+    }
+}
+
+/// How far a rowing boat has come, added for you.
+#[derive(Component, Reflect, Debug, Default, Clone, Copy, PartialEq)]
+#[reflect(Component)]
+pub struct RowingState {
+    /// Index of the waypoint being rowed to.
+    pub waypoint: usize,
+    /// Seconds spent rowing.
+    pub elapsed: f32,
+    /// Whether the boat has reached the last waypoint.
+    pub arrived: bool,
+}
+
+/// Something that carries whatever rests on it, e.g. the deck of a boat. Needs a `Collider`.
+#[derive(Component, Reflect, Debug, Default, Clone, Copy)]
+#[reflect(Component)]
+pub struct Deck {
+    /// Transform at the last step.
+    #[reflect(ignore)]
+    previous: Option<Transform>,
+}
+
+/// Sent when a rowing boat reaches the last waypoint of its path.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct BoatArrived {
+    pub boat: Entity,
+}
+
+pub struct AstraliminalBoatPlugin;
+
+impl Plugin for AstraliminalBoatPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Rowing>()
+            .register_type::<RowingState>()
+            .register_type::<Deck>()
+            .add_event::<BoatArrived>()
+            .add_systems(
+                FixedUpdate,
+                (
+                    prepare_boats.in_set(AstralSet::Setup),
+                    (row, carry_on_decks).chain().in_set(AstralSet::Move),
+                ),
+            );
+    }
+}
+
+/// Start new boats at their first waypoint.
+fn prepare_boats(
+    mut commands: Commands,
+    boats: Query<Entity, (Added<Rowing>, Without<RowingState>)>,
+) {
+    for entity in &boats {
+        commands.entity(entity).insert(RowingState::default());
+    }
+}
+
+/// Row boats towards their next waypoint.
+#[allow(clippy::type_complexity)]
+fn row(
+    mut arrived: EventWriter<BoatArrived>,
+    time: Res<Time>,
+    mut boats: Query<(
+        Entity,
+        &Rowing,
+        &mut RowingState,
+        &Position,
+        &Rotation,
+        &mut LinearVelocity,
+        &mut AngularVelocity,
+    )>,
+) {
+    let delta = time.delta_seconds();
+    for (entity, rowing, mut state, position, rotation, mut linear, mut angular) in &mut boats {
+        if !rowing.active || state.arrived {
+            continue;
+        }
+
+        let mut offset = Vec3::ZERO;
+        while let Some(waypoint) = rowing.path.get(state.waypoint) {
+            offset = (*waypoint - position.0) * Vec3::new(1.0, 0.0, 1.0);
+            if offset.length() > rowing.arrive_distance {
+                break;
+            }
+            state.waypoint += 1;
+        }
+        if state.waypoint >= rowing.path.len() {
+            state.arrived = true;
+            arrived.send(BoatArrived { boat: entity });
+            continue;
+        }
+
+        state.elapsed += delta;
+        let desired = offset.normalize_or_zero() * rowing.speed_at(state.elapsed);
+        let horizontal = linear.0 * Vec3::new(1.0, 0.0, 1.0);
+        let velocity = horizontal.lerp(desired, (RESPONSE * delta).min(1.0));
+        linear.x = velocity.x;
+        linear.z = velocity.z;
+
+        let forward = (rotation.0 * Vec3::NEG_Z).xz().normalize_or_zero();
+        let heading = offset.xz().normalize_or_zero();
+        // Positive turns left, like a rotation around +Y.
+        let off_course = heading.perp_dot(forward).atan2(forward.dot(heading));
+        angular.y = (off_course * TURN_GAIN).clamp(-rowing.turn_rate, rowing.turn_rate);
+    }
+}
+
+/// Move what rests on a deck by however the deck moved since the last step.
+fn carry_on_decks(
+    mut decks: Query<(Entity, &mut Deck)>,
+    mut transforms: Query<&mut Transform>,
+    collisions: Res<Collisions>,
+    bodies: BodyQuery,
+    held: Query<(), With<Held>>,
+) {
+    for (entity, mut deck) in &mut decks {
+        let Ok(to) = transforms.get(entity).copied() else {
+            continue;
+        };
+        let Some(from) = deck.previous.replace(to) else {
+            continue;
+        };
+        if from == to {
+            continue;
+        }
+
+        let (yaw, ..) = (to.rotation * from.rotation.inverse()).to_euler(EulerRot::YXZ);
+        for body in resting_bodies(entity, &collisions, &bodies) {
+            if held.contains(body) {
+                continue;
+            }
+            if let Ok(mut transform) = transforms.get_mut(body) {
+                let rotation = transform.rotation;
+                carry(&mut transform, &from, &to);
+                transform.rotation = Quat::from_rotation_y(yaw) * rotation;
+            }
+        }
+    }
+}
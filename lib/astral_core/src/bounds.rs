@@ -0,0 +1,383 @@
+//! Astraliminal's Bounds plugin.
+//!
+//! Objects that leave `WorldBounds` or touch a `KillVolume` are lost. The player respawns at the
+//! `RespawnPoint` behind a screen fade, `Critical` objects return to where they were placed with
+//! their original scale and anything else is despawned. Losing a critical object is logged, so
+//! playtests show where puzzles break.
+//!
+//! Kill volumes can be placed in Blender like triggers, with the `kill_volume = true` custom
+//! property.
+
+use bevy::{gltf::GltfExtras, prelude::*, utils::HashMap};
+use bevy_xpbd_3d::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    fade::ScreenFade,
+    player::{DropHeld, Player},
+    schedule::AstralSet,
+    trigger::{cube_empty_collider, read_extras},
+};
+
+/// Seconds a recovered object takes to grow back to its size, and the screen takes to fade in
+/// after the player respawns.
+const RECOVER_SECONDS: f32 = 0.5;
+
+/// The box objects have to stay in.
+#[derive(Resource, Reflect, Debug, Clone, Copy, PartialEq)]
+#[reflect(Resource)]
+pub struct WorldBounds {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Default for WorldBounds {
+    fn default() -> Self {
+        Self {
+            min: Vec3::new(-1000.0, -100.0, -1000.0),
+            max: Vec3::new(1000.0, 1000.0, 1000.0),
+        }
+    }
+}
+
+impl WorldBounds {
+    /// Whether a point is inside the bounds.
+    pub fn contains(&self, point: Vec3) -> bool {
+        point.cmpge(self.min).all() && point.cmple(self.max).all()
+    }
+}
+
+/// A sensor volume that loses everything that touches it. Needs a `Collider`.
+#[derive(Component, Reflect, Debug, Default, Clone, Copy)]
+#[reflect(Component)]
+pub struct KillVolume;
+
+/// Marker for objects a puzzle can't be solved without. They are returned instead of despawned.
+#[derive(Component, Reflect, Debug, Default, Clone, Copy)]
+#[reflect(Component)]
+pub struct Critical;
+
+/// Where a critical object or the player was placed, added for you.
+#[derive(Component, Reflect, Debug, Default, Clone, Copy, PartialEq)]
+#[reflect(Component)]
+pub struct SpawnPoint(pub Transform);
+
+/// Where the player respawns. Set to where the player started until a checkpoint moves it. While
+/// it is unset, the player respawns at their `SpawnPoint`.
+#[derive(Resource, Reflect, Debug, Default, Clone, Copy, PartialEq)]
+#[reflect(Resource)]
+pub struct RespawnPoint(pub Option<Transform>);
+
+/// Why an object was lost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LostReason {
+    OutOfBounds,
+    KillVolume(Entity),
+}
+
+/// Sent when an object is lost, before it is recovered.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct ObjectLost {
+    pub entity: Entity,
+    pub reason: LostReason,
+    /// Where the object was lost.
+    pub position: Vec3,
+}
+
+/// A recovered object growing back to its size.
+#[derive(Component, Debug, Default, Clone, Copy)]
+struct Recovering {
+    elapsed: f32,
+}
+
+/// Custom properties read from Blender objects through glTF extras.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct BoundsExtras {
+    kill_volume: bool,
+}
+
+pub struct AstraliminalBoundsPlugin;
+
+impl Plugin for AstraliminalBoundsPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<WorldBounds>()
+            .register_type::<KillVolume>()
+            .register_type::<Critical>()
+            .register_type::<SpawnPoint>()
+            .register_type::<RespawnPoint>()
+            .init_resource::<WorldBounds>()
+            .init_resource::<RespawnPoint>()
+            .add_event::<ObjectLost>()
+            .add_systems(
+                FixedUpdate,
+                (
+                    (kill_volumes_from_extras, record_spawns)
+                        .chain()
+                        .in_set(AstralSet::Setup),
+                    (find_lost, recover, grow_back)
+                        .chain()
+                        .in_set(AstralSet::Resolve),
+                ),
+            );
+    }
+}
+
+/// Turn Blender objects with the kill volume custom property into kill volumes.
+fn kill_volumes_from_extras(
+    mut commands: Commands,
+    extras: Query<(Entity, &GltfExtras), Added<GltfExtras>>,
+) {
+    for (entity, extras) in &extras {
+        let Ok(extras) = read_extras::<BoundsExtras>(extras) else {
+            continue;
+        };
+        if extras.kill_volume {
+            commands
+                .entity(entity)
+                .insert((KillVolume, cube_empty_collider()));
+        }
+    }
+}
+
+/// Remember where critical objects and the player start, and set up new kill volumes.
+#[allow(clippy::type_complexity)]
+fn record_spawns(
+    mut commands: Commands,
+    mut respawn: ResMut<RespawnPoint>,
+    critical: Query<(Entity, &Transform), (Added<Critical>, Without<SpawnPoint>)>,
+    players: Query<(Entity, &Transform), (Added<Player>, Without<SpawnPoint>)>,
+    kill_volumes: Query<Entity, Added<KillVolume>>,
+) {
+    for (entity, transform) in critical.iter().chain(&players) {
+        commands.entity(entity).insert(SpawnPoint(*transform));
+    }
+    if respawn.0.is_none() {
+        if let Some((_, transform)) = players.iter().next() {
+            respawn.0 = Some(*transform);
+        }
+    }
+    for entity in &kill_volumes {
+        commands
+            .entity(entity)
+            .insert((Sensor, CollidingEntities::default()));
+    }
+}
+
+/// Whether an object can get lost. Static and kinematic bodies stay where levels put them.
+fn is_movable(body: Option<&RigidBody>, is_player: bool) -> bool {
+    is_player || body.is_some_and(RigidBody::is_dynamic)
+}
+
+/// Find the player and dynamic bodies that left the bounds or touched a kill volume. Objects that
+/// are still growing back aren't lost again.
+#[allow(clippy::type_complexity)]
+fn find_lost(
+    mut lost: EventWriter<ObjectLost>,
+    bounds: Res<WorldBounds>,
+    kill_volumes: Query<(Entity, &CollidingEntities), With<KillVolume>>,
+    objects: Query<
+        (Entity, &GlobalTransform, Option<&RigidBody>, Has<Player>),
+        Without<Recovering>,
+    >,
+) {
+    let mut found = HashMap::default();
+    for (entity, transform, body, is_player) in &objects {
+        if is_movable(body, is_player) && !bounds.contains(transform.translation()) {
+            found.insert(entity, LostReason::OutOfBounds);
+        }
+    }
+    for (volume, colliding) in &kill_volumes {
+        for &entity in colliding.iter() {
+            if objects
+                .get(entity)
+                .is_ok_and(|(_, _, body, is_player)| is_movable(body, is_player))
+            {
+                found
+                    .entry(entity)
+                    .or_insert(LostReason::KillVolume(volume));
+            }
+        }
+    }
+
+    let mut found: Vec<_> = found.into_iter().collect();
+    found.sort_by_key(|(entity, _)| *entity);
+    for (entity, reason) in found {
+        let Ok((_, transform, ..)) = objects.get(entity) else {
+            continue;
+        };
+        lost.send(ObjectLost {
+            entity,
+            reason,
+            position: transform.translation(),
+        });
+    }
+}
+
+/// Respawn the player, return critical objects and despawn everything else that was lost.
+#[allow(clippy::type_complexity)]
+fn recover(
+    mut commands: Commands,
+    mut lost: EventReader<ObjectLost>,
+    mut fade: ResMut<ScreenFade>,
+    respawn: Res<RespawnPoint>,
+    mut objects: Query<(
+        &mut Transform,
+        Option<&SpawnPoint>,
+        Option<&Name>,
+        Has<Player>,
+        Option<&mut LinearVelocity>,
+        Option<&mut AngularVelocity>,
+    )>,
+) {
+    for event in lost.read() {
+        let Ok((mut transform, spawn, name, is_player, linear, angular)) =
+            objects.get_mut(event.entity)
+        else {
+            continue;
+        };
+        let name = name.map_or_else(|| format!("{:?}", event.entity), |name| name.to_string());
+
+        let target = if is_player {
+            info!("Player was lost at {} ({:?})", event.position, event.reason);
+            fade.flash(RECOVER_SECONDS);
+            respawn.0.or(spawn.map(|spawn| spawn.0))
+        } else if let Some(spawn) = spawn {
+            warn!(
+                "Critical object {} was lost at {} ({:?}), returning it",
+                name, event.position, event.reason
+            );
+            commands.add(DropHeld(event.entity));
+            commands.entity(event.entity).insert(Recovering::default());
+            Some(spawn.0)
+        } else {
+            info!(
+                "{} was lost at {} ({:?})",
+                name, event.position, event.reason
+            );
+            commands.entity(event.entity).despawn_recursive();
+            None
+        };
+
+        if let Some(target) = target {
+            *transform = target;
+            if let Some(mut linear) = linear {
+                linear.0 = Vec3::ZERO;
+            }
+            if let Some(mut angular) = angular {
+                angular.0 = Vec3::ZERO;
+            }
+        }
+    }
+}
+
+/// Grow recovered objects from nothing back to their original scale.
+fn grow_back(
+    mut commands: Commands,
+    mut objects: Query<(Entity, &mut Recovering, &mut Transform, &SpawnPoint)>,
+    time: Res<Time>,
+) {
+    for (entity, mut recovering, mut transform, spawn) in &mut objects {
+        recovering.elapsed += time.delta_seconds();
+        let t = (recovering.elapsed / RECOVER_SECONDS).min(1.0);
+        // Keep a little size so the collider stays valid.
+        transform.scale = spawn.0.scale * (t * t * (3.0 - 2.0 * t)).max(0.01);
+        if t >= 1.0 {
+            commands.entity(entity).remove::<Recovering>();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::{ecs::event::ManualEventReader, time::TimeUpdateStrategy};
+
+    use super::*;
+
+    const STEP: Duration = Duration::from_millis(100);
+
+    /// A headless app with one tick per update.
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            HierarchyPlugin,
+            TransformPlugin,
+            AstraliminalBoundsPlugin,
+        ))
+        .insert_resource(Time::<Fixed>::from_duration(STEP))
+        .insert_resource(TimeUpdateStrategy::ManualDuration(STEP))
+        .init_resource::<ScreenFade>();
+        app
+    }
+
+    /// Run `updates` frames and count the `ObjectLost` events sent for `entity`.
+    fn lost(app: &mut App, entity: Entity, updates: usize) -> usize {
+        let mut reader = ManualEventReader::<ObjectLost>::default();
+        let mut count = 0;
+        for _ in 0..updates {
+            app.update();
+            count += reader
+                .read(app.world.resource::<Events<ObjectLost>>())
+                .filter(|lost| lost.entity == entity)
+                .count();
+        }
+        count
+    }
+
+    fn translation(app: &App, entity: Entity) -> Vec3 {
+        app.world.get::<Transform>(entity).unwrap().translation
+    }
+
+    #[test]
+    fn player_respawns_at_their_spawn_point_without_a_respawn_point() {
+        let mut app = app();
+        let start = Vec3::new(1.0, 2.0, 3.0);
+        let player = app
+            .world
+            .spawn((
+                Player,
+                TransformBundle::from_transform(Transform::from_translation(start)),
+            ))
+            .id();
+        app.update();
+        app.update();
+        app.world.resource_mut::<RespawnPoint>().0 = None;
+
+        app.world
+            .get_mut::<Transform>(player)
+            .unwrap()
+            .translation
+            .y = -500.0;
+        assert_eq!(lost(&mut app, player, 5), 1);
+        assert_eq!(translation(&app, player), start);
+    }
+
+    #[test]
+    fn critical_objects_are_lost_once() {
+        let mut app = app();
+        let start = Vec3::new(0.0, 1.0, 0.0);
+        let crate_ = app
+            .world
+            .spawn((
+                Critical,
+                RigidBody::Dynamic,
+                TransformBundle::from_transform(Transform::from_translation(start)),
+            ))
+            .id();
+        app.update();
+        app.update();
+
+        app.world
+            .get_mut::<Transform>(crate_)
+            .unwrap()
+            .translation
+            .x = 5000.0;
+        assert_eq!(lost(&mut app, crate_, 10), 1);
+        let transform = app.world.get::<Transform>(crate_).unwrap();
+        assert_eq!(transform.translation, start);
+        assert_eq!(transform.scale, Vec3::ONE);
+    }
+}
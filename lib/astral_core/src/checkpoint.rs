@@ -0,0 +1,474 @@
+//! Astraliminal's Checkpoint plugin.
+//!
+//! Entering a room or a `Checkpoint` trigger takes a `Snapshot` of the world: the player, dynamic
+//! bodies, movers, the logic graph, story flags and the active dimension. Restarting from the
+//! checkpoint puts all of that back in place without reloading anything. Clones made since the
+//! snapshot are removed; materialized paintings stay materialized.
+//!
+//! Reaching a checkpoint also autosaves, so the game continues from it after a restart.
+
+use bevy::{ecs::system::Command, gltf::GltfExtras, prelude::*, utils::HashMap};
+use bevy_xpbd_3d::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    bounds::RespawnPoint,
+    dimension::ActiveDimension,
+    duplicate::CloneOf,
+    fade::ScreenFade,
+    logic::LogicState,
+    mover::{Mover, MoverState},
+    player::{input_allowed, DropHeld, Held, Player},
+    room::RoomChanged,
+    save::{SaveData, SaveGame, SaveSet, SavedCheckpoint},
+    schedule::AstralSet,
+    story::{Blackboard, StoryFlags},
+    trigger::{read_extras, TriggerEntered, TriggerVolume},
+};
+
+/// Seconds the screen takes to fade in after restarting from a checkpoint.
+const RESTART_FADE: f32 = 0.5;
+
+/// Marker for a `TriggerVolume` that is a checkpoint. The trigger's id is the checkpoint's id.
+#[derive(Component, Reflect, Debug, Default, Clone, Copy)]
+#[reflect(Component)]
+pub struct Checkpoint;
+
+/// A body's transform and velocity in a snapshot.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct BodySnapshot {
+    pub transform: Transform,
+    pub linear_velocity: Option<Vec3>,
+    pub angular_velocity: Option<Vec3>,
+}
+
+/// The state of the world when a checkpoint was reached. Entities are kept by id, so a snapshot
+/// can only be restored into the world it was taken from.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Snapshot {
+    /// Id of the checkpoint, or `room:<id>` for room entries.
+    pub id: String,
+    /// Where the player was.
+    pub player: Option<Transform>,
+    /// The player and every dynamic body, including their scale.
+    pub bodies: HashMap<Entity, BodySnapshot>,
+    pub movers: HashMap<Entity, MoverState>,
+    pub logic: Option<LogicState>,
+    pub story_flags: Option<StoryFlags>,
+    pub blackboard: Option<Blackboard>,
+    pub dimension: Option<ActiveDimension>,
+}
+
+impl Snapshot {
+    /// Take a snapshot of `world`.
+    pub fn take(world: &mut World, id: impl Into<String>) -> Self {
+        let player = world
+            .query_filtered::<&Transform, With<Player>>()
+            .iter(world)
+            .next()
+            .copied();
+        let bodies = world
+            .query::<(
+                Entity,
+                &Transform,
+                Option<&RigidBody>,
+                Option<&LinearVelocity>,
+                Option<&AngularVelocity>,
+                Has<Player>,
+            )>()
+            .iter(world)
+            .filter(|(_, _, body, _, _, is_player)| {
+                *is_player || body.is_some_and(RigidBody::is_dynamic)
+            })
+            .map(|(entity, transform, _, linear, angular, _)| {
+                let body = BodySnapshot {
+                    transform: *transform,
+                    linear_velocity: linear.map(|velocity| velocity.0),
+                    angular_velocity: angular.map(|velocity| velocity.0),
+                };
+                (entity, body)
+            })
+            .collect();
+        let movers = world
+            .query::<(Entity, &MoverState)>()
+            .iter(world)
+            .map(|(entity, state)| (entity, *state))
+            .collect();
+
+        Self {
+            id: id.into(),
+            player,
+            bodies,
+            movers,
+            logic: world.get_resource::<LogicState>().cloned(),
+            story_flags: world.get_resource::<StoryFlags>().cloned(),
+            blackboard: world.get_resource::<Blackboard>().cloned(),
+            dimension: world.get_resource::<ActiveDimension>().copied(),
+        }
+    }
+
+    /// Put `world` back into the state of the snapshot.
+    pub fn restore(&self, world: &mut World) {
+        let held: Vec<Entity> = world
+            .query_filtered::<Entity, With<Held>>()
+            .iter(world)
+            .collect();
+        for entity in held {
+            DropHeld(entity).apply(world);
+        }
+
+        for (&entity, body) in &self.bodies {
+            let Some(mut entity) = world.get_entity_mut(entity) else {
+                continue;
+            };
+            if let Some(mut transform) = entity.get_mut::<Transform>() {
+                *transform = body.transform;
+            }
+            if let (Some(velocity), Some(mut linear)) =
+                (body.linear_velocity, entity.get_mut::<LinearVelocity>())
+            {
+                linear.0 = velocity;
+            }
+            if let (Some(velocity), Some(mut angular)) =
+                (body.angular_velocity, entity.get_mut::<AngularVelocity>())
+            {
+                angular.0 = velocity;
+            }
+        }
+
+        for (&entity, state) in &self.movers {
+            let Some(mut entity) = world.get_entity_mut(entity) else {
+                continue;
+            };
+            let pose = entity
+                .get::<Mover>()
+                .map(|mover| mover.path.pose(&state.home, state.progress));
+            if let (Some(pose), Some(mut transform)) = (pose, entity.get_mut::<Transform>()) {
+                *transform = pose;
+            }
+            entity.insert(*state);
+        }
+
+        let clones: Vec<Entity> = world
+            .query_filtered::<Entity, With<CloneOf>>()
+            .iter(world)
+            .filter(|entity| !self.bodies.contains_key(entity))
+            .collect();
+        for entity in clones {
+            world.entity_mut(entity).despawn_recursive();
+        }
+
+        if let Some(logic) = &self.logic {
+            world.insert_resource(logic.clone());
+        }
+        if let Some(story_flags) = &self.story_flags {
+            world.insert_resource(story_flags.clone());
+        }
+        if let Some(blackboard) = &self.blackboard {
+            world.insert_resource(blackboard.clone());
+        }
+        if let Some(dimension) = self.dimension {
+            world.insert_resource(dimension);
+        }
+    }
+}
+
+/// The last checkpoint reached.
+#[derive(Resource, Debug, Default, Clone)]
+pub struct Checkpoints {
+    pub last: Option<Snapshot>,
+}
+
+/// Sent after a snapshot was taken.
+#[derive(Event, Debug, Clone)]
+pub struct CheckpointReached {
+    pub id: String,
+}
+
+/// Send to put the world back to the last checkpoint.
+#[derive(Event, Debug, Default, Clone, Copy)]
+pub struct RestartFromCheckpoint;
+
+/// Sent after the world was put back to a checkpoint.
+#[derive(Event, Debug, Clone)]
+pub struct CheckpointRestored {
+    pub id: String,
+}
+
+/// Custom properties read from Blender objects through glTF extras.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct CheckpointExtras {
+    /// Whether the trigger is a checkpoint.
+    checkpoint: bool,
+}
+
+pub struct AstraliminalCheckpointPlugin;
+
+impl Plugin for AstraliminalCheckpointPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Checkpoint>()
+            .init_resource::<Checkpoints>()
+            .add_event::<CheckpointReached>()
+            .add_event::<RestartFromCheckpoint>()
+            .add_event::<CheckpointRestored>()
+            .add_systems(PreUpdate, restore_checkpoint.in_set(SaveSet::Restore))
+            .add_systems(Update, restart_input.run_if(input_allowed))
+            .add_systems(
+                FixedUpdate,
+                (
+                    (checkpoints_from_extras, place_player)
+                        .chain()
+                        .in_set(AstralSet::Setup),
+                    (reach_checkpoints, restart)
+                        .chain()
+                        .in_set(AstralSet::React),
+                ),
+            )
+            .add_systems(Last, collect_checkpoint.in_set(SaveSet::Collect));
+    }
+}
+
+/// Respawn at the saved checkpoint, if there is one.
+fn restore_checkpoint(mut respawn: ResMut<RespawnPoint>, save: Res<SaveData>) {
+    if let Some(checkpoint) = &save.checkpoint {
+        respawn.0 = Some(checkpoint.player);
+    }
+}
+
+/// Turn Blender triggers with the checkpoint custom property into checkpoints.
+fn checkpoints_from_extras(
+    mut commands: Commands,
+    extras: Query<(Entity, &GltfExtras), Added<GltfExtras>>,
+) {
+    for (entity, extras) in &extras {
+        let Ok(extras) = read_extras::<CheckpointExtras>(extras) else {
+            continue;
+        };
+        if extras.checkpoint {
+            commands.entity(entity).insert(Checkpoint);
+        }
+    }
+}
+
+/// Put a new player at the saved checkpoint.
+fn place_player(mut players: Query<&mut Transform, Added<Player>>, save: Res<SaveData>) {
+    let Some(checkpoint) = &save.checkpoint else {
+        return;
+    };
+    for mut transform in &mut players {
+        *transform = checkpoint.player;
+    }
+}
+
+/// Take a snapshot when the player enters a checkpoint or a room.
+fn reach_checkpoints(
+    mut commands: Commands,
+    mut entered: EventReader<TriggerEntered>,
+    mut room_changes: EventReader<RoomChanged>,
+    checkpoints: Query<&TriggerVolume, With<Checkpoint>>,
+    player: Query<(), With<Player>>,
+) {
+    let reached = entered
+        .read()
+        .filter(|entered| player.contains(entered.entity))
+        .filter_map(|entered| checkpoints.get(entered.trigger).ok())
+        .map(|trigger| trigger.id.clone());
+    let rooms = room_changes
+        .read()
+        .filter_map(|change| change.to.as_ref())
+        .map(|room| format!("room:{}", room));
+
+    // Only the last one matters when several are reached at once.
+    let Some(id) = reached.chain(rooms).last() else {
+        return;
+    };
+    commands.add(move |world: &mut World| {
+        let snapshot = Snapshot::take(world, id.clone());
+        if let Some(player) = snapshot.player {
+            world.resource_mut::<RespawnPoint>().0 = Some(player);
+        }
+        world.resource_mut::<Checkpoints>().last = Some(snapshot);
+        world.send_event(CheckpointReached { id });
+        world.send_event(SaveGame);
+    });
+}
+
+/// Restart from the last checkpoint when the restart key is pressed.
+fn restart_input(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut restarts: EventWriter<RestartFromCheckpoint>,
+) {
+    // TODO: pull key code from config.
+    if keys.just_pressed(KeyCode::KeyR) {
+        restarts.send(RestartFromCheckpoint);
+    }
+}
+
+/// Put the world back to the last checkpoint.
+fn restart(mut commands: Commands, mut restarts: EventReader<RestartFromCheckpoint>) {
+    if restarts.read().last().is_none() {
+        return;
+    }
+    commands.add(|world: &mut World| {
+        let Some(snapshot) = world.resource::<Checkpoints>().last.clone() else {
+            return;
+        };
+        snapshot.restore(world);
+        if let Some(mut fade) = world.get_resource_mut::<ScreenFade>() {
+            fade.flash(RESTART_FADE);
+        }
+        world.send_event(CheckpointRestored { id: snapshot.id });
+    });
+}
+
+/// Copy the last checkpoint into the save data.
+fn collect_checkpoint(mut save: ResMut<SaveData>, checkpoints: Res<Checkpoints>) {
+    let Some(snapshot) = &checkpoints.last else {
+        return;
+    };
+    if let Some(player) = snapshot.player {
+        save.checkpoint = Some(SavedCheckpoint {
+            id: snapshot.id.clone(),
+            player,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::time::TimeUpdateStrategy;
+
+    use super::*;
+    use crate::mover::MoverPath;
+
+    /// A headless app with what the checkpoint plugin needs, ticking once per update.
+    fn app() -> App {
+        let mut app = App::new();
+        let tick = Time::<Fixed>::default().timestep();
+        app.add_plugins((MinimalPlugins, AstraliminalCheckpointPlugin))
+            .insert_resource(TimeUpdateStrategy::ManualDuration(tick))
+            .init_resource::<SaveData>()
+            .init_resource::<RespawnPoint>()
+            .init_resource::<ButtonInput<KeyCode>>()
+            .init_resource::<LogicState>()
+            .init_resource::<StoryFlags>()
+            .init_resource::<ActiveDimension>()
+            .add_event::<TriggerEntered>()
+            .add_event::<RoomChanged>()
+            .add_event::<SaveGame>();
+        app.update();
+        app
+    }
+
+    /// Spawn a player, a resized box and a door halfway open.
+    fn populate(world: &mut World) -> (Entity, Entity, Entity) {
+        let player = world
+            .spawn((
+                Player,
+                Transform::from_xyz(1.0, 0.0, 2.0),
+                RigidBody::Dynamic,
+                LinearVelocity(Vec3::X),
+            ))
+            .id();
+        let crate_ = world
+            .spawn((
+                Transform::from_xyz(0.0, 1.0, 0.0).with_scale(Vec3::splat(3.0)),
+                RigidBody::Dynamic,
+                LinearVelocity::default(),
+                AngularVelocity(Vec3::Y),
+            ))
+            .id();
+        let mover = Mover {
+            path: MoverPath::Slide(Vec3::Y * 4.0),
+            ..default()
+        };
+        let state = MoverState {
+            home: Transform::default(),
+            progress: 0.5,
+            target: 1.0,
+            blocked: false,
+        };
+        let door = world
+            .spawn((mover.path.pose(&state.home, state.progress), mover, state))
+            .id();
+        (player, crate_, door)
+    }
+
+    /// Change everything a snapshot covers.
+    fn scramble(world: &mut World, (player, crate_, door): (Entity, Entity, Entity)) {
+        world.get_mut::<Transform>(player).unwrap().translation = Vec3::splat(9.0);
+        world.entity_mut(player).insert(LinearVelocity(Vec3::NEG_Y));
+        let mut transform = world.get_mut::<Transform>(crate_).unwrap();
+        transform.translation = Vec3::new(5.0, -3.0, 2.0);
+        transform.scale = Vec3::splat(0.2);
+        world
+            .entity_mut(crate_)
+            .insert((Held::default(), AngularVelocity(Vec3::X)));
+        world.get_mut::<MoverState>(door).unwrap().progress = 1.0;
+        world.get_mut::<Transform>(door).unwrap().translation = Vec3::Y * 4.0;
+        world
+            .resource_mut::<LogicState>()
+            .values
+            .insert("door_open".to_string(), true);
+        world
+            .resource_mut::<StoryFlags>()
+            .0
+            .insert("met_seekers".to_string());
+        world.resource_mut::<ActiveDimension>().0 = 1;
+    }
+
+    #[test]
+    fn restore_reproduces_snapshot() {
+        let mut app = app();
+        let entities = populate(&mut app.world);
+        let snapshot = Snapshot::take(&mut app.world, "start");
+
+        scramble(&mut app.world, entities);
+        assert_ne!(Snapshot::take(&mut app.world, "start"), snapshot);
+
+        snapshot.restore(&mut app.world);
+        assert_eq!(Snapshot::take(&mut app.world, "start"), snapshot);
+        assert!(!app.world.entity(entities.1).contains::<Held>());
+    }
+
+    #[test]
+    fn restart_restores_last_checkpoint() {
+        let mut app = app();
+        let entities = populate(&mut app.world);
+        app.world.send_event(RoomChanged {
+            from: None,
+            to: Some("hall".to_string()),
+        });
+        app.update();
+
+        let checkpoints = app.world.resource::<Checkpoints>();
+        let snapshot = checkpoints
+            .last
+            .clone()
+            .expect("room entry takes a snapshot");
+        assert_eq!(snapshot.id, "room:hall");
+        assert_eq!(
+            app.world.resource::<RespawnPoint>().0,
+            Some(Transform::from_xyz(1.0, 0.0, 2.0))
+        );
+
+        scramble(&mut app.world, entities);
+        let clone = app
+            .world
+            .spawn((
+                CloneOf {
+                    source: "crate".to_string(),
+                    room: "hall".to_string(),
+                },
+                Transform::default(),
+                RigidBody::Dynamic,
+            ))
+            .id();
+        app.world.send_event(RestartFromCheckpoint);
+        app.update();
+
+        assert_eq!(Snapshot::take(&mut app.world, "room:hall"), snapshot);
+        assert!(app.world.get_entity(clone).is_none());
+    }
+}
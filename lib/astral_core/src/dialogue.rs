@@ -0,0 +1,730 @@
+//! Astraliminal's Dialogue plugin.
+//!
+//! Conversations are `DialogueScript`s, loaded from a `.dialogue.ron` file set as the level's
+//! `LevelDialogue`. A script is a set of nodes, e.g. `Z.a`; each speaks its lines in order, then
+//! offers its choices or goes on to its `next` node.
+//!
+//! Nodes, choices and branches can use the story: choices are only offered while their
+//! `condition` holds, a node goes on to the first of its `branches` whose condition holds before
+//! its `next`, and nodes and choices apply their `effects` when reached or chosen. Every node
+//! reached is remembered for `visited(...)` conditions.
+//!
+//! The runtime only talks through events: send `StartDialogue`, `AdvanceDialogue` and
+//! `ChooseDialogue`, and read `DialogueStarted`, `DialogueSpoken`, `DialogueChoices` and
+//! `DialogueEnded` to present it, as the dialogue UI does. Player input is blocked while a
+//! conversation runs.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    expression::{Condition, Effects},
+    player::InputBlocked,
+    ron_loader::RonLoader,
+    story::Story,
+};
+
+/// Reason in `InputBlocked` during a conversation.
+const BLOCK_REASON: &str = "dialogue";
+
+/// Dialogue nodes, loaded from a `.dialogue.ron` file.
+#[derive(Asset, TypePath, Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DialogueScript {
+    pub nodes: Vec<DialogueNode>,
+}
+
+impl DialogueScript {
+    /// The node with the given id.
+    pub fn node(&self, id: &str) -> Option<&DialogueNode> {
+        self.nodes.iter().find(|node| node.id == id)
+    }
+}
+
+/// A part of a conversation.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DialogueNode {
+    /// Unique id, used by other nodes and NPCs to refer to this one.
+    pub id: String,
+    pub lines: Vec<DialogueLine>,
+    /// Offered after the last line. The conversation waits for one to be chosen.
+    pub choices: Vec<DialogueChoice>,
+    /// Seconds to choose in. The `default_choice` is taken when they run out.
+    pub choice_seconds: Option<f32>,
+    /// Index of the choice taken when time runs out.
+    pub default_choice: usize,
+    /// Checked in order after the last line when no choices are offered. The conversation goes
+    /// on to the first one whose condition holds.
+    pub branches: Vec<DialogueBranch>,
+    /// Node to go on to after the last line when no choices are offered and no branch is taken.
+    /// The conversation ends without one.
+    pub next: Option<String>,
+    /// Applied when the node is reached.
+    pub effects: Option<Effects>,
+    /// Node the speaker's next conversation starts at, once this one has been reached.
+    pub next_time: Option<String>,
+}
+
+/// Something said.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DialogueLine {
+    pub speaker: String,
+    pub text: String,
+}
+
+/// An answer the player can give.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DialogueChoice {
+    pub text: String,
+    /// Node the answer leads to. The conversation ends without one.
+    #[serde(default)]
+    pub next: Option<String>,
+    /// Only offered while this holds.
+    #[serde(default)]
+    pub condition: Option<Condition>,
+    /// Applied when chosen.
+    #[serde(default)]
+    pub effects: Option<Effects>,
+}
+
+/// A node to go on to if a condition holds, e.g. `visited(Z.a) && !flag(met_seekers)`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DialogueBranch {
+    pub condition: Condition,
+    /// The conversation ends without one.
+    #[serde(default)]
+    pub next: Option<String>,
+}
+
+impl DialogueNode {
+    /// The node to go on to after the last line when no choices are offered.
+    fn follow(&self, story: &Story) -> Option<String> {
+        self.branches
+            .iter()
+            .find(|branch| story.check(&branch.condition))
+            .map_or(&self.next, |branch| &branch.next)
+            .clone()
+    }
+}
+
+/// The dialogue script of the current level.
+#[derive(Resource, Debug, Default, Clone)]
+pub struct LevelDialogue(pub Handle<DialogueScript>);
+
+/// The running conversation, if any.
+#[derive(Resource, Debug, Default, Clone)]
+pub struct Dialogue {
+    active: Option<Conversation>,
+}
+
+impl Dialogue {
+    /// Whether a conversation is running.
+    pub fn is_active(&self) -> bool {
+        self.active.is_some()
+    }
+
+    /// The running conversation.
+    pub fn conversation(&self) -> Option<&Conversation> {
+        self.active.as_ref()
+    }
+}
+
+/// Where a conversation is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conversation {
+    /// Id of the current node.
+    pub node: String,
+    /// Index of the line being shown. Past the last line while choices are offered.
+    pub line: usize,
+    /// Indices of the node's choices that were offered, in order. Empty until they are.
+    pub offered: Vec<usize>,
+    /// Who the player is talking to.
+    pub with: Option<Entity>,
+    /// Nodes reached so far, in order.
+    pub path: Vec<String>,
+}
+
+/// Send to start a conversation at `node`, unless one is running.
+#[derive(Event, Debug, Clone)]
+pub struct StartDialogue {
+    pub node: String,
+    pub with: Option<Entity>,
+}
+
+/// Send to go on to the next line.
+#[derive(Event, Debug, Default, Clone, Copy)]
+pub struct AdvanceDialogue;
+
+/// Send to pick one of the offered choices, by index.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct ChooseDialogue(pub usize);
+
+/// Sent when a conversation starts.
+#[derive(Event, Debug, Clone)]
+pub struct DialogueStarted {
+    pub node: String,
+    pub with: Option<Entity>,
+}
+
+/// Sent for every line to show.
+#[derive(Event, Debug, Clone, PartialEq)]
+pub struct DialogueSpoken {
+    pub node: String,
+    pub line: DialogueLine,
+}
+
+/// Sent when choices are offered, in order.
+#[derive(Event, Debug, Clone, PartialEq)]
+pub struct DialogueChoices {
+    pub node: String,
+    pub choices: Vec<String>,
+    /// Seconds to choose in, if limited.
+    pub seconds: Option<f32>,
+    /// Index of the choice to take when time runs out.
+    pub default: usize,
+}
+
+/// Sent when a conversation ends.
+#[derive(Event, Debug, Clone)]
+pub struct DialogueEnded {
+    pub with: Option<Entity>,
+    /// Nodes reached, in order.
+    pub path: Vec<String>,
+}
+
+pub struct AstraliminalDialoguePlugin;
+
+impl Plugin for AstraliminalDialoguePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<DialogueScript>()
+            .register_asset_loader(RonLoader::<DialogueScript>::new(&["dialogue.ron"]))
+            .init_resource::<Dialogue>()
+            .add_event::<StartDialogue>()
+            .add_event::<AdvanceDialogue>()
+            .add_event::<ChooseDialogue>()
+            .add_event::<DialogueStarted>()
+            .add_event::<DialogueSpoken>()
+            .add_event::<DialogueChoices>()
+            .add_event::<DialogueEnded>()
+            .add_systems(
+                Update,
+                // The interact key also advances dialogue: ending a conversation with it must not
+                // start a new one in the same frame.
+                run_dialogue
+                    .after(crate::interact::interact)
+                    .run_if(resource_exists::<LevelDialogue>),
+            );
+    }
+}
+
+/// Everything a conversation step changes besides the conversation.
+struct Outputs<'a, 's, 'c, 't> {
+    spoken: &'a mut EventWriter<'s, DialogueSpoken>,
+    offered: &'a mut EventWriter<'c, DialogueChoices>,
+    story: &'a mut Story<'t>,
+}
+
+/// Start, advance and end conversations.
+#[allow(clippy::too_many_arguments)]
+fn run_dialogue(
+    mut dialogue: ResMut<Dialogue>,
+    mut blocked: ResMut<InputBlocked>,
+    mut starts: EventReader<StartDialogue>,
+    mut advances: EventReader<AdvanceDialogue>,
+    mut choices: EventReader<ChooseDialogue>,
+    mut started: EventWriter<DialogueStarted>,
+    mut spoken: EventWriter<DialogueSpoken>,
+    mut offered: EventWriter<DialogueChoices>,
+    mut ended: EventWriter<DialogueEnded>,
+    level: Res<LevelDialogue>,
+    scripts: Res<Assets<DialogueScript>>,
+    mut story: Story,
+) {
+    let Some(script) = scripts.get(&level.0) else {
+        return;
+    };
+    let mut outputs = Outputs {
+        spoken: &mut spoken,
+        offered: &mut offered,
+        story: &mut story,
+    };
+
+    for start in starts.read() {
+        if dialogue.is_active() {
+            continue;
+        }
+        let mut conversation = Conversation {
+            node: String::new(),
+            line: 0,
+            offered: Vec::new(),
+            with: start.with,
+            path: Vec::new(),
+        };
+        if enter(&mut conversation, &start.node, script, &mut outputs) {
+            blocked.0.insert(BLOCK_REASON.to_string());
+            started.send(DialogueStarted {
+                node: start.node.clone(),
+                with: start.with,
+            });
+            dialogue.active = Some(conversation);
+        }
+    }
+
+    let chosen = choices.read().last().copied();
+    let advanced = advances.read().count() > 0;
+    let Some(conversation) = &mut dialogue.active else {
+        return;
+    };
+    let Some(node) = script.node(&conversation.node) else {
+        return;
+    };
+
+    let going_on = if !conversation.offered.is_empty() {
+        let choice = chosen
+            .and_then(|ChooseDialogue(index)| conversation.offered.get(index))
+            .and_then(|&index| node.choices.get(index));
+        choice.map(|choice| {
+            if let Some(effects) = &choice.effects {
+                outputs.story.apply(effects);
+            }
+            choice.next.clone()
+        })
+    } else if advanced {
+        conversation.line += 1;
+        if conversation.line < node.lines.len() {
+            outputs.spoken.send(DialogueSpoken {
+                node: node.id.clone(),
+                line: node.lines[conversation.line].clone(),
+            });
+            None
+        } else if offer(conversation, node, &mut outputs) {
+            None
+        } else {
+            Some(node.follow(outputs.story))
+        }
+    } else {
+        None
+    };
+
+    let Some(next) = going_on else {
+        return;
+    };
+    let keep_going = next.is_some_and(|next| enter(conversation, &next, script, &mut outputs));
+    if !keep_going {
+        blocked.0.remove(BLOCK_REASON);
+        ended.send(DialogueEnded {
+            with: conversation.with,
+            path: std::mem::take(&mut conversation.path),
+        });
+        dialogue.active = None;
+    }
+}
+
+/// Go to a node and show its first line, or its choices if it has no lines. Nodes without either
+/// are passed through. Returns false if the conversation ends instead.
+fn enter(
+    conversation: &mut Conversation,
+    id: &str,
+    script: &DialogueScript,
+    outputs: &mut Outputs,
+) -> bool {
+    let mut id = id.to_string();
+    // Bounded, so nodes that only point at each other can't hang the game.
+    for _ in 0..=script.nodes.len() {
+        let Some(node) = script.node(&id) else {
+            warn!("Dialogue node {} does not exist", id);
+            return false;
+        };
+        conversation.node = id.clone();
+        conversation.line = 0;
+        conversation.offered.clear();
+        conversation.path.push(id.clone());
+        outputs.story.blackboard.visited.insert(id.clone());
+        if let Some(effects) = &node.effects {
+            outputs.story.apply(effects);
+        }
+
+        if let Some(line) = node.lines.first() {
+            outputs.spoken.send(DialogueSpoken {
+                node: id,
+                line: line.clone(),
+            });
+            return true;
+        }
+        if offer(conversation, node, outputs) {
+            return true;
+        }
+        match node.follow(outputs.story) {
+            Some(next) => id = next,
+            None => return false,
+        }
+    }
+    warn!("Dialogue nodes loop without lines at {}", id);
+    false
+}
+
+/// Offer the node's choices whose condition holds. Returns false if there are none.
+fn offer(conversation: &mut Conversation, node: &DialogueNode, outputs: &mut Outputs) -> bool {
+    conversation.line = node.lines.len();
+    conversation.offered = (0..node.choices.len())
+        .filter(|&index| outputs.story.allows(node.choices[index].condition.as_ref()))
+        .collect();
+    if conversation.offered.is_empty() {
+        return false;
+    }
+
+    outputs.offered.send(DialogueChoices {
+        node: node.id.clone(),
+        choices: conversation
+            .offered
+            .iter()
+            .map(|&index| node.choices[index].text.clone())
+            .collect(),
+        seconds: node.choice_seconds,
+        default: conversation
+            .offered
+            .iter()
+            .position(|&index| index == node.default_choice)
+            .unwrap_or_default(),
+    });
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::time::TimeUpdateStrategy;
+    use bevy_xpbd_3d::prelude::*;
+
+    use super::*;
+    use crate::{
+        interact::{AstraliminalInteractPlugin, InteractionFocus},
+        npc::{AstraliminalNpcPlugin, Npc, NpcMemories},
+        save::AstraliminalSavePlugin,
+        story::{AstraliminalStoryPlugin, Blackboard, StoryFlags},
+    };
+
+    /// What the runtime said, in order.
+    #[derive(Resource, Debug, Default)]
+    struct Log {
+        started: Vec<String>,
+        spoken: Vec<String>,
+        choices: Vec<(Vec<String>, usize)>,
+        ended: Vec<Vec<String>>,
+    }
+
+    fn log(
+        mut log: ResMut<Log>,
+        mut started: EventReader<DialogueStarted>,
+        mut spoken: EventReader<DialogueSpoken>,
+        mut choices: EventReader<DialogueChoices>,
+        mut ended: EventReader<DialogueEnded>,
+    ) {
+        log.started
+            .extend(started.read().map(|started| started.node.clone()));
+        log.spoken
+            .extend(spoken.read().map(|spoken| spoken.line.text.clone()));
+        log.choices.extend(
+            choices
+                .read()
+                .map(|offered| (offered.choices.clone(), offered.default)),
+        );
+        log.ended
+            .extend(ended.read().map(|ended| ended.path.clone()));
+    }
+
+    fn node(id: &str, lines: &[&str]) -> DialogueNode {
+        DialogueNode {
+            id: id.to_string(),
+            lines: lines
+                .iter()
+                .map(|text| DialogueLine {
+                    speaker: "Rower".to_string(),
+                    text: text.to_string(),
+                })
+                .collect(),
+            ..default()
+        }
+    }
+
+    fn choice(text: &str, next: Option<&str>) -> DialogueChoice {
+        DialogueChoice {
+            text: text.to_string(),
+            next: next.map(String::from),
+            ..default()
+        }
+    }
+
+    fn script() -> DialogueScript {
+        DialogueScript {
+            nodes: vec![
+                DialogueNode {
+                    next: Some("rower.b".to_string()),
+                    ..node("rower.a", &["Row, row.", "Almost there."])
+                },
+                // No lines: passed through on the way to `rower.ask`.
+                DialogueNode {
+                    branches: vec![DialogueBranch {
+                        condition: "flag(knows_secret)".parse().unwrap(),
+                        next: Some("rower.secret".to_string()),
+                    }],
+                    next: Some("rower.ask".to_string()),
+                    effects: Some("set(met_rower)".parse().unwrap()),
+                    next_time: Some("rower.again".to_string()),
+                    ..node("rower.b", &[])
+                },
+                DialogueNode {
+                    choices: vec![
+                        choice("Where to?", Some("rower.island")),
+                        DialogueChoice {
+                            condition: Some("flag(knows_secret)".parse().unwrap()),
+                            ..choice("The secret", Some("rower.secret"))
+                        },
+                        DialogueChoice {
+                            effects: Some("set(said_bye)".parse().unwrap()),
+                            ..choice("Bye", None)
+                        },
+                    ],
+                    default_choice: 2,
+                    ..node("rower.ask", &["Anything else?"])
+                },
+                node("rower.island", &["The island."]),
+                node("rower.secret", &["Shh."]),
+                node("rower.again", &["We're close to our destination."]),
+                DialogueNode {
+                    next: Some("loop.b".to_string()),
+                    ..node("loop.a", &[])
+                },
+                DialogueNode {
+                    next: Some("loop.a".to_string()),
+                    ..node("loop.b", &[])
+                },
+            ],
+        }
+    }
+
+    /// A headless app stepping 0.1 seconds a frame, with `script()` as the level's dialogue.
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            AssetPlugin::default(),
+            HierarchyPlugin,
+            TransformPlugin,
+            PhysicsPlugins::default(),
+        ))
+        .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_millis(
+            100,
+        )))
+        .init_resource::<InputBlocked>()
+        .init_resource::<ButtonInput<KeyCode>>()
+        .init_resource::<Log>()
+        .add_plugins((
+            AstraliminalSavePlugin,
+            AstraliminalStoryPlugin,
+            AstraliminalInteractPlugin,
+            AstraliminalNpcPlugin,
+            AstraliminalDialoguePlugin,
+        ))
+        .add_systems(PostUpdate, log);
+        let script = app
+            .world
+            .resource_mut::<Assets<DialogueScript>>()
+            .add(script());
+        app.insert_resource(LevelDialogue(script));
+        app.update();
+        app
+    }
+
+    fn start(app: &mut App, node: &str) {
+        app.world.send_event(StartDialogue {
+            node: node.to_string(),
+            with: None,
+        });
+        app.update();
+    }
+
+    fn advance(app: &mut App) {
+        app.world.send_event(AdvanceDialogue);
+        app.update();
+    }
+
+    fn choose(app: &mut App, index: usize) {
+        app.world.send_event(ChooseDialogue(index));
+        app.update();
+    }
+
+    fn log_of(app: &App) -> &Log {
+        app.world.resource::<Log>()
+    }
+
+    fn flag(app: &App, flag: &str) -> bool {
+        app.world.resource::<StoryFlags>().is_set(flag)
+    }
+
+    fn blocked(app: &App) -> bool {
+        app.world.resource::<InputBlocked>().is_blocked()
+    }
+
+    #[test]
+    fn lines_pass_through_empty_nodes_to_choices() {
+        let mut app = app();
+        start(&mut app, "rower.a");
+        assert_eq!(log_of(&app).started, ["rower.a"]);
+        assert_eq!(log_of(&app).spoken, ["Row, row."]);
+        assert!(blocked(&app));
+
+        advance(&mut app);
+        assert_eq!(log_of(&app).spoken.last().unwrap(), "Almost there.");
+        assert!(!flag(&app, "met_rower"));
+
+        // `rower.b` has no lines: its effects apply and it goes on to its `next`.
+        advance(&mut app);
+        assert!(flag(&app, "met_rower"));
+        assert!(app
+            .world
+            .resource::<Blackboard>()
+            .visited
+            .contains("rower.b"));
+        assert_eq!(log_of(&app).spoken.last().unwrap(), "Anything else?");
+        assert!(log_of(&app).choices.is_empty());
+
+        // Only choices whose condition holds are offered, and the default follows them.
+        advance(&mut app);
+        let offered = (vec!["Where to?".to_string(), "Bye".to_string()], 1);
+        assert_eq!(log_of(&app).choices, [offered]);
+        let conversation = app.world.resource::<Dialogue>().conversation().unwrap();
+        assert_eq!(conversation.offered, [0, 2]);
+
+        // Advancing doesn't pick a choice.
+        advance(&mut app);
+        assert!(app.world.resource::<Dialogue>().is_active());
+
+        choose(&mut app, 1);
+        assert!(flag(&app, "said_bye"));
+        assert!(!app.world.resource::<Dialogue>().is_active());
+        assert!(!blocked(&app));
+        assert_eq!(
+            log_of(&app).ended,
+            [vec!["rower.a", "rower.b", "rower.ask"]]
+        );
+    }
+
+    #[test]
+    fn choices_lead_to_their_next_node() {
+        let mut app = app();
+        start(&mut app, "rower.ask");
+        advance(&mut app);
+        choose(&mut app, 0);
+        assert_eq!(log_of(&app).spoken.last().unwrap(), "The island.");
+
+        advance(&mut app);
+        assert!(!app.world.resource::<Dialogue>().is_active());
+        assert_eq!(log_of(&app).ended, [vec!["rower.ask", "rower.island"]]);
+        assert!(!flag(&app, "said_bye"));
+    }
+
+    #[test]
+    fn branches_come_before_next() {
+        let mut app = app();
+        app.world
+            .resource_mut::<StoryFlags>()
+            .0
+            .insert("knows_secret".to_string());
+        start(&mut app, "rower.b");
+        assert_eq!(log_of(&app).spoken, ["Shh."]);
+
+        advance(&mut app);
+        assert_eq!(log_of(&app).ended, [vec!["rower.b", "rower.secret"]]);
+    }
+
+    #[test]
+    fn nodes_looping_without_lines_never_start() {
+        let mut app = app();
+        start(&mut app, "loop.a");
+        assert!(log_of(&app).started.is_empty());
+        assert!(!app.world.resource::<Dialogue>().is_active());
+        assert!(!blocked(&app));
+
+        start(&mut app, "missing");
+        assert!(log_of(&app).started.is_empty());
+    }
+
+    #[test]
+    fn conversations_only_start_when_none_is_running() {
+        let mut app = app();
+        start(&mut app, "rower.island");
+        start(&mut app, "rower.secret");
+        assert_eq!(log_of(&app).started, ["rower.island"]);
+        assert_eq!(log_of(&app).spoken, ["The island."]);
+    }
+
+    /// Press the interact key for one frame, advancing the dialogue like its UI does.
+    fn press_interact(app: &mut App) {
+        app.world
+            .resource_mut::<ButtonInput<KeyCode>>()
+            .press(KeyCode::KeyE);
+        if app.world.resource::<Dialogue>().is_active() {
+            app.world.send_event(AdvanceDialogue);
+        }
+        app.update();
+        let mut keys = app.world.resource_mut::<ButtonInput<KeyCode>>();
+        keys.release(KeyCode::KeyE);
+        keys.clear();
+        // Let the interaction reach the NPC and the runtime.
+        app.update();
+        app.update();
+    }
+
+    #[test]
+    fn npcs_start_again_where_the_script_says() {
+        let mut app = app();
+        let rower = app
+            .world
+            .spawn((
+                Npc {
+                    id: "rower".to_string(),
+                    dialogue: "rower.a".to_string(),
+                    ..default()
+                },
+                TransformBundle::default(),
+            ))
+            .id();
+        app.update();
+        app.insert_resource(InteractionFocus(Some(rower)));
+
+        press_interact(&mut app);
+        assert_eq!(log_of(&app).started, ["rower.a"]);
+        assert_eq!(
+            app.world
+                .resource::<Dialogue>()
+                .conversation()
+                .unwrap()
+                .with,
+            Some(rower)
+        );
+        press_interact(&mut app);
+        press_interact(&mut app);
+        advance(&mut app);
+        choose(&mut app, 1);
+        app.update();
+        assert!(!app.world.resource::<Dialogue>().is_active());
+        let memories = app.world.resource::<NpcMemories>();
+        assert!(memories.talked_to("rower"));
+        assert_eq!(memories.0["rower"].next.as_deref(), Some("rower.again"));
+
+        press_interact(&mut app);
+        assert_eq!(log_of(&app).started, ["rower.a", "rower.again"]);
+        assert_eq!(
+            log_of(&app).spoken.last().unwrap(),
+            "We're close to our destination."
+        );
+
+        // The key that ends the conversation doesn't start it over.
+        press_interact(&mut app);
+        assert!(!app.world.resource::<Dialogue>().is_active());
+        assert_eq!(log_of(&app).started.len(), 2);
+        assert_eq!(app.world.resource::<NpcMemories>().0["rower"].talks, 2);
+    }
+}
@@ -0,0 +1,629 @@
+//! Astraliminal's Dialogue UI plugin.
+//!
+//! Shows conversations in a box at the bottom of the screen: the speaker's name, the line typed
+//! out a few characters at a time and the choices, which can be picked with the mouse, keyboard
+//! or a gamepad. Confirming while a line is still being typed shows all of it. Choices with a time
+//! limit show a shrinking bar and take the default choice when it runs out. Lines spoken by
+//! timelines are shown the same way for as long as the timeline says. The history key opens a log
+//! of everything said and chosen.
+//!
+//! All of it is kept in `DialogueView`, which only changes through the runtime's events, input
+//! and time, so it can be checked without rendering anything.
+
+use bevy::prelude::*;
+
+use crate::{
+    dialogue::{
+        AdvanceDialogue, ChooseDialogue, Dialogue, DialogueChoices, DialogueEnded, DialogueLine,
+        DialogueSpoken, DialogueStarted,
+    },
+    timeline::SpokenLine,
+};
+
+/// Characters typed out per second.
+const TYPEWRITER_SPEED: f32 = 40.0;
+/// Entries kept in the history.
+const HISTORY_LENGTH: usize = 200;
+/// Entries shown in the history panel.
+const HISTORY_SHOWN: usize = 30;
+
+const PANEL_COLOR: Color = Color::rgba(0.0, 0.0, 0.0, 0.75);
+const SELECTED_COLOR: Color = Color::rgba(1.0, 1.0, 1.0, 0.2);
+const SPEAKER_COLOR: Color = Color::rgb(1.0, 0.85, 0.5);
+
+/// Something in the dialogue history.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HistoryEntry {
+    Line { speaker: String, text: String },
+    Choice(String),
+}
+
+/// What the dialogue UI shows.
+#[derive(Resource, Debug, Default, Clone, PartialEq)]
+pub struct DialogueView {
+    /// Whether the dialogue box is shown.
+    pub visible: bool,
+    pub speaker: String,
+    pub text: String,
+    /// Characters of `text` typed out so far.
+    pub revealed: f32,
+    /// Choices offered, once the line is typed out.
+    pub choices: Vec<String>,
+    /// Index of the highlighted choice.
+    pub selected: usize,
+    /// Seconds left to choose in, and how many there were, if limited.
+    pub choice_time: Option<(f32, f32)>,
+    /// Choice taken when time runs out.
+    pub default_choice: usize,
+    /// Seconds until a line spoken by a timeline is hidden again.
+    pub subtitle_left: Option<f32>,
+    /// Everything said and chosen, oldest first.
+    pub history: Vec<HistoryEntry>,
+    /// Whether the history panel is open.
+    pub history_open: bool,
+}
+
+/// What the player asked the runtime for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DialogueReply {
+    Advance,
+    Choose(usize),
+}
+
+impl DialogueView {
+    /// Start typing out a line.
+    pub fn show_line(&mut self, line: &DialogueLine) {
+        self.visible = true;
+        self.speaker = line.speaker.clone();
+        self.text = line.text.clone();
+        self.revealed = 0.0;
+        self.choices.clear();
+        self.choice_time = None;
+        self.record(HistoryEntry::Line {
+            speaker: line.speaker.clone(),
+            text: line.text.clone(),
+        });
+    }
+
+    /// Offer choices below the current line.
+    pub fn show_choices(&mut self, choices: &DialogueChoices) {
+        self.visible = true;
+        self.choices = choices.choices.clone();
+        self.default_choice = choices.default.min(self.choices.len().saturating_sub(1));
+        self.selected = self.default_choice;
+        self.choice_time = choices.seconds.map(|seconds| (seconds, seconds));
+    }
+
+    /// Hide the box.
+    pub fn hide(&mut self) {
+        self.visible = false;
+        self.choices.clear();
+        self.choice_time = None;
+        self.subtitle_left = None;
+    }
+
+    /// Whether the whole line is shown.
+    pub fn is_revealed(&self) -> bool {
+        self.revealed as usize >= self.text.chars().count()
+    }
+
+    /// The part of the line typed out so far.
+    pub fn visible_text(&self) -> &str {
+        let end = self
+            .text
+            .char_indices()
+            .nth(self.revealed as usize)
+            .map_or(self.text.len(), |(index, _)| index);
+        &self.text[..end]
+    }
+
+    /// Whether the choices can be picked: there are some and the line is typed out.
+    pub fn is_choosing(&self) -> bool {
+        !self.choices.is_empty() && self.is_revealed()
+    }
+
+    /// Move the highlight by `offset` choices, wrapping around.
+    pub fn select(&mut self, offset: isize) {
+        if self.choices.is_empty() {
+            return;
+        }
+        let count = self.choices.len() as isize;
+        self.selected = (self.selected as isize + offset).rem_euclid(count) as usize;
+    }
+
+    /// Confirm: show the rest of the line if it is still being typed, else take the highlighted
+    /// choice or go on.
+    pub fn confirm(&mut self) -> Option<DialogueReply> {
+        if !self.is_revealed() {
+            self.revealed = self.text.chars().count() as f32;
+            return None;
+        }
+        if self.choices.is_empty() {
+            return Some(DialogueReply::Advance);
+        }
+        self.choose(self.selected)
+    }
+
+    /// Take a choice by index.
+    pub fn choose(&mut self, index: usize) -> Option<DialogueReply> {
+        if !self.is_choosing() {
+            return None;
+        }
+        let choice = self.choices.get(index)?.clone();
+        self.record(HistoryEntry::Choice(choice));
+        self.choices.clear();
+        self.choice_time = None;
+        Some(DialogueReply::Choose(index))
+    }
+
+    /// Type out the line, count down the choice and subtitle timers, and take the default choice
+    /// when time runs out.
+    pub fn tick(&mut self, delta: f32) -> Option<DialogueReply> {
+        if !self.is_revealed() {
+            self.revealed += TYPEWRITER_SPEED * delta;
+        }
+        if let Some(left) = &mut self.subtitle_left {
+            *left -= delta;
+            if *left <= 0.0 {
+                self.hide();
+            }
+        }
+
+        if !self.is_revealed() {
+            return None;
+        }
+        let (left, _) = self.choice_time.as_mut()?;
+        *left -= delta;
+        if *left > 0.0 {
+            return None;
+        }
+        self.choose(self.default_choice)
+    }
+
+    fn record(&mut self, entry: HistoryEntry) {
+        self.history.push(entry);
+        if self.history.len() > HISTORY_LENGTH {
+            self.history.remove(0);
+        }
+    }
+}
+
+/// Parts of the dialogue UI, kept in sync with the `DialogueView`.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+enum DialogueUiPart {
+    Box,
+    Speaker,
+    Body,
+    Choices,
+    Timer,
+    History,
+    HistoryText,
+}
+
+/// A button for the choice with this index.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+struct ChoiceButton(usize);
+
+pub struct AstraliminalDialogueUiPlugin;
+
+impl Plugin for AstraliminalDialogueUiPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DialogueView>()
+            .add_systems(Startup, spawn_dialogue_ui)
+            .add_systems(
+                Update,
+                (
+                    follow_runtime,
+                    dialogue_input,
+                    tick_view,
+                    update_dialogue_ui,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Show what the dialogue runtime and timelines send.
+fn follow_runtime(
+    mut view: ResMut<DialogueView>,
+    mut started: EventReader<DialogueStarted>,
+    mut spoken: EventReader<DialogueSpoken>,
+    mut offered: EventReader<DialogueChoices>,
+    mut ended: EventReader<DialogueEnded>,
+    mut subtitles: EventReader<SpokenLine>,
+    dialogue: Option<Res<Dialogue>>,
+) {
+    for _ in started.read() {
+        view.visible = true;
+        view.subtitle_left = None;
+    }
+    for event in spoken.read() {
+        view.show_line(&event.line);
+    }
+    for event in offered.read() {
+        view.show_choices(event);
+    }
+    for _ in ended.read() {
+        view.hide();
+    }
+
+    let talking = dialogue.is_some_and(|dialogue| dialogue.is_active());
+    for line in subtitles.read() {
+        if talking {
+            continue;
+        }
+        view.show_line(&DialogueLine {
+            speaker: line.speaker.clone(),
+            text: line.text.clone(),
+        });
+        view.subtitle_left = Some(line.seconds);
+    }
+}
+
+/// Advance, choose and open the history with the keyboard, mouse or a gamepad.
+#[allow(clippy::too_many_arguments)]
+fn dialogue_input(
+    mut view: ResMut<DialogueView>,
+    mut advances: EventWriter<AdvanceDialogue>,
+    mut choices: EventWriter<ChooseDialogue>,
+    keys: Res<ButtonInput<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<ButtonInput<GamepadButton>>,
+    buttons: Query<(&Interaction, &ChoiceButton), Changed<Interaction>>,
+    dialogue: Option<Res<Dialogue>>,
+) {
+    let pad = |kind: GamepadButtonType| {
+        gamepads
+            .iter()
+            .any(|gamepad| gamepad_buttons.just_pressed(GamepadButton::new(gamepad, kind)))
+    };
+
+    // TODO: pull key codes from config.
+    if (view.visible || view.history_open)
+        && (keys.just_pressed(KeyCode::KeyH) || pad(GamepadButtonType::North))
+    {
+        view.history_open = !view.history_open;
+    }
+    if !dialogue.is_some_and(|dialogue| dialogue.is_active()) {
+        return;
+    }
+
+    let mut reply = None;
+    if keys.any_just_pressed([KeyCode::ArrowUp, KeyCode::KeyW]) || pad(GamepadButtonType::DPadUp) {
+        view.select(-1);
+    }
+    if keys.any_just_pressed([KeyCode::ArrowDown, KeyCode::KeyS])
+        || pad(GamepadButtonType::DPadDown)
+    {
+        view.select(1);
+    }
+    let digits = [
+        KeyCode::Digit1,
+        KeyCode::Digit2,
+        KeyCode::Digit3,
+        KeyCode::Digit4,
+        KeyCode::Digit5,
+        KeyCode::Digit6,
+        KeyCode::Digit7,
+        KeyCode::Digit8,
+        KeyCode::Digit9,
+    ];
+    for (index, digit) in digits.into_iter().enumerate() {
+        if keys.just_pressed(digit) {
+            reply = reply.or(view.choose(index));
+        }
+    }
+    for (interaction, &ChoiceButton(index)) in &buttons {
+        match interaction {
+            Interaction::Hovered => view.selected = index,
+            Interaction::Pressed => reply = reply.or(view.choose(index)),
+            Interaction::None => {}
+        }
+    }
+    if keys.any_just_pressed([KeyCode::KeyE, KeyCode::Enter, KeyCode::Space])
+        || pad(GamepadButtonType::South)
+    {
+        reply = reply.or_else(|| view.confirm());
+    }
+
+    match reply {
+        Some(DialogueReply::Advance) => {
+            advances.send(AdvanceDialogue);
+        }
+        Some(DialogueReply::Choose(index)) => {
+            choices.send(ChooseDialogue(index));
+        }
+        None => {}
+    }
+}
+
+/// Type out lines and run out timers.
+fn tick_view(
+    mut view: ResMut<DialogueView>,
+    mut choices: EventWriter<ChooseDialogue>,
+    time: Res<Time>,
+) {
+    if !view.visible {
+        return;
+    }
+    if let Some(DialogueReply::Choose(index)) = view.tick(time.delta_seconds()) {
+        choices.send(ChooseDialogue(index));
+    }
+}
+
+/// Spawn the dialogue box and history panel, hidden.
+fn spawn_dialogue_ui(mut commands: Commands) {
+    let text = |part: DialogueUiPart, size: f32, color: Color| {
+        (
+            part,
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font_size: size,
+                    color,
+                    ..default()
+                },
+            ),
+        )
+    };
+
+    commands
+        .spawn((
+            DialogueUiPart::Box,
+            NodeBundle {
+                style: Style {
+                    display: Display::None,
+                    position_type: PositionType::Absolute,
+                    bottom: Val::Percent(4.0),
+                    left: Val::Percent(15.0),
+                    width: Val::Percent(70.0),
+                    flex_direction: FlexDirection::Column,
+                    row_gap: Val::Px(8.0),
+                    padding: UiRect::all(Val::Px(16.0)),
+                    ..default()
+                },
+                background_color: PANEL_COLOR.into(),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn(text(DialogueUiPart::Speaker, 22.0, SPEAKER_COLOR));
+            parent.spawn(text(DialogueUiPart::Body, 26.0, Color::WHITE));
+            parent.spawn((
+                DialogueUiPart::Choices,
+                NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        row_gap: Val::Px(4.0),
+                        ..default()
+                    },
+                    ..default()
+                },
+            ));
+            parent.spawn((
+                DialogueUiPart::Timer,
+                NodeBundle {
+                    style: Style {
+                        display: Display::None,
+                        width: Val::Percent(100.0),
+                        height: Val::Px(4.0),
+                        ..default()
+                    },
+                    background_color: SPEAKER_COLOR.into(),
+                    ..default()
+                },
+            ));
+        });
+
+    commands
+        .spawn((
+            DialogueUiPart::History,
+            NodeBundle {
+                style: Style {
+                    display: Display::None,
+                    position_type: PositionType::Absolute,
+                    top: Val::Percent(5.0),
+                    left: Val::Percent(15.0),
+                    width: Val::Percent(70.0),
+                    height: Val::Percent(65.0),
+                    flex_direction: FlexDirection::Column,
+                    justify_content: JustifyContent::FlexEnd,
+                    overflow: Overflow::clip(),
+                    padding: UiRect::all(Val::Px(16.0)),
+                    ..default()
+                },
+                background_color: PANEL_COLOR.into(),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn(text(DialogueUiPart::HistoryText, 20.0, Color::WHITE));
+        });
+}
+
+/// Show the `DialogueView` on the UI.
+fn update_dialogue_ui(
+    mut commands: Commands,
+    view: Res<DialogueView>,
+    mut parts: Query<(Entity, &DialogueUiPart, &mut Style, Option<&mut Text>)>,
+    mut choice_buttons: Query<(Entity, &ChoiceButton, &mut BackgroundColor)>,
+    mut shown_choices: Local<Vec<String>>,
+) {
+    if !view.is_changed() {
+        return;
+    }
+
+    let display = |shown: bool| if shown { Display::Flex } else { Display::None };
+    let choosing = view.is_choosing();
+    for (entity, part, mut style, text) in &mut parts {
+        match part {
+            DialogueUiPart::Box => style.display = display(view.visible),
+            DialogueUiPart::History => style.display = display(view.history_open),
+            DialogueUiPart::Timer => match view.choice_time.filter(|_| choosing) {
+                Some((left, total)) => {
+                    style.display = Display::Flex;
+                    style.width = Val::Percent(100.0 * left.max(0.0) / total.max(f32::EPSILON));
+                }
+                None => style.display = Display::None,
+            },
+            DialogueUiPart::Choices => {
+                let wanted = if choosing {
+                    view.choices.clone()
+                } else {
+                    Vec::new()
+                };
+                if *shown_choices != wanted {
+                    for (button, ..) in &choice_buttons {
+                        commands.entity(button).despawn_recursive();
+                    }
+                    commands.entity(entity).with_children(|parent| {
+                        for (index, choice) in wanted.iter().enumerate() {
+                            spawn_choice(parent, index, choice);
+                        }
+                    });
+                    *shown_choices = wanted;
+                }
+            }
+            DialogueUiPart::Speaker | DialogueUiPart::Body | DialogueUiPart::HistoryText => {
+                let Some(mut text) = text else {
+                    continue;
+                };
+                let value = match part {
+                    DialogueUiPart::Speaker => view.speaker.clone(),
+                    DialogueUiPart::Body => view.visible_text().to_string(),
+                    _ => history_text(&view.history),
+                };
+                if text.sections[0].value != value {
+                    text.sections[0].value = value;
+                }
+            }
+        }
+    }
+
+    for (_, &ChoiceButton(index), mut color) in &mut choice_buttons {
+        color.0 = if index == view.selected {
+            SELECTED_COLOR
+        } else {
+            Color::NONE
+        };
+    }
+}
+
+/// Spawn the button for a choice.
+fn spawn_choice(parent: &mut ChildBuilder, index: usize, choice: &str) {
+    parent
+        .spawn((
+            ChoiceButton(index),
+            ButtonBundle {
+                style: Style {
+                    padding: UiRect::axes(Val::Px(8.0), Val::Px(4.0)),
+                    ..default()
+                },
+                background_color: Color::NONE.into(),
+                ..default()
+            },
+        ))
+        .with_children(|button| {
+            button.spawn(TextBundle::from_section(
+                format!("{}. {}", index + 1, choice),
+                TextStyle {
+                    font_size: 24.0,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            ));
+        });
+}
+
+/// The last entries of the history as text.
+fn history_text(history: &[HistoryEntry]) -> String {
+    history[history.len().saturating_sub(HISTORY_SHOWN)..]
+        .iter()
+        .map(|entry| match entry {
+            HistoryEntry::Line { speaker, text } if speaker.is_empty() => text.clone(),
+            HistoryEntry::Line { speaker, text } => format!("{}: {}", speaker, text),
+            HistoryEntry::Choice(choice) => format!("> {}", choice),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(text: &str) -> DialogueLine {
+        DialogueLine {
+            speaker: "Rower".to_string(),
+            text: text.to_string(),
+        }
+    }
+
+    fn choices(seconds: Option<f32>, default: usize) -> DialogueChoices {
+        DialogueChoices {
+            node: "ask".to_string(),
+            choices: vec!["Yes".to_string(), "No".to_string(), "Maybe".to_string()],
+            seconds,
+            default,
+        }
+    }
+
+    #[test]
+    fn confirming_while_typing_shows_the_line() {
+        let mut view = DialogueView::default();
+        view.show_line(&line("We're close to our destination."));
+        assert_eq!(view.tick(0.1), None);
+        assert_eq!(view.visible_text(), "We'r");
+
+        assert_eq!(view.confirm(), None);
+        assert!(view.is_revealed());
+        assert_eq!(view.visible_text(), "We're close to our destination.");
+        assert_eq!(view.confirm(), Some(DialogueReply::Advance));
+    }
+
+    #[test]
+    fn choices_wait_for_the_line() {
+        let mut view = DialogueView::default();
+        view.show_line(&line("Ready?"));
+        view.show_choices(&choices(None, 0));
+        assert!(!view.is_choosing());
+        assert_eq!(view.choose(1), None);
+
+        view.confirm();
+        view.select(-1);
+        assert_eq!(view.selected, 2);
+        view.select(2);
+        assert_eq!(view.selected, 1);
+        assert_eq!(view.confirm(), Some(DialogueReply::Choose(1)));
+        assert!(view.choices.is_empty());
+        assert_eq!(
+            view.history.last(),
+            Some(&HistoryEntry::Choice("No".to_string()))
+        );
+    }
+
+    #[test]
+    fn timed_choices_take_the_default() {
+        let mut view = DialogueView::default();
+        view.show_line(&line("Quick!"));
+        view.show_choices(&choices(Some(1.0), 2));
+        assert_eq!(view.selected, 2);
+
+        // The timer only runs once the line is typed out.
+        assert_eq!(view.tick(0.1), None);
+        assert_eq!(view.choice_time, Some((1.0, 1.0)));
+        view.select(-1);
+        assert_eq!(view.tick(0.5), None);
+        assert_eq!(view.tick(0.6), Some(DialogueReply::Choose(2)));
+        assert_eq!(view.choice_time, None);
+        assert_eq!(view.tick(1.0), None);
+    }
+
+    #[test]
+    fn out_of_range_defaults_take_the_last_choice() {
+        let mut view = DialogueView::default();
+        view.show_line(&line(""));
+        view.show_choices(&choices(Some(0.5), 7));
+        assert_eq!(view.tick(1.0), Some(DialogueReply::Choose(2)));
+    }
+}
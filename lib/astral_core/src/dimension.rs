@@ -0,0 +1,303 @@
+//! Astraliminal's Dimension plugin.
+//!
+//! A level can hold several versions of the same space, e.g. the island and the Zeta Dimension.
+//! Entities tagged with a `DimensionLayer` only exist in that layer: outside of it they are
+//! hidden and don't collide. Untagged entities exist in every layer.
+//!
+//! Send `ShiftDimension` to move the player to another layer. Other systems (audio, lighting,
+//! achievements) should react to `DimensionShifted`.
+
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+
+use crate::{
+    player::{DropHeld, Grab, Held, Player},
+    save::{SaveData, SaveSet},
+    schedule::AstralSet,
+    trigger::TriggerEntered,
+};
+
+/// The layer an entity lives in.
+#[derive(Component, Reflect, Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[reflect(Component)]
+pub struct DimensionLayer(pub u8);
+
+/// The layer the player is in.
+#[derive(Resource, Reflect, Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[reflect(Resource)]
+pub struct ActiveDimension(pub u8);
+
+/// An object that moves the player to another layer when grabbed, like the Spiritual Cube. On a
+/// `TriggerVolume`, it moves the player when they walk into the trigger.
+#[derive(Component, Reflect, Debug, Default, Clone, Copy)]
+#[reflect(Component)]
+pub struct DimensionArtifact {
+    /// The layer to go to.
+    pub to: u8,
+    /// Whether held objects come along.
+    pub carry_held: bool,
+}
+
+/// Send to move the player to another layer.
+#[derive(Event, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ShiftDimension {
+    /// The layer to go to.
+    pub to: u8,
+    /// Whether held objects come along. Otherwise they are dropped and stay behind.
+    pub carry_held: bool,
+}
+
+/// Sent after the player moved to another layer.
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DimensionShifted {
+    pub from: u8,
+    pub to: u8,
+}
+
+/// The collision layers an entity had before its dimension layer was applied.
+#[derive(Component, Debug, Clone, Copy)]
+struct BaseCollisionLayers(CollisionLayers);
+
+/// Marker for entities hidden because they are outside the active layer, so entities hidden for
+/// other reasons stay hidden.
+#[derive(Component, Debug, Default, Clone, Copy)]
+struct HiddenByLayer;
+
+pub struct AstraliminalDimensionPlugin;
+
+impl Plugin for AstraliminalDimensionPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<DimensionLayer>()
+            .register_type::<ActiveDimension>()
+            .register_type::<DimensionArtifact>()
+            .init_resource::<ActiveDimension>()
+            .add_event::<ShiftDimension>()
+            .add_event::<DimensionShifted>()
+            .add_systems(PreUpdate, restore_dimension.in_set(SaveSet::Restore))
+            .add_systems(
+                FixedUpdate,
+                (use_artifacts, shift_dimension, apply_layers)
+                    .chain()
+                    .in_set(AstralSet::React),
+            )
+            .add_systems(Last, collect_dimension.in_set(SaveSet::Collect));
+    }
+}
+
+/// Start in the layer the player saved in.
+fn restore_dimension(mut active: ResMut<ActiveDimension>, save: Res<SaveData>) {
+    active.0 = save.dimension;
+}
+
+/// Shift layers when an artifact is grabbed or the player enters an artifact trigger.
+fn use_artifacts(
+    mut grabs: EventReader<Grab>,
+    mut entered: EventReader<TriggerEntered>,
+    mut shifts: EventWriter<ShiftDimension>,
+    artifacts: Query<&DimensionArtifact>,
+    player: Query<(), With<Player>>,
+) {
+    let grabbed = grabs.read().map(|grab| grab.entity);
+    let triggered = entered
+        .read()
+        .filter(|entered| player.contains(entered.entity))
+        .map(|entered| entered.trigger);
+
+    for entity in grabbed.chain(triggered) {
+        if let Ok(artifact) = artifacts.get(entity) {
+            shifts.send(ShiftDimension {
+                to: artifact.to,
+                carry_held: artifact.carry_held,
+            });
+        }
+    }
+}
+
+/// Change the active layer and bring held objects along, or drop them.
+fn shift_dimension(
+    mut commands: Commands,
+    mut shifts: EventReader<ShiftDimension>,
+    mut shifted: EventWriter<DimensionShifted>,
+    mut active: ResMut<ActiveDimension>,
+    held: Query<Entity, With<Held>>,
+) {
+    for shift in shifts.read() {
+        if shift.to == active.0 {
+            continue;
+        }
+
+        for entity in &held {
+            if shift.carry_held {
+                commands.entity(entity).insert(DimensionLayer(shift.to));
+            } else {
+                commands.add(DropHeld(entity));
+            }
+        }
+
+        shifted.send(DimensionShifted {
+            from: active.0,
+            to: shift.to,
+        });
+        active.0 = shift.to;
+    }
+}
+
+/// Entities tagged with a layer and what `apply_layers` changes on them.
+type LayeredQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        Ref<'static, DimensionLayer>,
+        &'static mut Visibility,
+        Has<HiddenByLayer>,
+        Option<&'static mut CollisionLayers>,
+        Option<&'static BaseCollisionLayers>,
+    ),
+>;
+
+/// Hide entities outside the active layer and turn off their collisions.
+fn apply_layers(mut commands: Commands, active: Res<ActiveDimension>, mut entities: LayeredQuery) {
+    for (entity, layer, mut visibility, hidden_by_layer, collision_layers, base) in &mut entities {
+        if !active.is_changed() && !layer.is_changed() {
+            continue;
+        }
+
+        let in_layer = layer.0 == active.0;
+        if in_layer && hidden_by_layer {
+            *visibility = Visibility::Inherited;
+            commands.entity(entity).remove::<HiddenByLayer>();
+        } else if !in_layer && *visibility != Visibility::Hidden {
+            *visibility = Visibility::Hidden;
+            commands.entity(entity).insert(HiddenByLayer);
+        }
+
+        let base = match base {
+            Some(base) => base.0,
+            None => {
+                let base = collision_layers.as_deref().copied().unwrap_or_default();
+                commands.entity(entity).insert(BaseCollisionLayers(base));
+                base
+            }
+        };
+        let layers = if in_layer {
+            base
+        } else {
+            CollisionLayers::new(LayerMask::NONE, LayerMask::NONE)
+        };
+        match collision_layers {
+            Some(mut collision_layers) => *collision_layers = layers,
+            None => {
+                commands.entity(entity).insert(layers);
+            }
+        }
+    }
+}
+
+/// Copy the active layer into the save data.
+fn collect_dimension(mut save: ResMut<SaveData>, active: Res<ActiveDimension>) {
+    save.dimension = active.0;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::time::TimeUpdateStrategy;
+
+    use super::*;
+    use crate::player::{pick_up, Carriable, PlayerCamera};
+
+    /// A headless app with a player camera and a crate in layer 0, with one tick per update.
+    fn app() -> (App, Entity) {
+        let mut app = App::new();
+        let step = Duration::from_millis(100);
+        app.add_plugins((
+            MinimalPlugins,
+            HierarchyPlugin,
+            TransformPlugin,
+            AstraliminalDimensionPlugin,
+        ))
+        .insert_resource(Time::<Fixed>::from_duration(step))
+        .insert_resource(TimeUpdateStrategy::ManualDuration(step))
+        .init_resource::<SaveData>()
+        .add_event::<Grab>()
+        .add_event::<TriggerEntered>()
+        .add_systems(FixedUpdate, pick_up.in_set(AstralSet::Player));
+        app.world.spawn((
+            PlayerCamera,
+            TransformBundle::from_transform(Transform::from_xyz(0.0, 2.0, 0.0)),
+        ));
+        let crate_ = app
+            .world
+            .spawn((
+                Carriable,
+                RigidBody::Dynamic,
+                DimensionLayer(0),
+                VisibilityBundle::default(),
+                TransformBundle::from_transform(Transform::from_xyz(1.0, 2.0, -2.0)),
+            ))
+            .id();
+        // The first update has no delta.
+        app.update();
+        (app, crate_)
+    }
+
+    fn grab(app: &mut App, entity: Entity) {
+        app.world.send_event(Grab {
+            entity,
+            point: Vec3::ZERO,
+        });
+        app.update();
+    }
+
+    fn shift(app: &mut App, to: u8, carry_held: bool) {
+        app.world.send_event(ShiftDimension { to, carry_held });
+        app.update();
+    }
+
+    #[test]
+    fn held_objects_come_along() {
+        let (mut app, crate_) = app();
+        grab(&mut app, crate_);
+        let entity = app.world.entity(crate_);
+        assert!(entity.contains::<Held>());
+        assert!(entity.contains::<Parent>());
+        assert_eq!(entity.get::<RigidBody>(), Some(&RigidBody::Kinematic));
+
+        shift(&mut app, 1, true);
+        let entity = app.world.entity(crate_);
+        assert_eq!(app.world.resource::<ActiveDimension>().0, 1);
+        assert_eq!(entity.get::<DimensionLayer>(), Some(&DimensionLayer(1)));
+        assert_eq!(entity.get::<Visibility>(), Some(&Visibility::Inherited));
+        assert!(entity.contains::<Held>());
+
+        // Grabbing it again puts it down where it is, falling again.
+        grab(&mut app, crate_);
+        let entity = app.world.entity(crate_);
+        assert!(!entity.contains::<Held>());
+        assert!(!entity.contains::<Parent>());
+        assert_eq!(entity.get::<RigidBody>(), Some(&RigidBody::Dynamic));
+        assert_eq!(
+            entity.get::<Transform>().unwrap().translation,
+            Vec3::new(1.0, 2.0, -2.0)
+        );
+    }
+
+    #[test]
+    fn held_objects_stay_behind() {
+        let (mut app, crate_) = app();
+        app.world.entity_mut(crate_).insert(RigidBody::Kinematic);
+        app.update();
+        grab(&mut app, crate_);
+
+        shift(&mut app, 1, false);
+        let entity = app.world.entity(crate_);
+        assert!(!entity.contains::<Held>());
+        assert!(!entity.contains::<Parent>());
+        assert_eq!(entity.get::<DimensionLayer>(), Some(&DimensionLayer(0)));
+        assert_eq!(entity.get::<Visibility>(), Some(&Visibility::Hidden));
+        // It was kinematic before it was picked up, so it stays kinematic.
+        assert_eq!(entity.get::<RigidBody>(), Some(&RigidBody::Kinematic));
+    }
+}
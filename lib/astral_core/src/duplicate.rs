@@ -0,0 +1,377 @@
+//! Astraliminal's Duplicate plugin.
+//!
+//! Grabbing a `Duplicable` object leaves it where it is and spawns a copy. Copies belong to the
+//! room they were made in and are cleaned up when the player leaves that room, which also gives
+//! the objects their clone budgets back.
+
+use bevy::{prelude::*, utils::HashMap};
+use bevy_xpbd_3d::prelude::*;
+
+use crate::{
+    level::LevelEntity,
+    player::Grab,
+    room::{CurrentRoom, RoomChanged},
+    save::{SaveData, SaveSet, SavedClone},
+    schedule::AstralSet,
+};
+
+/// Default for the total number of clones that may exist in one room.
+const MAX_CLONES_PER_ROOM: usize = 32;
+
+/// An object that spawns a copy of itself when grabbed. Needs a `Name` so clones can be saved.
+#[derive(Component, Reflect, Debug, Clone)]
+#[reflect(Component)]
+pub struct Duplicable {
+    /// How many clones of this object may exist at the same time.
+    pub max_clones: usize,
+    /// Where the copy appears, relative to the object and multiplied by its current scale.
+    pub offset: Vec3,
+}
+
+impl Default for Duplicable {
+    fn default() -> Self {
+        Self {
+            max_clones: 1,
+            offset: Vec3::Y,
+        }
+    }
+}
+
+/// A copy made from a `Duplicable` object.
+#[derive(Component, Reflect, Debug, Default, Clone)]
+#[reflect(Component)]
+pub struct CloneOf {
+    /// The `Name` of the source object.
+    pub source: String,
+    /// The room the clone was made in.
+    pub room: String,
+}
+
+/// Limits on clones across all objects.
+#[derive(Resource, Reflect, Debug, Clone)]
+#[reflect(Resource)]
+pub struct CloneBudget {
+    /// Total number of clones that may exist in one room.
+    pub per_room: usize,
+}
+
+impl Default for CloneBudget {
+    fn default() -> Self {
+        Self {
+            per_room: MAX_CLONES_PER_ROOM,
+        }
+    }
+}
+
+/// Sent when an object was duplicated.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct Duplicated {
+    /// The object that was grabbed.
+    pub source: Entity,
+    /// The new copy.
+    pub clone: Entity,
+}
+
+pub struct AstraliminalDuplicatePlugin;
+
+impl Plugin for AstraliminalDuplicatePlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Duplicable>()
+            .register_type::<CloneOf>()
+            .register_type::<CloneBudget>()
+            .init_resource::<CloneBudget>()
+            .add_event::<Duplicated>()
+            .add_systems(
+                FixedUpdate,
+                (clean_up_clones, restore_clones, duplicate)
+                    .chain()
+                    .in_set(AstralSet::React),
+            )
+            .add_systems(Last, collect_clones.in_set(SaveSet::Collect));
+    }
+}
+
+/// The parts of an object that a copy takes over.
+type SourceQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static Name,
+        &'static Transform,
+        Option<&'static Handle<Scene>>,
+        Option<&'static Handle<Mesh>>,
+        Option<&'static Handle<StandardMaterial>>,
+        Option<&'static RigidBody>,
+        Option<&'static Collider>,
+        Option<&'static Friction>,
+        Option<&'static Restitution>,
+    ),
+>;
+
+/// Spawn a copy of `source` at `transform`. Returns `None` if `source` isn't a named object.
+fn spawn_clone(
+    commands: &mut Commands,
+    sources: &SourceQuery,
+    source: Entity,
+    room: &str,
+    transform: Transform,
+) -> Option<Entity> {
+    let (name, _, scene, mesh, material, body, collider, friction, restitution) =
+        sources.get(source).ok()?;
+
+    let mut clone = commands.spawn((
+        SpatialBundle::from_transform(transform),
+        Name::new(format!("{} (clone)", name)),
+        CloneOf {
+            source: name.to_string(),
+            room: room.to_string(),
+        },
+        LevelEntity,
+    ));
+
+    if let Some(scene) = scene {
+        clone.insert(scene.clone());
+    }
+    if let Some(mesh) = mesh {
+        clone.insert(mesh.clone());
+    }
+    if let Some(material) = material {
+        clone.insert(material.clone());
+    }
+    if let Some(body) = body {
+        clone.insert(*body);
+    }
+    if let Some(collider) = collider {
+        clone.insert(collider.clone());
+    }
+    if let Some(friction) = friction {
+        clone.insert(*friction);
+    }
+    if let Some(restitution) = restitution {
+        clone.insert(*restitution);
+    }
+
+    Some(clone.id())
+}
+
+/// Duplicate grabbed objects while they and the room still have clones to spare.
+#[allow(clippy::too_many_arguments)]
+fn duplicate(
+    mut commands: Commands,
+    mut grabs: EventReader<Grab>,
+    mut duplicated: EventWriter<Duplicated>,
+    budget: Res<CloneBudget>,
+    current: Res<CurrentRoom>,
+    duplicables: Query<(&Name, &Duplicable)>,
+    sources: SourceQuery,
+    clones: Query<&CloneOf>,
+) {
+    let Some(room) = &current.0 else {
+        grabs.clear();
+        return;
+    };
+
+    let mut in_room = clones.iter().filter(|clone| &clone.room == room).count();
+    // Clones spawned this tick aren't in the query yet.
+    let mut spawned: HashMap<&str, usize> = HashMap::default();
+
+    for grab in grabs.read() {
+        let Ok((name, duplicable)) = duplicables.get(grab.entity) else {
+            continue;
+        };
+
+        let of_source = clones
+            .iter()
+            .filter(|clone| clone.source == name.as_str())
+            .count()
+            + spawned.get(name.as_str()).copied().unwrap_or_default();
+        if of_source >= duplicable.max_clones || in_room >= budget.per_room {
+            continue;
+        }
+
+        let Ok((_, transform, ..)) = sources.get(grab.entity) else {
+            continue;
+        };
+        let mut transform = *transform;
+        transform.translation += transform.rotation * (duplicable.offset * transform.scale);
+
+        if let Some(clone) = spawn_clone(&mut commands, &sources, grab.entity, room, transform) {
+            in_room += 1;
+            *spawned.entry(name.as_str()).or_default() += 1;
+            duplicated.send(Duplicated {
+                source: grab.entity,
+                clone,
+            });
+        }
+    }
+}
+
+/// Remove the clones of the room the player just left.
+fn clean_up_clones(
+    mut commands: Commands,
+    mut changed: EventReader<RoomChanged>,
+    mut save: ResMut<SaveData>,
+    clones: Query<(Entity, &CloneOf)>,
+) {
+    for RoomChanged { from, .. } in changed.read() {
+        let Some(from) = from else {
+            continue;
+        };
+
+        for (entity, clone) in &clones {
+            if &clone.room == from {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+        save.clones.retain(|clone| &clone.room != from);
+    }
+}
+
+/// Bring back saved clones when the player enters their room.
+fn restore_clones(
+    mut commands: Commands,
+    mut changed: EventReader<RoomChanged>,
+    save: Res<SaveData>,
+    sources: SourceQuery,
+    named: Query<(Entity, &Name), With<Duplicable>>,
+) {
+    for RoomChanged { to, .. } in changed.read() {
+        let Some(to) = to else {
+            continue;
+        };
+
+        for saved in save.clones.iter().filter(|clone| &clone.room == to) {
+            let Some((source, _)) = named.iter().find(|(_, name)| name.as_str() == saved.source)
+            else {
+                warn!("Saved clone of missing object {}", saved.source);
+                continue;
+            };
+            spawn_clone(&mut commands, &sources, source, to, saved.transform);
+        }
+    }
+}
+
+/// Copy the live clones into the save data.
+fn collect_clones(mut save: ResMut<SaveData>, clones: Query<(&CloneOf, &Transform)>) {
+    save.clones = clones
+        .iter()
+        .map(|(clone, transform)| SavedClone {
+            room: clone.room.clone(),
+            source: clone.source.clone(),
+            transform: *transform,
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::time::TimeUpdateStrategy;
+
+    use super::*;
+
+    /// A headless app where the player is in room "a", with one tick per update.
+    fn app() -> App {
+        let mut app = App::new();
+        let step = Duration::from_millis(100);
+        app.add_plugins((MinimalPlugins, AstraliminalDuplicatePlugin))
+            .insert_resource(Time::<Fixed>::from_duration(step))
+            .insert_resource(TimeUpdateStrategy::ManualDuration(step))
+            .insert_resource(CurrentRoom(Some("a".to_string())))
+            .init_resource::<SaveData>()
+            .add_event::<Grab>()
+            .add_event::<RoomChanged>();
+        // The first update has no delta.
+        app.update();
+        app
+    }
+
+    fn duplicable(app: &mut App, name: &str, max_clones: usize) -> Entity {
+        app.world
+            .spawn((
+                Name::new(name.to_string()),
+                Transform::default(),
+                Duplicable {
+                    max_clones,
+                    ..default()
+                },
+            ))
+            .id()
+    }
+
+    fn grab(app: &mut App, entity: Entity) {
+        app.world.send_event(Grab {
+            entity,
+            point: Vec3::ZERO,
+        });
+    }
+
+    fn clones(app: &mut App, source: &str) -> usize {
+        app.world
+            .query::<&CloneOf>()
+            .iter(&app.world)
+            .filter(|clone| clone.source == source)
+            .count()
+    }
+
+    #[test]
+    fn objects_keep_to_their_budget() {
+        let mut app = app();
+        let crate_ = duplicable(&mut app, "crate", 2);
+
+        for _ in 0..3 {
+            grab(&mut app, crate_);
+            app.update();
+        }
+        assert_eq!(clones(&mut app, "crate"), 2);
+    }
+
+    #[test]
+    fn grabs_in_one_tick_keep_to_the_budget() {
+        let mut app = app();
+        let crate_ = duplicable(&mut app, "crate", 2);
+
+        for _ in 0..3 {
+            grab(&mut app, crate_);
+        }
+        app.update();
+        assert_eq!(clones(&mut app, "crate"), 2);
+    }
+
+    #[test]
+    fn rooms_keep_to_their_budget() {
+        let mut app = app();
+        app.insert_resource(CloneBudget { per_room: 3 });
+        let crate_ = duplicable(&mut app, "crate", 2);
+        let ball = duplicable(&mut app, "ball", 2);
+
+        grab(&mut app, crate_);
+        grab(&mut app, ball);
+        grab(&mut app, crate_);
+        grab(&mut app, ball);
+        app.update();
+        assert_eq!(clones(&mut app, "crate") + clones(&mut app, "ball"), 3);
+    }
+
+    #[test]
+    fn leaving_the_room_gives_the_budget_back() {
+        let mut app = app();
+        let crate_ = duplicable(&mut app, "crate", 1);
+        grab(&mut app, crate_);
+        app.update();
+        assert_eq!(clones(&mut app, "crate"), 1);
+
+        app.insert_resource(CurrentRoom(Some("b".to_string())));
+        app.world.send_event(RoomChanged {
+            from: Some("a".to_string()),
+            to: Some("b".to_string()),
+        });
+        app.update();
+        assert_eq!(clones(&mut app, "crate"), 0);
+
+        grab(&mut app, crate_);
+        app.update();
+        assert_eq!(clones(&mut app, "crate"), 1);
+    }
+}
@@ -0,0 +1,627 @@
+//! Astraliminal's Ending plugin.
+//!
+//! The game's endings, e.g. the Zeta Dimension's "Explore Other Possibilities" and the island
+//! path, are listed in an `.endings.ron` file set as the `GameEndings`. Send `ReachEnding`, or use
+//! the `reach_ending(...)` story effect, to end the game: the ending is recorded as seen, its
+//! timeline plays if it has one, and then its own credits roll. Skipping during the timeline goes
+//! straight to the credits, as does a timeline that fails to load or can't play because another
+//! one is playing. `CreditsFinished` is sent once the credits are over or skipped.
+//!
+//! The endings seen are kept across playthroughs and saved as soon as a new one is reached.
+//! `EndingCompletion` says how many of the game's endings the player has seen, for menus.
+
+use std::collections::BTreeSet;
+
+use bevy::{asset::LoadState, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    player::InputBlocked,
+    ron_loader::RonLoader,
+    save::{SaveData, SaveGame, SaveSet},
+    timeline::{PlayTimeline, Sequencer, SkipTimeline, Timeline, TimelineFinished},
+};
+
+/// Reason in `InputBlocked` while an ending is shown.
+const BLOCK_REASON: &str = "credits";
+
+/// The game's endings, loaded from an `.endings.ron` file.
+#[derive(Asset, TypePath, Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Endings {
+    pub endings: Vec<Ending>,
+}
+
+impl Endings {
+    /// The ending with the given id.
+    pub fn ending(&self, id: &str) -> Option<&Ending> {
+        self.endings.iter().find(|ending| ending.id == id)
+    }
+
+    /// How many of these endings have been seen.
+    pub fn completion(&self, seen: &EndingsSeen) -> EndingCompletion {
+        EndingCompletion {
+            seen: self
+                .endings
+                .iter()
+                .filter(|ending| seen.0.contains(&ending.id))
+                .count(),
+            total: self.endings.len(),
+        }
+    }
+}
+
+/// A way the game can end.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Ending {
+    /// Unique id, used by `reach_ending(...)` and `seen_ending(...)`.
+    pub id: String,
+    /// Shown at the top of the credits and in menus.
+    pub title: String,
+    /// Asset path of a timeline played before the credits, e.g. `"zeta.timeline.ron"`.
+    pub timeline: Option<String>,
+    /// Dialogue node a New Game+ can start at once the ending has been seen, e.g. `Z.a`.
+    pub branch_point: Option<String>,
+    /// Lines of the credits, top to bottom. Empty lines leave a gap.
+    pub credits: Vec<String>,
+    /// Seconds the credits take to scroll by.
+    pub credits_seconds: f32,
+}
+
+impl Default for Ending {
+    fn default() -> Self {
+        Self {
+            id: String::new(),
+            title: String::new(),
+            timeline: None,
+            branch_point: None,
+            credits: Vec::new(),
+            credits_seconds: 60.0,
+        }
+    }
+}
+
+/// The game's endings.
+#[derive(Resource, Debug, Default, Clone)]
+pub struct GameEndings(pub Handle<Endings>);
+
+/// Ids of the endings reached, in any playthrough.
+#[derive(Resource, Debug, Default, Clone, PartialEq, Eq)]
+pub struct EndingsSeen(pub BTreeSet<String>);
+
+/// How many of the game's endings have been seen.
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct EndingCompletion {
+    pub seen: usize,
+    pub total: usize,
+}
+
+impl EndingCompletion {
+    /// The endings seen, from 0 to 100.
+    pub fn percent(&self) -> f32 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        100.0 * self.seen as f32 / self.total as f32
+    }
+}
+
+/// The ending being shown, if any.
+#[derive(Resource, Debug, Default, Clone)]
+pub struct Credits {
+    rolling: Option<Roll>,
+}
+
+#[derive(Debug, Clone)]
+struct Roll {
+    ending: Ending,
+    /// The ending's timeline, until it has finished.
+    timeline: Option<Handle<Timeline>>,
+    /// Seconds the credits have been scrolling.
+    elapsed: f32,
+}
+
+impl Credits {
+    /// Whether an ending is being shown.
+    pub fn is_rolling(&self) -> bool {
+        self.rolling.is_some()
+    }
+
+    /// The ending being shown.
+    pub fn ending(&self) -> Option<&Ending> {
+        self.rolling.as_ref().map(|roll| &roll.ending)
+    }
+
+    /// How far the credits have scrolled, from 0 to 1, or `None` while the timeline plays.
+    pub fn progress(&self) -> Option<f32> {
+        let roll = self
+            .rolling
+            .as_ref()
+            .filter(|roll| roll.timeline.is_none())?;
+        Some((roll.elapsed / roll.ending.credits_seconds.max(f32::EPSILON)).min(1.0))
+    }
+}
+
+/// Send to end the game with the ending of this id.
+#[derive(Event, Debug, Clone, PartialEq, Eq)]
+pub struct ReachEnding {
+    pub id: String,
+}
+
+/// Sent when an ending is reached.
+#[derive(Event, Debug, Clone, PartialEq, Eq)]
+pub struct EndingReached {
+    pub id: String,
+    /// Whether no playthrough reached it before.
+    pub first_time: bool,
+}
+
+/// Send to skip the ending's timeline, or else the rest of the credits.
+#[derive(Event, Debug, Default, Clone, Copy)]
+pub struct SkipCredits;
+
+/// Sent when the credits are over, e.g. to go back to the menu.
+#[derive(Event, Debug, Clone, PartialEq, Eq)]
+pub struct CreditsFinished {
+    pub ending: String,
+    pub skipped: bool,
+}
+
+/// The scrolling part of the credits.
+#[derive(Component, Debug, Default, Clone, Copy)]
+struct CreditsRoll;
+
+/// The full-screen backdrop of the credits.
+#[derive(Component, Debug, Default, Clone, Copy)]
+struct CreditsScreen;
+
+pub struct AstraliminalEndingPlugin;
+
+impl Plugin for AstraliminalEndingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<Endings>()
+            .register_asset_loader(RonLoader::<Endings>::new(&["endings.ron"]))
+            .init_resource::<EndingsSeen>()
+            .init_resource::<EndingCompletion>()
+            .init_resource::<Credits>()
+            .add_event::<ReachEnding>()
+            .add_event::<EndingReached>()
+            .add_event::<SkipCredits>()
+            .add_event::<CreditsFinished>()
+            .add_systems(Startup, spawn_credits)
+            .add_systems(PreUpdate, restore_endings.in_set(SaveSet::Restore))
+            .add_systems(
+                Update,
+                (
+                    (reach_endings, update_completion).run_if(resource_exists::<GameEndings>),
+                    skip_input,
+                    roll_credits,
+                    update_credits_ui,
+                )
+                    .chain(),
+            )
+            .add_systems(Last, collect_endings.in_set(SaveSet::Collect));
+    }
+}
+
+/// Start with the endings from the save data.
+fn restore_endings(mut seen: ResMut<EndingsSeen>, save: Res<SaveData>) {
+    seen.0 = save.endings_seen.clone();
+}
+
+/// Copy the endings into the save data.
+fn collect_endings(mut save: ResMut<SaveData>, seen: Res<EndingsSeen>) {
+    save.endings_seen = seen.0.clone();
+}
+
+/// Record the endings reached and start showing them.
+#[allow(clippy::too_many_arguments)]
+fn reach_endings(
+    mut requests: EventReader<ReachEnding>,
+    mut reached: EventWriter<EndingReached>,
+    mut timelines: EventWriter<PlayTimeline>,
+    mut saves: EventWriter<SaveGame>,
+    mut seen: ResMut<EndingsSeen>,
+    mut credits: ResMut<Credits>,
+    mut blocked: ResMut<InputBlocked>,
+    game: Res<GameEndings>,
+    endings: Res<Assets<Endings>>,
+    sequencer: Res<Sequencer>,
+    asset_server: Res<AssetServer>,
+) {
+    let Some(endings) = endings.get(&game.0) else {
+        if !requests.is_empty() {
+            warn!("Endings are not loaded, ignoring ReachEnding");
+            requests.clear();
+        }
+        return;
+    };
+
+    for ReachEnding { id } in requests.read() {
+        let Some(ending) = endings.ending(id) else {
+            warn!("Ending {} does not exist", id);
+            continue;
+        };
+        if credits.is_rolling() {
+            warn!("An ending is already being shown, ignoring {}", id);
+            continue;
+        }
+
+        let first_time = seen.0.insert(id.clone());
+        info!("Reached ending {}", id);
+        reached.send(EndingReached {
+            id: id.clone(),
+            first_time,
+        });
+        if first_time {
+            saves.send(SaveGame);
+        }
+
+        let mut timeline = ending.timeline.as_ref().map(|path| asset_server.load(path));
+        if timeline.is_some() && sequencer.is_playing() {
+            warn!(
+                "A timeline is already playing, rolling the credits of {} without theirs",
+                id
+            );
+            timeline = None;
+        }
+        if let Some(timeline) = &timeline {
+            timelines.send(PlayTimeline(timeline.clone()));
+        }
+        blocked.0.insert(BLOCK_REASON.to_string());
+        credits.rolling = Some(Roll {
+            ending: ending.clone(),
+            timeline,
+            elapsed: 0.0,
+        });
+    }
+}
+
+/// Keep the completion up to date with the endings and those seen.
+fn update_completion(
+    mut completion: ResMut<EndingCompletion>,
+    seen: Res<EndingsSeen>,
+    game: Res<GameEndings>,
+    endings: Res<Assets<Endings>>,
+) {
+    if let Some(endings) = endings.get(&game.0) {
+        completion.set_if_neq(endings.completion(&seen));
+    }
+}
+
+/// Skip the ending's timeline or the credits on key press.
+fn skip_input(
+    keys: Res<ButtonInput<KeyCode>>,
+    credits: Res<Credits>,
+    mut skips: EventWriter<SkipCredits>,
+) {
+    // TODO: pull key codes from config.
+    if credits.is_rolling()
+        && keys.any_just_pressed([KeyCode::Escape, KeyCode::Space, KeyCode::Enter])
+    {
+        skips.send(SkipCredits);
+    }
+}
+
+/// Wait for the ending's timeline, then scroll the credits until they are over or skipped.
+#[allow(clippy::too_many_arguments)]
+fn roll_credits(
+    mut credits: ResMut<Credits>,
+    mut blocked: ResMut<InputBlocked>,
+    mut finished_timelines: EventReader<TimelineFinished>,
+    mut skips: EventReader<SkipCredits>,
+    mut skip_timelines: EventWriter<SkipTimeline>,
+    mut finished: EventWriter<CreditsFinished>,
+    asset_server: Res<AssetServer>,
+    time: Res<Time>,
+) {
+    let skipped = skips.read().count() > 0;
+    let Some(roll) = &mut credits.rolling else {
+        finished_timelines.clear();
+        return;
+    };
+
+    if let Some(timeline) = &roll.timeline {
+        let over = finished_timelines
+            .read()
+            .any(|finished| &finished.timeline == timeline);
+        if asset_server.load_state(timeline) == LoadState::Failed {
+            warn!(
+                "Timeline of ending {} failed to load, rolling the credits",
+                roll.ending.id
+            );
+        } else if !over {
+            // A skipped timeline still finishes, unless it can't be skipped.
+            if skipped {
+                skip_timelines.send(SkipTimeline);
+            }
+            return;
+        }
+        roll.timeline = None;
+        return;
+    }
+
+    roll.elapsed += time.delta_seconds();
+    if skipped || roll.elapsed >= roll.ending.credits_seconds {
+        blocked.0.remove(BLOCK_REASON);
+        finished.send(CreditsFinished {
+            ending: roll.ending.id.clone(),
+            skipped,
+        });
+        credits.rolling = None;
+    }
+}
+
+/// Spawn the credits screen, hidden, above everything but the fade overlay.
+fn spawn_credits(mut commands: Commands) {
+    commands
+        .spawn((
+            CreditsScreen,
+            NodeBundle {
+                style: Style {
+                    display: Display::None,
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    justify_content: JustifyContent::Center,
+                    overflow: Overflow::clip(),
+                    ..default()
+                },
+                background_color: Color::BLACK.into(),
+                z_index: ZIndex::Global(i32::MAX - 2),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                CreditsRoll,
+                TextBundle::default()
+                    .with_text_justify(JustifyText::Center)
+                    .with_style(Style {
+                        position_type: PositionType::Absolute,
+                        top: Val::Percent(100.0),
+                        ..default()
+                    }),
+            ));
+        });
+}
+
+/// Show the credits of the ending being shown and scroll them from the bottom of the screen to
+/// past its top.
+#[allow(clippy::type_complexity)]
+fn update_credits_ui(
+    credits: Res<Credits>,
+    mut screens: Query<(&mut Style, &Node), (With<CreditsScreen>, Without<CreditsRoll>)>,
+    mut rolls: Query<(&mut Style, &mut Text, &Node), With<CreditsRoll>>,
+    mut shown: Local<Option<String>>,
+) {
+    let progress = credits.progress();
+    let Ok((mut screen_style, screen)) = screens.get_single_mut() else {
+        return;
+    };
+    let display = if progress.is_some() {
+        Display::Flex
+    } else {
+        Display::None
+    };
+    if screen_style.display != display {
+        screen_style.display = display;
+    }
+    let (Some(progress), Some(ending)) = (progress, credits.ending()) else {
+        *shown = None;
+        return;
+    };
+
+    let Ok((mut style, mut text, roll)) = rolls.get_single_mut() else {
+        return;
+    };
+    if shown.as_deref() != Some(ending.id.as_str()) {
+        let line = |value: String, size: f32| {
+            TextSection::new(
+                value,
+                TextStyle {
+                    font_size: size,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            )
+        };
+        text.sections = std::iter::once(line(format!("{}\n\n", ending.title), 48.0))
+            .chain(std::iter::once(line(ending.credits.join("\n"), 28.0)))
+            .collect();
+        *shown = Some(ending.id.clone());
+    }
+
+    let height = screen.size().y;
+    style.top = Val::Px(height - (height + roll.size().y) * progress);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::time::TimeUpdateStrategy;
+
+    use super::*;
+    use crate::{
+        fade::ScreenFade,
+        timeline::{AstraliminalTimelinePlugin, TimelineAction, TimelineClip, TimelineTrack},
+    };
+
+    fn endings() -> Endings {
+        let ending = |id: &str| Ending {
+            id: id.to_string(),
+            timeline: Some(format!("{}.timeline.ron", id)),
+            credits_seconds: 1.0,
+            ..default()
+        };
+        Endings {
+            endings: vec![ending("zeta"), ending("island"), ending("boat")],
+        }
+    }
+
+    #[test]
+    fn completion() {
+        let endings = endings();
+        let seen = |ids: &[&str]| EndingsSeen(ids.iter().map(|id| id.to_string()).collect());
+
+        let none = endings.completion(&seen(&[]));
+        assert_eq!(none, EndingCompletion { seen: 0, total: 3 });
+        assert_eq!(none.percent(), 0.0);
+
+        // Endings no longer in the game don't count.
+        let some = endings.completion(&seen(&["island", "cut"]));
+        assert_eq!(some, EndingCompletion { seen: 1, total: 3 });
+        assert!((some.percent() - 100.0 / 3.0).abs() < 1e-4);
+
+        let all = endings.completion(&seen(&["zeta", "island", "boat"]));
+        assert_eq!(all.percent(), 100.0);
+        assert_eq!(
+            Endings::default().completion(&seen(&["zeta"])).percent(),
+            0.0
+        );
+    }
+
+    /// A cutscene of `seconds`, written to the asset directory as `<ending>.timeline.ron`.
+    fn cutscene(seconds: f32, skippable: bool) -> Timeline {
+        Timeline {
+            skippable,
+            tracks: vec![TimelineTrack {
+                clips: vec![TimelineClip {
+                    seconds,
+                    action: TimelineAction::Wait,
+                }],
+            }],
+            ..default()
+        }
+    }
+
+    /// A headless app stepping 0.1 seconds a frame, with `endings()` loaded and the timelines of
+    /// "zeta" (skippable) and "boat" (unskippable) in a temporary asset directory.
+    fn app(test: &str) -> App {
+        let assets = std::env::temp_dir().join(format!("astral_{}_{}", test, std::process::id()));
+        std::fs::create_dir_all(&assets).unwrap();
+        for (ending, skippable) in [("zeta", true), ("boat", false)] {
+            let timeline = ron::to_string(&cutscene(5.0, skippable)).unwrap();
+            std::fs::write(assets.join(format!("{}.timeline.ron", ending)), timeline).unwrap();
+        }
+
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            AssetPlugin {
+                file_path: assets.to_string_lossy().into_owned(),
+                ..default()
+            },
+        ))
+        .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_millis(
+            100,
+        )))
+        .init_resource::<ScreenFade>()
+        .init_resource::<InputBlocked>()
+        .init_resource::<ButtonInput<KeyCode>>()
+        .init_resource::<SaveData>()
+        .add_event::<SaveGame>()
+        .add_plugins((AstraliminalTimelinePlugin, AstraliminalEndingPlugin));
+        let endings = app.world.resource_mut::<Assets<Endings>>().add(endings());
+        app.insert_resource(GameEndings(endings));
+        app.update();
+        app
+    }
+
+    /// Reach `ending` and update until its timeline plays, waiting for it to load.
+    fn reach(app: &mut App, ending: &str) {
+        app.world.send_event(ReachEnding {
+            id: ending.to_string(),
+        });
+        for _ in 0..500 {
+            app.update();
+            if app.world.resource::<Sequencer>().is_playing() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        panic!("timeline of {} never played", ending);
+    }
+
+    fn credits_finished(app: &App) -> Vec<bool> {
+        let events = app.world.resource::<Events<CreditsFinished>>();
+        let mut reader = events.get_reader();
+        reader
+            .read(events)
+            .map(|finished| finished.skipped)
+            .collect()
+    }
+
+    #[test]
+    fn skipping_the_timeline_rolls_the_credits() {
+        let mut app = app("skip_ending_timeline");
+        reach(&mut app, "zeta");
+        let credits = app.world.resource::<Credits>();
+        assert!(credits.is_rolling());
+        assert_eq!(credits.progress(), None);
+        assert!(app.world.resource::<InputBlocked>().is_blocked());
+
+        // The credits wait for the skipped timeline to finish.
+        app.world.send_event(SkipCredits);
+        app.update();
+        assert_eq!(app.world.resource::<Credits>().progress(), None);
+        for _ in 0..3 {
+            app.update();
+        }
+        assert!(!app.world.resource::<Sequencer>().is_playing());
+        assert!(app.world.resource::<Credits>().progress().is_some());
+        assert!(credits_finished(&app).is_empty());
+
+        app.world.send_event(SkipCredits);
+        app.update();
+        assert!(!app.world.resource::<Credits>().is_rolling());
+        assert_eq!(credits_finished(&app), vec![true]);
+        assert!(!app.world.resource::<InputBlocked>().is_blocked());
+    }
+
+    #[test]
+    fn unskippable_timelines_hold_the_credits() {
+        let mut app = app("unskippable_ending_timeline");
+        reach(&mut app, "boat");
+
+        app.world.send_event(SkipCredits);
+        for _ in 0..10 {
+            app.update();
+        }
+        assert!(app.world.resource::<Sequencer>().is_playing());
+        assert_eq!(app.world.resource::<Credits>().progress(), None);
+
+        for _ in 0..42 {
+            app.update();
+        }
+        assert!(!app.world.resource::<Sequencer>().is_playing());
+        assert!(app.world.resource::<Credits>().progress().is_some());
+        assert!(credits_finished(&app).is_empty());
+    }
+
+    #[test]
+    fn credits_roll_while_another_timeline_plays() {
+        let mut app = app("ending_during_timeline");
+        let cutscene = app.world.resource_mut::<Assets<Timeline>>().add(Timeline {
+            block_input: false,
+            ..cutscene(5.0, true)
+        });
+        app.world.send_event(PlayTimeline(cutscene));
+        app.update();
+        assert!(app.world.resource::<Sequencer>().is_playing());
+
+        app.world.send_event(ReachEnding {
+            id: "island".to_string(),
+        });
+        app.update();
+        assert_eq!(app.world.resource::<Credits>().progress(), Some(0.1));
+        for _ in 0..9 {
+            app.update();
+        }
+        assert!(app.world.resource::<Sequencer>().is_playing());
+        assert!(!app.world.resource::<Credits>().is_rolling());
+        assert_eq!(credits_finished(&app), vec![false]);
+        assert!(!app.world.resource::<InputBlocked>().is_blocked());
+    }
+}
@@ -0,0 +1,962 @@
+//! Story expressions, written as strings in dialogue scripts, triggers and logic graphs.
+//!
+//! A `Condition` such as `visited(Z.a) && !flag(met_seekers)` can use `true`, `false`, whole
+//! numbers, "strings", variables by name, `!`, `&&`, `||`, `==`, `!=`, `<`, `<=`, `>`, `>=`, `+`,
+//! `-`, parentheses and these functions:
+//!
+//! - `flag(name)`: whether the story flag is set.
+//! - `visited(node)`: whether the dialogue node has been reached.
+//! - `unlocked(achievement)`: whether the achievement is unlocked.
+//! - `seen_ending(ending)`: whether the ending has been reached, in any playthrough.
+//!
+//! `Effects` such as `set(met_seekers); inc(trust, 2); unlock_achievement(seeker)` are calls
+//! separated by `;`:
+//!
+//! - `set(flag)` and `clear(flag)`: set or clear a story flag.
+//! - `set(variable, value)`: set a variable to the value of an expression.
+//! - `inc(variable)` and `inc(variable, amount)`: add to a whole number variable.
+//! - `unlock_achievement(achievement)`.
+//! - `reach_ending(ending)`: end the game, rolling the ending's credits.
+//!
+//! Names may contain letters, digits, `_` and `.`. Variables that were never set read as `false`,
+//! `0` or `""`, whichever the expression needs. Both are parsed when the file they are in loads,
+//! and errors say at which column of the expression they are.
+
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// A story variable's value.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    Str(String),
+}
+
+impl Value {
+    /// What kind of value this is, for error messages.
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Bool(_) => "true or false",
+            Self::Int(_) => "a whole number",
+            Self::Str(_) => "a string",
+        }
+    }
+
+    /// What a variable of the same kind reads as when it was never set.
+    fn unset(&self) -> Self {
+        match self {
+            Self::Bool(_) => Self::Bool(false),
+            Self::Int(_) => Self::Int(0),
+            Self::Str(_) => Self::Str(String::new()),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bool(value) => write!(f, "{}", value),
+            Self::Int(value) => write!(f, "{}", value),
+            Self::Str(value) => write!(f, "{:?}", value),
+        }
+    }
+}
+
+/// The story state expressions read.
+pub trait Scope {
+    /// The value of a variable, if it was ever set.
+    fn variable(&self, name: &str) -> Option<&Value>;
+    /// Whether the story flag is set.
+    fn flag(&self, name: &str) -> bool;
+    /// Whether the dialogue node has been reached.
+    fn visited(&self, node: &str) -> bool;
+    /// Whether the achievement is unlocked.
+    fn unlocked(&self, achievement: &str) -> bool;
+    /// Whether the ending has been reached.
+    fn seen_ending(&self, ending: &str) -> bool;
+}
+
+/// The story state effects change.
+pub trait ScopeMut: Scope {
+    fn set_flag(&mut self, name: &str, set: bool);
+    fn set_variable(&mut self, name: &str, value: Value);
+    fn unlock(&mut self, achievement: &str);
+    fn reach_ending(&mut self, ending: &str);
+}
+
+/// An expression that can't be parsed or evaluated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpressionError {
+    /// The whole expression.
+    pub source: String,
+    /// Index of the character the error is at.
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at column {} of `{}`",
+            self.message,
+            self.position + 1,
+            self.source
+        )
+    }
+}
+
+impl std::error::Error for ExpressionError {}
+
+/// A parsed expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expr {
+    kind: ExprKind,
+    /// Index of the character the expression starts at.
+    position: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ExprKind {
+    Literal(Value),
+    Variable(String),
+    Flag(String),
+    Visited(String),
+    Unlocked(String),
+    SeenEnding(String),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Add,
+    Subtract,
+}
+
+/// Error message and position, before the source is added.
+type Failure = (usize, String);
+
+impl Expr {
+    /// The value of the expression, `None` for a variable that was never set.
+    fn value(&self, scope: &impl Scope) -> Result<Option<Value>, Failure> {
+        let value = match &self.kind {
+            ExprKind::Literal(value) => value.clone(),
+            ExprKind::Variable(name) => return Ok(scope.variable(name).cloned()),
+            ExprKind::Flag(name) => Value::Bool(scope.flag(name)),
+            ExprKind::Visited(node) => Value::Bool(scope.visited(node)),
+            ExprKind::Unlocked(achievement) => Value::Bool(scope.unlocked(achievement)),
+            ExprKind::SeenEnding(ending) => Value::Bool(scope.seen_ending(ending)),
+            ExprKind::Not(operand) => Value::Bool(!operand.boolean(scope)?),
+            ExprKind::Negate(operand) => Value::Int(
+                operand
+                    .integer(scope)?
+                    .checked_neg()
+                    .ok_or_else(|| self.fail("number is too large"))?,
+            ),
+            ExprKind::Binary(op, left, right) => self.binary(*op, left, right, scope)?,
+        };
+        Ok(Some(value))
+    }
+
+    fn binary(
+        &self,
+        op: BinaryOp,
+        left: &Expr,
+        right: &Expr,
+        scope: &impl Scope,
+    ) -> Result<Value, Failure> {
+        let value = match op {
+            BinaryOp::Or => Value::Bool(left.boolean(scope)? || right.boolean(scope)?),
+            BinaryOp::And => Value::Bool(left.boolean(scope)? && right.boolean(scope)?),
+            BinaryOp::Equal | BinaryOp::NotEqual => {
+                let (a, b) = match (left.value(scope)?, right.value(scope)?) {
+                    (Some(a), Some(b)) => (a, b),
+                    (Some(a), None) => (a.clone(), a.unset()),
+                    (None, Some(b)) => (b.unset(), b),
+                    (None, None) => (Value::Bool(false), Value::Bool(false)),
+                };
+                if std::mem::discriminant(&a) != std::mem::discriminant(&b) {
+                    return Err(self.fail(format!("can't compare {} with {}", a.kind(), b.kind())));
+                }
+                Value::Bool((a == b) == (op == BinaryOp::Equal))
+            }
+            BinaryOp::Less
+            | BinaryOp::LessOrEqual
+            | BinaryOp::Greater
+            | BinaryOp::GreaterOrEqual => {
+                let (a, b) = (left.integer(scope)?, right.integer(scope)?);
+                Value::Bool(match op {
+                    BinaryOp::Less => a < b,
+                    BinaryOp::LessOrEqual => a <= b,
+                    BinaryOp::Greater => a > b,
+                    _ => a >= b,
+                })
+            }
+            BinaryOp::Add | BinaryOp::Subtract => {
+                let (a, b) = (left.integer(scope)?, right.integer(scope)?);
+                let result = if op == BinaryOp::Add {
+                    a.checked_add(b)
+                } else {
+                    a.checked_sub(b)
+                };
+                Value::Int(result.ok_or_else(|| self.fail("number is too large"))?)
+            }
+        };
+        Ok(value)
+    }
+
+    fn boolean(&self, scope: &impl Scope) -> Result<bool, Failure> {
+        match self.value(scope)? {
+            None => Ok(false),
+            Some(Value::Bool(value)) => Ok(value),
+            Some(other) => {
+                Err(self.fail(format!("expected true or false, found {}", other.kind())))
+            }
+        }
+    }
+
+    fn integer(&self, scope: &impl Scope) -> Result<i64, Failure> {
+        match self.value(scope)? {
+            None => Ok(0),
+            Some(Value::Int(value)) => Ok(value),
+            Some(other) => {
+                Err(self.fail(format!("expected a whole number, found {}", other.kind())))
+            }
+        }
+    }
+
+    fn fail(&self, message: impl Into<String>) -> Failure {
+        (self.position, message.into())
+    }
+}
+
+/// A condition, e.g. `visited(Z.a) && !flag(met_seekers)`. Written as a string in data files.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Condition {
+    source: String,
+    expr: Expr,
+}
+
+impl Condition {
+    /// The condition as written.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Whether the condition holds.
+    pub fn evaluate(&self, scope: &impl Scope) -> Result<bool, ExpressionError> {
+        self.expr
+            .boolean(scope)
+            .map_err(|failure| error(&self.source, failure))
+    }
+}
+
+impl FromStr for Condition {
+    type Err = ExpressionError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser::new(source)?;
+        let expr = parser
+            .expression()
+            .map_err(|failure| error(source, failure))?;
+        parser.end().map_err(|failure| error(source, failure))?;
+        Ok(Self {
+            source: source.to_string(),
+            expr,
+        })
+    }
+}
+
+impl TryFrom<String> for Condition {
+    type Error = ExpressionError;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        source.parse()
+    }
+}
+
+impl From<Condition> for String {
+    fn from(condition: Condition) -> Self {
+        condition.source
+    }
+}
+
+/// Something effects do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Effect {
+    SetFlag(String),
+    ClearFlag(String),
+    Set { variable: String, value: Expr },
+    Increment { variable: String, amount: Expr },
+    UnlockAchievement(String),
+    ReachEnding(String),
+}
+
+/// Effects, e.g. `set(met_seekers); inc(trust, 2)`. Written as a string in data files.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Effects {
+    source: String,
+    /// Each effect and the index of the character it starts at.
+    effects: Vec<(usize, Effect)>,
+}
+
+impl Effects {
+    /// The effects as written.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Apply the effects in order. Stops at the first one that fails.
+    pub fn apply(&self, scope: &mut impl ScopeMut) -> Result<(), ExpressionError> {
+        let fail = |position, message: String| error(&self.source, (position, message));
+        for (position, effect) in &self.effects {
+            match effect {
+                Effect::SetFlag(name) => scope.set_flag(name, true),
+                Effect::ClearFlag(name) => scope.set_flag(name, false),
+                Effect::Set { variable, value } => {
+                    let value = value
+                        .value(scope)
+                        .map_err(|failure| error(&self.source, failure))?
+                        .ok_or_else(|| fail(value.position, "variable was never set".into()))?;
+                    scope.set_variable(variable, value);
+                }
+                Effect::Increment { variable, amount } => {
+                    let amount = amount
+                        .integer(scope)
+                        .map_err(|failure| error(&self.source, failure))?;
+                    let current = match scope.variable(variable) {
+                        None => 0,
+                        Some(Value::Int(current)) => *current,
+                        Some(other) => {
+                            let message =
+                                format!("{} is {}, not a whole number", variable, other.kind());
+                            return Err(fail(*position, message));
+                        }
+                    };
+                    let sum = current
+                        .checked_add(amount)
+                        .ok_or_else(|| fail(*position, "number is too large".into()))?;
+                    scope.set_variable(variable, Value::Int(sum));
+                }
+                Effect::UnlockAchievement(achievement) => scope.unlock(achievement),
+                Effect::ReachEnding(ending) => scope.reach_ending(ending),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Effects {
+    type Err = ExpressionError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser::new(source)?;
+        let effects = parser.effects().map_err(|failure| error(source, failure))?;
+        Ok(Self {
+            source: source.to_string(),
+            effects,
+        })
+    }
+}
+
+impl TryFrom<String> for Effects {
+    type Error = ExpressionError;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        source.parse()
+    }
+}
+
+impl From<Effects> for String {
+    fn from(effects: Effects) -> Self {
+        effects.source
+    }
+}
+
+fn error(source: &str, (position, message): Failure) -> ExpressionError {
+    ExpressionError {
+        source: source.to_string(),
+        position,
+        message,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Name(String),
+    Literal(Value),
+    Symbol(&'static str),
+    End,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Name(name) => write!(f, "`{}`", name),
+            Self::Literal(value) => write!(f, "`{}`", value),
+            Self::Symbol(symbol) => write!(f, "`{}`", symbol),
+            Self::End => write!(f, "the end"),
+        }
+    }
+}
+
+/// Longest first, so `<=` isn't read as `<`.
+const SYMBOLS: [&str; 15] = [
+    "&&", "||", "==", "!=", "<=", ">=", "<", ">", "!", "+", "-", "(", ")", ",", ";",
+];
+
+/// Split an expression into tokens and the index of the character each starts at.
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, ExpressionError> {
+    let chars: Vec<char> = source.chars().collect();
+    let fail = |position, message: String| Err(error(source, (position, message)));
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let start = i;
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        if c.is_ascii_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || "_.".contains(chars[i])) {
+                i += 1;
+            }
+            let name: String = chars[start..i].iter().collect();
+            tokens.push((
+                start,
+                match name.as_str() {
+                    "true" => Token::Literal(Value::Bool(true)),
+                    "false" => Token::Literal(Value::Bool(false)),
+                    _ => Token::Name(name),
+                },
+            ));
+        } else if c.is_ascii_digit() {
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            let digits: String = chars[start..i].iter().collect();
+            let Ok(number) = digits.parse() else {
+                return fail(start, "number is too large".into());
+            };
+            tokens.push((start, Token::Literal(Value::Int(number))));
+        } else if c == '"' {
+            let mut text = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return fail(start, "string is never closed".into()),
+                    Some('"') => break,
+                    Some('\\') => {
+                        match chars.get(i + 1) {
+                            Some(&escaped @ ('"' | '\\')) => text.push(escaped),
+                            _ => return fail(i, "expected `\\\"` or `\\\\`".into()),
+                        }
+                        i += 1;
+                    }
+                    Some(&other) => text.push(other),
+                }
+                i += 1;
+            }
+            i += 1;
+            tokens.push((start, Token::Literal(Value::Str(text))));
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            let Some(symbol) = SYMBOLS.into_iter().find(|symbol| rest.starts_with(symbol)) else {
+                let message = match c {
+                    '=' => "expected `==`".to_string(),
+                    '&' => "expected `&&`".to_string(),
+                    '|' => "expected `||`".to_string(),
+                    _ => format!("unexpected `{}`", c),
+                };
+                return fail(start, message);
+            };
+            i += symbol.len();
+            tokens.push((start, Token::Symbol(symbol)));
+        }
+    }
+
+    tokens.push((chars.len(), Token::End));
+    Ok(tokens)
+}
+
+/// Recursive descent parser, from lowest precedence to highest: `||`, `&&`, comparisons, `+` and
+/// `-`, then `!`, `-` and single values.
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+}
+
+impl Parser {
+    fn new(source: &str) -> Result<Self, ExpressionError> {
+        Ok(Self {
+            tokens: tokenize(source)?,
+            next: 0,
+        })
+    }
+
+    fn peek(&self) -> &(usize, Token) {
+        &self.tokens[self.next.min(self.tokens.len() - 1)]
+    }
+
+    fn bump(&mut self) -> (usize, Token) {
+        let token = self.peek().clone();
+        self.next += 1;
+        token
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.peek(), (_, Token::Symbol(s)) if *s == symbol);
+        if found {
+            self.next += 1;
+        }
+        found
+    }
+
+    fn unexpected(&self, expected: &str) -> Failure {
+        let (position, token) = self.peek();
+        (*position, format!("expected {}, found {}", expected, token))
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), Failure> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("`{}`", symbol)))
+        }
+    }
+
+    fn end(&self) -> Result<(), Failure> {
+        match self.peek() {
+            (_, Token::End) => Ok(()),
+            _ => Err(self.unexpected("an operator or the end")),
+        }
+    }
+
+    fn expression(&mut self) -> Result<Expr, Failure> {
+        self.binary(0)
+    }
+
+    /// Operators by precedence level, lowest first.
+    const LEVELS: [&'static [(&'static str, BinaryOp)]; 4] = [
+        &[("||", BinaryOp::Or)],
+        &[("&&", BinaryOp::And)],
+        &[
+            ("==", BinaryOp::Equal),
+            ("!=", BinaryOp::NotEqual),
+            ("<=", BinaryOp::LessOrEqual),
+            (">=", BinaryOp::GreaterOrEqual),
+            ("<", BinaryOp::Less),
+            (">", BinaryOp::Greater),
+        ],
+        &[("+", BinaryOp::Add), ("-", BinaryOp::Subtract)],
+    ];
+
+    fn binary(&mut self, level: usize) -> Result<Expr, Failure> {
+        let Some(operators) = Self::LEVELS.get(level) else {
+            return self.unary();
+        };
+        let mut left = self.binary(level + 1)?;
+        while let Some(&(_, op)) = operators.iter().find(|(symbol, _)| self.eat(symbol)) {
+            let right = self.binary(level + 1)?;
+            left = Expr {
+                position: left.position,
+                kind: ExprKind::Binary(op, Box::new(left), Box::new(right)),
+            };
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, Failure> {
+        let position = self.peek().0;
+        if self.eat("!") {
+            let operand = Box::new(self.unary()?);
+            return Ok(Expr {
+                kind: ExprKind::Not(operand),
+                position,
+            });
+        }
+        if self.eat("-") {
+            let operand = Box::new(self.unary()?);
+            return Ok(Expr {
+                kind: ExprKind::Negate(operand),
+                position,
+            });
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, Failure> {
+        let (position, token) = self.peek().clone();
+        let kind = match token {
+            Token::Literal(value) => {
+                self.next += 1;
+                ExprKind::Literal(value)
+            }
+            Token::Symbol("(") => {
+                self.next += 1;
+                let inner = self.expression()?;
+                self.expect(")")?;
+                return Ok(inner);
+            }
+            Token::Name(name) => {
+                self.next += 1;
+                if !self.eat("(") {
+                    return Ok(Expr {
+                        kind: ExprKind::Variable(name),
+                        position,
+                    });
+                }
+                let argument = self.name()?;
+                self.expect(")")?;
+                match name.as_str() {
+                    "flag" => ExprKind::Flag(argument),
+                    "visited" => ExprKind::Visited(argument),
+                    "unlocked" => ExprKind::Unlocked(argument),
+                    "seen_ending" => ExprKind::SeenEnding(argument),
+                    "set" | "clear" | "inc" | "unlock_achievement" | "reach_ending" => {
+                        return Err((
+                            position,
+                            format!("`{}` is an effect and can't be used in a condition", name),
+                        ));
+                    }
+                    _ => {
+                        return Err((
+                            position,
+                            format!(
+                                "unknown function `{}`; expected flag, visited, unlocked or \
+                                 seen_ending",
+                                name
+                            ),
+                        ));
+                    }
+                }
+            }
+            _ => return Err(self.unexpected("a value")),
+        };
+        Ok(Expr { kind, position })
+    }
+
+    /// A name given to a function, bare or as a string.
+    fn name(&mut self) -> Result<String, Failure> {
+        match self.peek().clone() {
+            (_, Token::Name(name)) | (_, Token::Literal(Value::Str(name))) => {
+                self.next += 1;
+                Ok(name)
+            }
+            _ => Err(self.unexpected("a name")),
+        }
+    }
+
+    fn effects(&mut self) -> Result<Vec<(usize, Effect)>, Failure> {
+        let mut effects = Vec::new();
+        loop {
+            while self.eat(";") {}
+            if let (_, Token::End) = self.peek() {
+                return Ok(effects);
+            }
+            effects.push(self.effect()?);
+            if !self.eat(";") {
+                self.end().map_err(|_| self.unexpected("`;` or the end"))?;
+            }
+        }
+    }
+
+    fn effect(&mut self) -> Result<(usize, Effect), Failure> {
+        let (position, token) = self.bump();
+        let Token::Name(function) = token else {
+            return Err((position, format!("expected an effect, found {}", token)));
+        };
+        self.expect("(")?;
+        let name = self.name()?;
+        let argument = if self.eat(",") {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.expect(")")?;
+
+        let effect = match (function.as_str(), argument) {
+            ("set", None) => Effect::SetFlag(name),
+            ("set", Some(value)) => Effect::Set {
+                variable: name,
+                value,
+            },
+            ("clear", None) => Effect::ClearFlag(name),
+            ("inc", amount) => Effect::Increment {
+                variable: name,
+                amount: amount.unwrap_or(Expr {
+                    kind: ExprKind::Literal(Value::Int(1)),
+                    position,
+                }),
+            },
+            ("unlock_achievement", None) => Effect::UnlockAchievement(name),
+            ("reach_ending", None) => Effect::ReachEnding(name),
+            ("clear" | "unlock_achievement" | "reach_ending", Some(value)) => {
+                return Err((
+                    value.position,
+                    format!("`{}` takes a single name", function),
+                ));
+            }
+            _ => {
+                return Err((
+                    position,
+                    format!(
+                        "unknown effect `{}`; expected set, clear, inc, unlock_achievement or \
+                         reach_ending",
+                        function
+                    ),
+                ));
+            }
+        };
+        Ok((position, effect))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Story {
+        variables: HashMap<String, Value>,
+        flags: HashSet<String>,
+        visited: HashSet<String>,
+        unlocked: HashSet<String>,
+        endings: HashSet<String>,
+    }
+
+    impl Scope for Story {
+        fn variable(&self, name: &str) -> Option<&Value> {
+            self.variables.get(name)
+        }
+
+        fn flag(&self, name: &str) -> bool {
+            self.flags.contains(name)
+        }
+
+        fn visited(&self, node: &str) -> bool {
+            self.visited.contains(node)
+        }
+
+        fn unlocked(&self, achievement: &str) -> bool {
+            self.unlocked.contains(achievement)
+        }
+
+        fn seen_ending(&self, ending: &str) -> bool {
+            self.endings.contains(ending)
+        }
+    }
+
+    impl ScopeMut for Story {
+        fn set_flag(&mut self, name: &str, set: bool) {
+            if set {
+                self.flags.insert(name.to_string());
+            } else {
+                self.flags.remove(name);
+            }
+        }
+
+        fn set_variable(&mut self, name: &str, value: Value) {
+            self.variables.insert(name.to_string(), value);
+        }
+
+        fn unlock(&mut self, achievement: &str) {
+            self.unlocked.insert(achievement.to_string());
+        }
+
+        fn reach_ending(&mut self, ending: &str) {
+            self.endings.insert(ending.to_string());
+        }
+    }
+
+    fn holds(source: &str, story: &Story) -> bool {
+        source
+            .parse::<Condition>()
+            .unwrap()
+            .evaluate(story)
+            .unwrap()
+    }
+
+    /// The column and message of the error parsing `source` as a condition.
+    fn parse_error(source: &str) -> (usize, String) {
+        let error = source.parse::<Condition>().unwrap_err();
+        (error.position + 1, error.message)
+    }
+
+    #[test]
+    fn precedence() {
+        let story = Story::default();
+        assert!(holds("true || false && false", &story));
+        assert!(!holds("(true || false) && false", &story));
+        assert!(holds("1 + 2 == 3 && 2 - 3 < 0", &story));
+        assert!(holds("10 - 2 - 3 == 5", &story));
+        assert!(holds("!false == true", &story));
+        assert!(holds("-2 + 5 == 3", &story));
+        assert!(holds("!(1 > 2) && 2 >= 2 && 1 <= 1 && 1 != 2", &story));
+    }
+
+    #[test]
+    fn story_state() {
+        let mut story = Story::default();
+        let condition: Condition = "visited(Z.a) && !flag(met_seekers)".parse().unwrap();
+        assert!(!condition.evaluate(&story).unwrap());
+        story.visited.insert("Z.a".to_string());
+        assert!(condition.evaluate(&story).unwrap());
+
+        let effects: Effects = "set(met_seekers); inc(trust, 2); inc(trust); \
+                                unlock_achievement(seeker); reach_ending(\"boat\")"
+            .parse()
+            .unwrap();
+        effects.apply(&mut story).unwrap();
+        assert!(!condition.evaluate(&story).unwrap());
+        assert!(holds(
+            "trust == 3 && unlocked(seeker) && seen_ending(boat)",
+            &story
+        ));
+
+        let effects: Effects = "clear(met_seekers); set(name, \"Ro\" ); set(count, trust + 1)"
+            .parse()
+            .unwrap();
+        effects.apply(&mut story).unwrap();
+        assert!(holds(
+            "!flag(met_seekers) && name == \"Ro\" && count == 4",
+            &story
+        ));
+    }
+
+    #[test]
+    fn unset_variables_read_as_defaults() {
+        let story = Story::default();
+        assert!(!holds("missing", &story));
+        assert!(holds("!missing", &story));
+        assert!(holds(
+            "missing == 0 && missing < 1 && missing + 2 == 2",
+            &story
+        ));
+        assert!(holds("missing == \"\"", &story));
+        assert!(holds("missing == false", &story));
+        assert!(holds("missing == other", &story));
+
+        let mut story = Story::default();
+        "inc(visits)"
+            .parse::<Effects>()
+            .unwrap()
+            .apply(&mut story)
+            .unwrap();
+        assert_eq!(story.variables["visits"], Value::Int(1));
+        let error = "set(copy, missing)"
+            .parse::<Effects>()
+            .unwrap()
+            .apply(&mut story)
+            .unwrap_err();
+        assert_eq!(error.message, "variable was never set");
+        assert_eq!(error.position, 10);
+    }
+
+    #[test]
+    fn type_errors() {
+        let mut story = Story::default();
+        story.set_variable("name", Value::Str("Ro".to_string()));
+        let error = |source: &str| {
+            let error = source
+                .parse::<Condition>()
+                .unwrap()
+                .evaluate(&story)
+                .unwrap_err();
+            (error.position + 1, error.message)
+        };
+        assert_eq!(
+            error("true && 1 + name > 0"),
+            (13, "expected a whole number, found a string".to_string())
+        );
+        assert_eq!(
+            error("flag(a) || 2"),
+            (
+                12,
+                "expected true or false, found a whole number".to_string()
+            )
+        );
+        assert_eq!(
+            error("name == 1"),
+            (1, "can't compare a string with a whole number".to_string())
+        );
+        assert_eq!(
+            error("9223372036854775807 + 1 > 0"),
+            (1, "number is too large".to_string())
+        );
+
+        let error = "inc(name)"
+            .parse::<Effects>()
+            .unwrap()
+            .apply(&mut story)
+            .unwrap_err();
+        assert_eq!(error.message, "name is a string, not a whole number");
+        assert_eq!(
+            error.to_string(),
+            format!("{} at column 1 of `inc(name)`", error.message)
+        );
+    }
+
+    #[test]
+    fn error_columns() {
+        assert_eq!(
+            parse_error("flag(a) && \"open"),
+            (12, "string is never closed".to_string())
+        );
+        assert_eq!(parse_error("trust = 2"), (7, "expected `==`".to_string()));
+        assert_eq!(parse_error("a & b"), (3, "expected `&&`".to_string()));
+        assert_eq!(
+            parse_error("a && seen(b)"),
+            (
+                6,
+                "unknown function `seen`; expected flag, visited, unlocked or seen_ending"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            parse_error("!set(a)"),
+            (
+                2,
+                "`set` is an effect and can't be used in a condition".to_string()
+            )
+        );
+        assert_eq!(
+            parse_error("flag(a) flag(b)"),
+            (
+                9,
+                "expected an operator or the end, found `flag`".to_string()
+            )
+        );
+        assert_eq!(
+            parse_error("(a || b"),
+            (8, "expected `)`, found the end".to_string())
+        );
+
+        let error = "set(a) inc(b)".parse::<Effects>().unwrap_err();
+        assert_eq!(error.position + 1, 8);
+        assert_eq!(error.message, "expected `;` or the end, found `inc`");
+        let error = "clear(a, 1)".parse::<Effects>().unwrap_err();
+        assert_eq!(error.position + 1, 10);
+        let error = "shout(a)".parse::<Effects>().unwrap_err();
+        assert_eq!(error.position + 1, 1);
+    }
+}
@@ -0,0 +1,103 @@
+//! Astraliminal's Fade plugin.
+//!
+//! A full-screen black overlay for respawns and transitions. Set a target on `ScreenFade` and the
+//! overlay fades towards it.
+
+use bevy::prelude::*;
+
+/// How dark the screen is and where it is fading to.
+#[derive(Resource, Reflect, Debug, Clone, Copy, PartialEq)]
+#[reflect(Resource)]
+pub struct ScreenFade {
+    /// Current opacity of the overlay, from 0 (clear) to 1 (black).
+    pub alpha: f32,
+    /// Opacity the overlay is fading to.
+    pub target: f32,
+    /// Change in opacity per second.
+    pub speed: f32,
+}
+
+impl Default for ScreenFade {
+    fn default() -> Self {
+        Self {
+            alpha: 0.0,
+            target: 0.0,
+            speed: 2.0,
+        }
+    }
+}
+
+impl ScreenFade {
+    /// Fade to `target` over `seconds`.
+    pub fn fade_to(&mut self, target: f32, seconds: f32) {
+        self.target = target.clamp(0.0, 1.0);
+        self.speed = (self.target - self.alpha).abs() / seconds.max(f32::EPSILON);
+    }
+
+    /// Go black at once and fade back in over `seconds`.
+    pub fn flash(&mut self, seconds: f32) {
+        self.alpha = 1.0;
+        self.fade_to(0.0, seconds);
+    }
+
+    /// Whether the overlay has reached its target.
+    pub fn is_done(&self) -> bool {
+        self.alpha == self.target
+    }
+}
+
+/// Marker for the overlay node.
+#[derive(Component, Debug, Default, Clone, Copy)]
+struct FadeOverlay;
+
+pub struct AstraliminalFadePlugin;
+
+impl Plugin for AstraliminalFadePlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<ScreenFade>()
+            .init_resource::<ScreenFade>()
+            .add_systems(Startup, spawn_overlay)
+            .add_systems(Update, update_fade);
+    }
+}
+
+/// Spawn the overlay on top of all other UI but the loading screen.
+fn spawn_overlay(mut commands: Commands) {
+    commands.spawn((
+        FadeOverlay,
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                ..default()
+            },
+            background_color: Color::NONE.into(),
+            z_index: ZIndex::Global(i32::MAX - 1),
+            ..default()
+        },
+    ));
+}
+
+/// Move the opacity towards the target and show it on the overlay.
+fn update_fade(
+    mut fade: ResMut<ScreenFade>,
+    mut overlay: Query<&mut BackgroundColor, With<FadeOverlay>>,
+    time: Res<Time>,
+) {
+    if !fade.is_done() {
+        let step = fade.speed * time.delta_seconds();
+        fade.alpha = if fade.target > fade.alpha {
+            (fade.alpha + step).min(fade.target)
+        } else {
+            (fade.alpha - step).max(fade.target)
+        };
+    }
+
+    for mut color in &mut overlay {
+        let color_alpha = color.0.a();
+        if color_alpha != fade.alpha {
+            color.0 = Color::BLACK.with_a(fade.alpha);
+        }
+    }
+}
@@ -0,0 +1,322 @@
+//! Astraliminal's Hot Reload plugin, built with the `dev` feature.
+//!
+//! The `dev` feature watches the asset folder, so level scenes, room graphs, logic graphs,
+//! dialogue scripts and the settings file reload as soon as they are saved. This plugin applies
+//! them in place: spawned scenes that changed are spawned again under the same entity, and the
+//! rooms of a changed room graph are spawned again around the current room. The player stays
+//! where they are, and physics is paused until the respawned scenes are ready. Bodies that end up
+//! inside a changed collider are left to the physics engine to push out.
+//!
+//! What was reloaded, and every asset that failed to load, e.g. a dialogue script with a syntax
+//! error, is listed in a corner of the screen for a while. A file that fails to reload keeps its
+//! last working version.
+
+use bevy::{
+    asset::{UntypedAssetId, UntypedAssetLoadFailedEvent},
+    prelude::*,
+    scene::{InstanceId, SceneInstance},
+};
+use bevy_xpbd_3d::prelude::*;
+
+use crate::{
+    dialogue::DialogueScript,
+    level::GameState,
+    logic::LogicGraph,
+    room_graph::{ActiveRooms, GraphRoom, LevelRooms, RoomGraph, SeamPortal},
+    settings::GameSettings,
+};
+
+/// Seconds a message stays on screen.
+const MESSAGE_SECONDS: f32 = 6.0;
+/// Messages shown at once.
+const MESSAGES_SHOWN: usize = 8;
+
+const RELOADED_COLOR: Color = Color::rgb(0.6, 1.0, 0.6);
+const ERROR_COLOR: Color = Color::rgb(1.0, 0.45, 0.4);
+
+/// Something that was reloaded or failed to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReloadMessage {
+    pub text: String,
+    pub error: bool,
+    /// Seconds until it disappears.
+    pub left: f32,
+}
+
+/// The messages on screen, newest last.
+#[derive(Resource, Debug, Default, Clone, PartialEq)]
+pub struct ReloadLog(pub Vec<ReloadMessage>);
+
+impl ReloadLog {
+    fn push(&mut self, text: String, error: bool) {
+        if error {
+            error!("{}", text);
+        } else {
+            info!("{}", text);
+        }
+        self.0.push(ReloadMessage {
+            text,
+            error,
+            left: MESSAGE_SECONDS,
+        });
+        if self.0.len() > MESSAGES_SHOWN {
+            self.0.remove(0);
+        }
+    }
+}
+
+/// Scenes or rooms being spawned again, while physics is paused.
+#[derive(Resource, Debug, Default, Clone)]
+struct Respawning {
+    /// Whether physics ran before.
+    resume: bool,
+    /// Whether the rooms of a changed room graph are among them.
+    rooms: bool,
+    /// Entities whose scene is spawned again, with the instance it replaces.
+    scenes: Vec<(Entity, Option<InstanceId>)>,
+}
+
+impl Respawning {
+    fn is_active(&self) -> bool {
+        self.rooms || !self.scenes.is_empty()
+    }
+
+    /// Pause physics, remembering whether it ran before this or an earlier respawn.
+    fn pause(&mut self, physics_time: Option<ResMut<Time<Physics>>>) {
+        if let Some(mut physics_time) = physics_time {
+            self.resume |= !physics_time.is_paused();
+            physics_time.pause();
+        }
+    }
+}
+
+/// Marker for the text listing the messages.
+#[derive(Component, Debug, Default, Clone, Copy)]
+struct ReloadText;
+
+pub struct AstraliminalHotReloadPlugin;
+
+impl Plugin for AstraliminalHotReloadPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ReloadLog>()
+            .init_resource::<Respawning>()
+            .add_systems(Startup, spawn_reload_ui)
+            .add_systems(
+                Update,
+                (
+                    (
+                        (
+                            respawn_scenes,
+                            respawn_rooms.run_if(resource_exists::<LevelRooms>),
+                            resume_physics
+                                .run_if(|respawning: Res<Respawning>| respawning.is_active()),
+                        )
+                            .chain(),
+                        report_reloads::<LogicGraph>,
+                        report_reloads::<DialogueScript>,
+                        report_reloads::<GameSettings>,
+                        report_failures,
+                    ),
+                    update_reload_ui,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// The asset path of an asset, for messages.
+fn asset_path(asset_server: &AssetServer, id: impl Into<UntypedAssetId>) -> String {
+    let id = id.into();
+    asset_server
+        .get_path(id)
+        .map_or_else(|| format!("{:?}", id), |path| path.to_string())
+}
+
+/// Spawn every scene that changed again under the same entity, which keeps its other components,
+/// pausing physics until they are back.
+fn respawn_scenes(
+    mut events: EventReader<AssetEvent<Scene>>,
+    mut log: ResMut<ReloadLog>,
+    mut respawning: ResMut<Respawning>,
+    mut physics_time: Option<ResMut<Time<Physics>>>,
+    mut scenes: Query<(Entity, &mut Handle<Scene>, Option<&SceneInstance>)>,
+    asset_server: Res<AssetServer>,
+) {
+    for event in events.read() {
+        let AssetEvent::Modified { id } = event else {
+            continue;
+        };
+        let mut count = 0;
+        for (entity, mut handle, instance) in &mut scenes {
+            if handle.id() == *id {
+                // Bevy respawns scenes whose handle changed.
+                handle.set_changed();
+                respawning
+                    .scenes
+                    .push((entity, instance.map(|instance| **instance)));
+                count += 1;
+            }
+        }
+        if count > 0 {
+            respawning.pause(physics_time.take());
+        }
+        if count > 0 {
+            log.push(
+                format!(
+                    "Reloaded {} ({} spawned)",
+                    asset_path(&asset_server, *id),
+                    count
+                ),
+                false,
+            );
+        }
+    }
+}
+
+/// Spawn the rooms and seams of a changed room graph again, pausing physics until they are back.
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+fn respawn_rooms(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<RoomGraph>>,
+    mut log: ResMut<ReloadLog>,
+    mut active: ResMut<ActiveRooms>,
+    mut respawning: ResMut<Respawning>,
+    physics_time: Option<ResMut<Time<Physics>>>,
+    level: Res<LevelRooms>,
+    asset_server: Res<AssetServer>,
+    spawned: Query<Entity, Or<(With<GraphRoom>, With<SeamPortal>)>>,
+) {
+    if !events.read().any(|event| event.is_modified(&level.0)) {
+        return;
+    }
+    for entity in &spawned {
+        commands.entity(entity).despawn_recursive();
+    }
+    // The room graph plugin spawns what is missing on its next tick.
+    active.0.clear();
+    respawning.rooms = true;
+    respawning.pause(physics_time);
+    log.push(
+        format!("Reloaded {}", asset_path(&asset_server, &level.0)),
+        false,
+    );
+}
+
+/// Resume physics once every respawned scene is ready and, with the rooms, every room is back.
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+fn resume_physics(
+    mut respawning: ResMut<Respawning>,
+    mut physics_time: Option<ResMut<Time<Physics>>>,
+    active: Res<ActiveRooms>,
+    level: Option<Res<LevelRooms>>,
+    state: Option<Res<State<GameState>>>,
+    scene_spawner: Res<SceneSpawner>,
+    rooms: Query<Option<&SceneInstance>, With<GraphRoom>>,
+    instances: Query<Option<&SceneInstance>>,
+) {
+    let ready = |instance: Option<&SceneInstance>| {
+        instance.is_some_and(|instance| scene_spawner.instance_is_ready(**instance))
+    };
+    // A level change unloads what was respawning and resumes physics itself.
+    let unloaded = (respawning.rooms && level.is_none())
+        || state.is_some_and(|state| *state.get() == GameState::Loading);
+    if !unloaded {
+        if respawning.rooms && (active.0.is_empty() || !rooms.iter().all(ready)) {
+            return;
+        }
+        // Bevy swaps the instance once it spawns the scene again. Despawned entities are done.
+        let spawned = respawning.scenes.iter().all(|(entity, old)| {
+            instances.get(*entity).map_or(true, |instance| {
+                instance.map(|instance| **instance) != *old && ready(instance)
+            })
+        });
+        if !spawned {
+            return;
+        }
+        if let (true, Some(physics_time)) = (respawning.resume, &mut physics_time) {
+            physics_time.unpause();
+        }
+    }
+    *respawning = Respawning::default();
+}
+
+/// Report data files that changed. Their plugins pick the changes up themselves.
+fn report_reloads<A: Asset>(
+    mut events: EventReader<AssetEvent<A>>,
+    mut log: ResMut<ReloadLog>,
+    asset_server: Res<AssetServer>,
+) {
+    for event in events.read() {
+        if let AssetEvent::Modified { id } = event {
+            log.push(
+                format!("Reloaded {}", asset_path(&asset_server, *id)),
+                false,
+            );
+        }
+    }
+}
+
+/// Report every asset that failed to load, e.g. because of a parse error.
+fn report_failures(
+    mut failures: EventReader<UntypedAssetLoadFailedEvent>,
+    mut log: ResMut<ReloadLog>,
+) {
+    for failure in failures.read() {
+        log.push(format!("{}: {}", failure.path, failure.error), true);
+    }
+}
+
+/// Spawn the message list in the top left corner.
+fn spawn_reload_ui(mut commands: Commands) {
+    commands.spawn((
+        ReloadText,
+        TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(8.0),
+                left: Val::Px(8.0),
+                max_width: Val::Percent(60.0),
+                ..default()
+            },
+            z_index: ZIndex::Global(i32::MAX),
+            ..default()
+        },
+    ));
+}
+
+/// Age the messages and show the ones left.
+fn update_reload_ui(
+    mut log: ResMut<ReloadLog>,
+    mut texts: Query<&mut Text, With<ReloadText>>,
+    time: Res<Time>,
+) {
+    if log.0.is_empty() {
+        return;
+    }
+    for message in &mut log.0 {
+        message.left -= time.delta_seconds();
+    }
+    log.0.retain(|message| message.left > 0.0);
+
+    let Ok(mut text) = texts.get_single_mut() else {
+        return;
+    };
+    text.sections = log
+        .0
+        .iter()
+        .map(|message| {
+            TextSection::new(
+                format!("{}\n", message.text),
+                TextStyle {
+                    font_size: 16.0,
+                    color: if message.error {
+                        ERROR_COLOR
+                    } else {
+                        RELOADED_COLOR
+                    },
+                    ..default()
+                },
+            )
+        })
+        .collect();
+}
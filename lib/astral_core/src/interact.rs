@@ -0,0 +1,157 @@
+//! Astraliminal's Interact plugin.
+//!
+//! Look at an `Interactable` and press the interact key to send `Interacted`. Like triggers,
+//! interactables can be set up from Blender with the `interactable`, `interact_prompt` and
+//! `interact_range` custom properties.
+
+use bevy::{gltf::GltfExtras, math::primitives::Direction3d, prelude::*};
+use bevy_xpbd_3d::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    player::{input_allowed, Player, PlayerCamera},
+    schedule::AstralSet,
+    trigger::read_extras,
+};
+
+/// Default distance from the camera at which an interactable can be used.
+const INTERACT_RANGE: f32 = 3.0;
+
+/// Something the player can use by looking at it and pressing the interact key. Needs a
+/// `Collider`.
+#[derive(Component, Reflect, Debug, Clone)]
+#[reflect(Component)]
+pub struct Interactable {
+    /// Id used by level data to refer to this interactable.
+    pub id: String,
+    /// Text shown to the player while looking at it, e.g. "Talk".
+    pub prompt: String,
+    /// Largest distance from the camera, in world units.
+    pub range: f32,
+}
+
+impl Default for Interactable {
+    fn default() -> Self {
+        Self {
+            id: String::new(),
+            prompt: String::new(),
+            range: INTERACT_RANGE,
+        }
+    }
+}
+
+/// The interactable the player is looking at and is close enough to use.
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct InteractionFocus(pub Option<Entity>);
+
+/// Sent when the player uses an interactable.
+#[derive(Event, Debug, Clone)]
+pub struct Interacted {
+    pub entity: Entity,
+    pub id: String,
+}
+
+/// Custom properties read from Blender objects through glTF extras.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct InteractExtras {
+    /// Id of the interactable. The object only becomes interactable if this is set.
+    interactable: Option<String>,
+    interact_prompt: Option<String>,
+    interact_range: Option<f32>,
+}
+
+pub struct AstraliminalInteractPlugin;
+
+impl Plugin for AstraliminalInteractPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Interactable>()
+            .init_resource::<InteractionFocus>()
+            .add_event::<Interacted>()
+            .add_systems(
+                FixedUpdate,
+                interactables_from_extras.in_set(AstralSet::Setup),
+            )
+            .add_systems(
+                Update,
+                (update_focus, interact.run_if(input_allowed)).chain(),
+            );
+    }
+}
+
+/// Turn Blender objects with interactable custom properties into interactables.
+fn interactables_from_extras(
+    mut commands: Commands,
+    extras: Query<(Entity, &GltfExtras), Added<GltfExtras>>,
+) {
+    for (entity, extras) in &extras {
+        let Ok(extras) = read_extras::<InteractExtras>(extras) else {
+            continue;
+        };
+        let Some(id) = extras.interactable else {
+            continue;
+        };
+
+        commands.entity(entity).insert(Interactable {
+            id,
+            prompt: extras.interact_prompt.unwrap_or_default(),
+            range: extras.interact_range.unwrap_or(INTERACT_RANGE),
+        });
+    }
+}
+
+/// Find the interactable in the middle of the screen.
+fn update_focus(
+    mut focus: ResMut<InteractionFocus>,
+    camera: Query<&GlobalTransform, With<PlayerCamera>>,
+    player: Query<Entity, With<Player>>,
+    interactables: Query<&Interactable>,
+    spatial_query: SpatialQuery,
+) {
+    let Ok(camera) = camera.get_single() else {
+        return;
+    };
+
+    let direction = Direction3d::new_unchecked(camera.forward());
+    let max_range = interactables
+        .iter()
+        .map(|interactable| interactable.range)
+        .fold(0.0, f32::max);
+    let filter = SpatialQueryFilter::default().with_excluded_entities(player.iter());
+
+    let focused = spatial_query
+        .cast_ray(camera.translation(), direction, max_range, true, filter)
+        .filter(|hit| {
+            interactables
+                .get(hit.entity)
+                .is_ok_and(|interactable| hit.time_of_impact <= interactable.range)
+        })
+        .map(|hit| hit.entity);
+
+    if focus.0 != focused {
+        focus.0 = focused;
+    }
+}
+
+/// Use the focused interactable when the interact key is pressed.
+pub(crate) fn interact(
+    keys: Res<ButtonInput<KeyCode>>,
+    focus: Res<InteractionFocus>,
+    interactables: Query<&Interactable>,
+    mut interacted: EventWriter<Interacted>,
+) {
+    // TODO: pull key code from config.
+    if !keys.just_pressed(KeyCode::KeyE) {
+        return;
+    }
+
+    let Some(entity) = focus.0 else {
+        return;
+    };
+    if let Ok(interactable) = interactables.get(entity) {
+        interacted.send(Interacted {
+            entity,
+            id: interactable.id.clone(),
+        });
+    }
+}
@@ -0,0 +1,744 @@
+//! Astraliminal's Level plugin.
+//!
+//! Send `ChangeLevel` to move to another level, e.g. from the boat to the island. The screen fades
+//! out, everything marked `LevelEntity` is despawned, everything the next level's `LevelManifest`
+//! lists loads in the background while `Transition` reports the progress, the player is put at
+//! the named spawn point and the screen fades back in. With `LoadingSettings::press_to_continue`,
+//! the player is only placed after `ContinueLevel`. If any of the level's assets fail to load,
+//! `LevelLoadFailed` says which and the level change stops there, until another `ChangeLevel`,
+//! e.g. the one in `CurrentLevel` to go back to the level the player was in.
+//!
+//! The assets of a level stay loaded until the player is in the next level, so what both levels
+//! use isn't loaded twice, and what the next level doesn't use is dropped.
+//!
+//! The game is in `GameState::Loading` from the moment the old level is unloaded until the player
+//! stands in the new one. Meanwhile no `AstralSet` runs, physics is paused and input is blocked,
+//! so no gameplay ever sees a half-loaded level.
+
+use bevy::{
+    asset::{
+        io::AssetReaderError, AssetLoadError, LoadState, RecursiveDependencyLoadState,
+        UntypedAssetId, UntypedAssetLoadFailedEvent,
+    },
+    prelude::*,
+    scene::SceneInstance,
+};
+use bevy_xpbd_3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bounds::RespawnPoint,
+    checkpoint::Checkpoints,
+    dialogue::LevelDialogue,
+    fade::ScreenFade,
+    logic::{LevelLogic, LogicState},
+    manifest::{AssetGroups, GameAssetGroups, LevelManifest},
+    player::{InputBlocked, Player},
+    rewind::RewindBuffer,
+    ron_loader::RonLoader,
+    room::CurrentRoom,
+    room_graph::{ActiveRooms, LevelRooms},
+    save::SaveData,
+    schedule::AstralSet,
+};
+
+/// Seconds the screen takes to fade out before a level change and back in after it.
+const FADE_SECONDS: f32 = 0.5;
+/// Why input is blocked during a level change, see `InputBlocked`.
+const BLOCK_REASON: &str = "level";
+
+/// Whether gameplay runs.
+#[derive(States, Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GameState {
+    #[default]
+    Playing,
+    /// Between unloading a level and placing the player in the next one.
+    Loading,
+}
+
+/// How level changes load.
+#[derive(Resource, Reflect, Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[reflect(Resource)]
+#[serde(default)]
+pub struct LoadingSettings {
+    /// Wait for `ContinueLevel` once the level has loaded, e.g. so the player can finish reading a
+    /// loading screen tip.
+    pub press_to_continue: bool,
+}
+
+/// Marker for entities that belong to the current level and are despawned when it changes.
+#[derive(Component, Reflect, Debug, Default, Clone, Copy)]
+#[reflect(Component)]
+pub struct LevelEntity;
+
+/// The level that was loaded last.
+#[derive(Resource, Debug, Default, Clone, PartialEq, Eq)]
+pub struct CurrentLevel {
+    /// Id of the level.
+    pub id: Option<String>,
+    /// The level change that loaded it. Send it again to reload the level.
+    pub change: Option<ChangeLevel>,
+}
+
+/// The assets of the current level, kept loaded until the next level replaces them.
+#[derive(Resource, Debug, Default)]
+pub struct LevelAssets(Vec<(String, UntypedHandle)>);
+
+impl LevelAssets {
+    /// Asset paths of the level's assets.
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(|(path, _)| path.as_str())
+    }
+}
+
+/// Send to move to another level.
+#[derive(Event, Debug, Clone, PartialEq, Eq)]
+pub struct ChangeLevel {
+    /// Asset path of the level's manifest, e.g. `levels/island.level.ron`.
+    pub level: String,
+    /// Name of the entity in the level the player starts at, or `None` to stay where they are.
+    pub spawn: Option<String>,
+}
+
+/// Send to enter a level that has loaded, with `LoadingSettings::press_to_continue`.
+#[derive(Event, Debug, Default, Clone, Copy)]
+pub struct ContinueLevel;
+
+/// Sent once the player stands in a new level, as it starts to fade in.
+#[derive(Event, Debug, Clone, PartialEq, Eq)]
+pub struct LevelLoaded {
+    pub id: String,
+}
+
+/// Sent when some of a level's assets failed to load. The game stays in `GameState::Loading`
+/// until another `ChangeLevel`.
+#[derive(Event, Debug, Clone, PartialEq, Eq)]
+pub struct LevelLoadFailed {
+    pub id: String,
+    pub assets: Vec<AssetFailure>,
+}
+
+/// An asset of a level that couldn't be loaded, or one of its dependencies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssetFailure {
+    pub path: String,
+    /// Whether the file doesn't exist, rather than being broken.
+    pub missing: bool,
+    /// What went wrong.
+    pub error: String,
+}
+
+/// How far a level change has come.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TransitionPhase {
+    #[default]
+    Idle,
+    FadingOut,
+    Loading,
+    /// Loaded, waiting for `ContinueLevel`.
+    Ready,
+    /// Waiting for the level's scene to spawn, then placing the player.
+    Placing,
+    FadingIn,
+}
+
+/// The level change in progress, if any.
+#[derive(Resource, Debug, Default)]
+pub struct Transition {
+    phase: TransitionPhase,
+    request: Option<ChangeLevel>,
+    /// The manifest, once it has loaded.
+    level: Option<LevelManifest>,
+    /// Paths and handles of the assets being loaded.
+    assets: Vec<(String, UntypedHandle)>,
+    scene: Option<Entity>,
+    progress: f32,
+    /// Every asset that failed to load so far, and the ones that stop the level from loading.
+    errors: Vec<AssetFailure>,
+    failed: Vec<AssetFailure>,
+}
+
+impl Transition {
+    pub fn phase(&self) -> TransitionPhase {
+        self.phase
+    }
+
+    /// Whether a level change is in progress.
+    pub fn is_active(&self) -> bool {
+        self.phase != TransitionPhase::Idle
+    }
+
+    pub fn request(&self) -> Option<&ChangeLevel> {
+        self.request.as_ref()
+    }
+
+    /// The manifest of the level being moved to, once it has loaded.
+    pub fn level(&self) -> Option<&LevelManifest> {
+        self.level.as_ref()
+    }
+
+    /// Id of the level being moved to, or the path of its manifest until it has loaded.
+    pub fn level_name(&self) -> &str {
+        match (&self.level, &self.request) {
+            (Some(level), _) => &level.id,
+            (None, Some(request)) => &request.level,
+            (None, None) => "",
+        }
+    }
+
+    /// Record that the level can't load.
+    fn fail(&mut self, failures: &mut EventWriter<LevelLoadFailed>, assets: Vec<AssetFailure>) {
+        let id = self.level_name().to_string();
+        let paths: Vec<_> = assets.iter().map(|failure| failure.path.as_str()).collect();
+        error!("Level {} failed to load: {}", id, paths.join(", "));
+        self.failed = assets.clone();
+        failures.send(LevelLoadFailed { id, assets });
+    }
+
+    /// How much of the level has loaded, from 0 to 1.
+    pub fn progress(&self) -> f32 {
+        self.progress
+    }
+
+    /// The assets that failed to load. The level change is stuck while there are any.
+    pub fn failed(&self) -> &[AssetFailure] {
+        &self.failed
+    }
+
+    /// A level change to `level` in `phase`, for tests of what follows it.
+    #[cfg(test)]
+    pub(crate) fn at(phase: TransitionPhase, level: &str, failed: Vec<AssetFailure>) -> Self {
+        Self {
+            phase,
+            request: Some(ChangeLevel {
+                level: level.to_string(),
+                spawn: None,
+            }),
+            failed,
+            ..default()
+        }
+    }
+}
+
+pub struct AstraliminalLevelPlugin;
+
+impl Plugin for AstraliminalLevelPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<LevelManifest>()
+            .init_asset::<AssetGroups>()
+            .register_asset_loader(RonLoader::<LevelManifest>::new(&["level.ron"]))
+            .register_asset_loader(RonLoader::<AssetGroups>::new(&["groups.ron"]))
+            .register_type::<LevelEntity>()
+            .register_type::<LoadingSettings>()
+            .init_state::<GameState>()
+            .init_resource::<Transition>()
+            .init_resource::<CurrentLevel>()
+            .init_resource::<LevelAssets>()
+            .init_resource::<LoadingSettings>()
+            .add_event::<ChangeLevel>()
+            .add_event::<ContinueLevel>()
+            .add_event::<LevelLoaded>()
+            .add_event::<LevelLoadFailed>()
+            .configure_sets(
+                FixedUpdate,
+                (
+                    AstralSet::Setup,
+                    AstralSet::Player,
+                    AstralSet::Sense,
+                    AstralSet::React,
+                    AstralSet::Move,
+                    AstralSet::Resolve,
+                )
+                    .run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(
+                Update,
+                (
+                    start_transition,
+                    unload_level.run_if(faded_out),
+                    (load_level_assets, track_loading).run_if(in_phase(TransitionPhase::Loading)),
+                    continue_level.run_if(in_phase(TransitionPhase::Ready)),
+                    place_player.run_if(in_phase(TransitionPhase::Placing)),
+                    finish_transition.run_if(in_phase(TransitionPhase::FadingIn)),
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Run condition for a phase of the level change.
+fn in_phase(phase: TransitionPhase) -> impl Fn(Res<Transition>) -> bool + Clone {
+    move |transition: Res<Transition>| transition.phase == phase
+}
+
+/// Run condition for once the screen is black before a level change.
+fn faded_out(transition: Res<Transition>, fade: Res<ScreenFade>) -> bool {
+    transition.phase == TransitionPhase::FadingOut && fade.is_done()
+}
+
+/// Start fading out for a level change. A change can replace one that failed to load.
+fn start_transition(
+    mut requests: EventReader<ChangeLevel>,
+    mut transition: ResMut<Transition>,
+    mut fade: ResMut<ScreenFade>,
+    mut blocked: ResMut<InputBlocked>,
+) {
+    let Some(request) = requests.read().last() else {
+        return;
+    };
+    if transition.is_active() && transition.failed.is_empty() {
+        warn!("Already changing level, not changing to {}", request.level);
+        return;
+    }
+
+    info!("Changing level to {}", request.level);
+    *transition = Transition {
+        phase: TransitionPhase::FadingOut,
+        request: Some(request.clone()),
+        ..default()
+    };
+    fade.fade_to(1.0, FADE_SECONDS);
+    blocked.0.insert(BLOCK_REASON.to_string());
+}
+
+/// Despawn the current level, stop gameplay and start loading the next level.
+fn unload_level(world: &mut World) {
+    let entities: Vec<Entity> = world
+        .query_filtered::<Entity, With<LevelEntity>>()
+        .iter(world)
+        .collect();
+    for entity in entities {
+        // Level entities nested in others are already gone.
+        if let Some(entity) = world.get_entity_mut(entity) {
+            entity.despawn_recursive();
+        }
+    }
+
+    world.remove_resource::<LevelRooms>();
+    world.remove_resource::<LevelLogic>();
+    world.remove_resource::<LevelDialogue>();
+    reset::<CurrentRoom>(world);
+    reset::<ActiveRooms>(world);
+    reset::<LogicState>(world);
+    // Both refer to entities of the old level.
+    reset::<Checkpoints>(world);
+    reset::<RewindBuffer>(world);
+    if let Some(mut save) = world.get_resource_mut::<SaveData>() {
+        save.checkpoint = None;
+    }
+
+    world
+        .resource_mut::<NextState<GameState>>()
+        .set(GameState::Loading);
+    if let Some(mut physics_time) = world.get_resource_mut::<Time<Physics>>() {
+        physics_time.pause();
+    }
+
+    let asset_server = world.resource::<AssetServer>().clone();
+    let mut transition = world.resource_mut::<Transition>();
+    let Some(request) = transition.request.clone() else {
+        return;
+    };
+    let manifest = asset_server.load::<LevelManifest>(&request.level);
+    transition.phase = TransitionPhase::Loading;
+    transition.assets = vec![(request.level, manifest.untyped())];
+}
+
+/// Start loading everything in the level's manifest once the manifest has loaded.
+fn load_level_assets(
+    mut commands: Commands,
+    mut transition: ResMut<Transition>,
+    mut failures: EventWriter<LevelLoadFailed>,
+    asset_server: Res<AssetServer>,
+    manifests: Res<Assets<LevelManifest>>,
+    groups: Option<Res<GameAssetGroups>>,
+    group_assets: Res<Assets<AssetGroups>>,
+) {
+    if transition.level.is_some() || !transition.failed.is_empty() {
+        return;
+    }
+    let Some(level) = transition
+        .assets
+        .first()
+        .and_then(|(_, handle)| manifests.get(handle.id().typed::<LevelManifest>()))
+        .cloned()
+    else {
+        return;
+    };
+
+    let empty = AssetGroups::default();
+    let groups = match &groups {
+        Some(groups) if !level.groups.is_empty() => match group_assets.get(&groups.0) {
+            Some(groups) => groups,
+            None if asset_server.load_state(&groups.0) == LoadState::Failed => &empty,
+            None => return,
+        },
+        _ => &empty,
+    };
+    let unknown = level.unknown_groups(groups);
+    if !unknown.is_empty() {
+        let assets = unknown
+            .into_iter()
+            .map(|group| AssetFailure {
+                path: group.to_string(),
+                missing: true,
+                error: "unknown asset group".to_string(),
+            })
+            .collect();
+        transition.fail(&mut failures, assets);
+        return;
+    }
+
+    for path in level.asset_paths(groups) {
+        let handle = if level.scene.as_ref() == Some(&path) {
+            let handle: Handle<Scene> = asset_server.load(&path);
+            transition.scene = Some(
+                commands
+                    .spawn((
+                        SceneBundle {
+                            scene: handle.clone(),
+                            ..default()
+                        },
+                        LevelEntity,
+                        Name::new(level.id.clone()),
+                    ))
+                    .id(),
+            );
+            handle.untyped()
+        } else if level.rooms.as_ref() == Some(&path) {
+            let handle = asset_server.load(&path);
+            commands.insert_resource(LevelRooms(handle.clone()));
+            handle.untyped()
+        } else if level.logic.as_ref() == Some(&path) {
+            let handle = asset_server.load(&path);
+            commands.insert_resource(LevelLogic(handle.clone()));
+            handle.untyped()
+        } else if level.dialogue.as_ref() == Some(&path) {
+            let handle = asset_server.load(&path);
+            commands.insert_resource(LevelDialogue(handle.clone()));
+            handle.untyped()
+        } else {
+            asset_server.load_untyped(&path).untyped()
+        };
+        transition.assets.push((path, handle));
+    }
+    transition.level = Some(level);
+}
+
+/// Put a resource back to its default, if it exists.
+fn reset<R: Resource + Default>(world: &mut World) {
+    if let Some(mut resource) = world.get_resource_mut::<R>() {
+        *resource = R::default();
+    }
+}
+
+/// How much of an asset and its dependencies has loaded, or `None` if it failed.
+fn load_progress(asset_server: &AssetServer, id: UntypedAssetId) -> Option<f32> {
+    match (
+        asset_server.load_state(id),
+        asset_server.recursive_dependency_load_state(id),
+    ) {
+        (LoadState::Failed, _) | (_, RecursiveDependencyLoadState::Failed) => None,
+        (_, RecursiveDependencyLoadState::Loaded) => Some(1.0),
+        (LoadState::Loaded, _) => Some(0.5),
+        _ => Some(0.0),
+    }
+}
+
+impl AssetFailure {
+    fn new(event: &UntypedAssetLoadFailedEvent) -> Self {
+        Self {
+            path: event.path.to_string(),
+            missing: matches!(
+                event.error,
+                AssetLoadError::AssetReaderError(AssetReaderError::NotFound(_))
+            ),
+            error: event.error.to_string(),
+        }
+    }
+}
+
+/// Update the loading progress, and move on once the level's scene has spawned.
+fn track_loading(
+    mut transition: ResMut<Transition>,
+    mut asset_failures: EventReader<UntypedAssetLoadFailedEvent>,
+    mut failures: EventWriter<LevelLoadFailed>,
+    asset_server: Res<AssetServer>,
+    scene_spawner: Res<SceneSpawner>,
+    settings: Res<LoadingSettings>,
+    instances: Query<&SceneInstance>,
+) {
+    // Dependencies, e.g. a glTF's textures, report why they failed only through events.
+    for event in asset_failures.read() {
+        let failure = AssetFailure::new(event);
+        if !transition.errors.contains(&failure) {
+            transition.errors.push(failure);
+        }
+    }
+
+    let mut loaded = 0.0;
+    let mut failed = Vec::new();
+    for (path, handle) in &transition.assets {
+        match load_progress(&asset_server, handle.id()) {
+            Some(progress) => loaded += progress,
+            None => failed.push(path.clone()),
+        }
+    }
+
+    if !transition.failed.is_empty() {
+        return;
+    }
+    if !failed.is_empty() {
+        let mut assets = transition.errors.clone();
+        if assets.is_empty() {
+            assets = failed
+                .into_iter()
+                .map(|path| AssetFailure {
+                    path,
+                    missing: false,
+                    error: "failed to load".to_string(),
+                })
+                .collect();
+        }
+        transition.fail(&mut failures, assets);
+        return;
+    }
+    // Until the manifest has loaded, there is no telling how much is left.
+    if transition.level.is_none() {
+        return;
+    }
+
+    transition.progress = loaded / transition.assets.len() as f32;
+    let spawned = transition.scene.is_none_or(|scene| {
+        instances
+            .get(scene)
+            .is_ok_and(|instance| scene_spawner.instance_is_ready(**instance))
+    });
+    if transition.progress >= 1.0 && spawned {
+        transition.phase = if settings.press_to_continue {
+            TransitionPhase::Ready
+        } else {
+            TransitionPhase::Placing
+        };
+    }
+}
+
+/// Enter the loaded level once the player continues.
+fn continue_level(mut continues: EventReader<ContinueLevel>, mut transition: ResMut<Transition>) {
+    if continues.read().last().is_some() {
+        transition.phase = TransitionPhase::Placing;
+    }
+}
+
+/// Put the player at the spawn point, start gameplay and fade in.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn place_player(
+    mut transition: ResMut<Transition>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut respawn: ResMut<RespawnPoint>,
+    mut fade: ResMut<ScreenFade>,
+    mut current: ResMut<CurrentLevel>,
+    mut level_assets: ResMut<LevelAssets>,
+    mut loaded: EventWriter<LevelLoaded>,
+    mut physics_time: Option<ResMut<Time<Physics>>>,
+    spawns: Query<(&Name, &GlobalTransform), Without<Player>>,
+    mut players: Query<
+        (
+            &mut Transform,
+            Option<&mut LinearVelocity>,
+            Option<&mut AngularVelocity>,
+        ),
+        With<Player>,
+    >,
+) {
+    let (Some(request), Some(id)) = (
+        transition.request.clone(),
+        transition.level.as_ref().map(|level| level.id.clone()),
+    ) else {
+        return;
+    };
+
+    if let Ok((mut transform, linear, angular)) = players.get_single_mut() {
+        if let Some(spawn) = &request.spawn {
+            match spawns.iter().find(|(name, _)| name.as_str() == spawn) {
+                Some((_, spawn)) => *transform = spawn.compute_transform(),
+                None => warn!(
+                    "Level {} has no spawn point {}, leaving the player where they are",
+                    id, spawn
+                ),
+            }
+        }
+        if let Some(mut linear) = linear {
+            linear.0 = Vec3::ZERO;
+        }
+        if let Some(mut angular) = angular {
+            angular.0 = Vec3::ZERO;
+        }
+        respawn.0 = Some(*transform);
+    }
+
+    next_state.set(GameState::Playing);
+    if let Some(physics_time) = &mut physics_time {
+        physics_time.unpause();
+    }
+    fade.fade_to(0.0, FADE_SECONDS);
+
+    // Dropping the previous level's handles unloads what the new level doesn't use.
+    let previous = std::mem::replace(&mut level_assets.0, std::mem::take(&mut transition.assets));
+    let unused = previous
+        .iter()
+        .filter(|(path, _)| !level_assets.paths().any(|kept| kept == path))
+        .count();
+    if unused > 0 {
+        info!("Unloading {} assets the new level doesn't use", unused);
+    }
+
+    *current = CurrentLevel {
+        id: Some(id.clone()),
+        change: Some(request),
+    };
+    transition.phase = TransitionPhase::FadingIn;
+    info!("Level {} loaded", id);
+    loaded.send(LevelLoaded { id });
+}
+
+/// Give input back once the screen has faded in.
+fn finish_transition(
+    mut transition: ResMut<Transition>,
+    mut blocked: ResMut<InputBlocked>,
+    fade: Res<ScreenFade>,
+) {
+    if fade.is_done() {
+        *transition = Transition::default();
+        blocked.0.remove(BLOCK_REASON);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::Duration};
+
+    use bevy::time::TimeUpdateStrategy;
+
+    use super::*;
+    use crate::fade::AstraliminalFadePlugin;
+
+    /// Phases of the level change each gameplay tick ran in.
+    #[derive(Resource, Default)]
+    struct Ticks(Vec<TransitionPhase>);
+
+    fn tick(mut ticks: ResMut<Ticks>, transition: Res<Transition>) {
+        ticks.0.push(transition.phase());
+    }
+
+    /// A headless app stepping 0.1 seconds a frame, loading assets from a temporary directory with
+    /// a `test.level.ron` manifest.
+    fn app(test: &str) -> App {
+        let assets = std::env::temp_dir().join(format!("astral_{}_{}", test, std::process::id()));
+        fs::create_dir_all(&assets).unwrap();
+        fs::write(assets.join("test.level.ron"), "(id: \"test\")").unwrap();
+
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            AssetPlugin {
+                file_path: assets.to_string_lossy().into_owned(),
+                ..default()
+            },
+        ))
+        .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_millis(
+            100,
+        )))
+        .init_resource::<SceneSpawner>()
+        .init_resource::<InputBlocked>()
+        .init_resource::<RespawnPoint>()
+        .init_resource::<SaveData>()
+        .init_resource::<Ticks>()
+        .add_plugins((AstraliminalFadePlugin, AstraliminalLevelPlugin))
+        .add_systems(FixedUpdate, tick.in_set(AstralSet::React));
+        app.update();
+        app
+    }
+
+    fn change(level: &str) -> ChangeLevel {
+        ChangeLevel {
+            level: level.to_string(),
+            spawn: None,
+        }
+    }
+
+    /// Update until `done`, waiting for assets to load in the background.
+    fn update_until(app: &mut App, done: impl Fn(&World) -> bool) {
+        for _ in 0..500 {
+            app.update();
+            if done(&app.world) {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        panic!(
+            "timed out in {:?}",
+            app.world.resource::<Transition>().phase()
+        );
+    }
+
+    fn is_loaded(world: &World) -> bool {
+        world.resource::<CurrentLevel>().id.is_some()
+    }
+
+    #[test]
+    fn no_gameplay_while_loading() {
+        let mut app = app("loading");
+        app.world.send_event(change("test.level.ron"));
+        update_until(&mut app, is_loaded);
+        update_until(&mut app, |world| {
+            !world.resource::<Transition>().is_active()
+        });
+
+        let ticks = &app.world.resource::<Ticks>().0;
+        assert!(ticks.contains(&TransitionPhase::FadingOut));
+        assert!(ticks.contains(&TransitionPhase::FadingIn));
+        assert!(!ticks.iter().any(|phase| matches!(
+            phase,
+            TransitionPhase::Loading | TransitionPhase::Ready | TransitionPhase::Placing
+        )));
+        assert!(!app.world.resource::<InputBlocked>().is_blocked());
+    }
+
+    #[test]
+    fn going_back_after_a_failed_load() {
+        let mut app = app("going_back");
+        app.world.send_event(change("test.level.ron"));
+        update_until(&mut app, |world| {
+            !world.resource::<Transition>().is_active()
+        });
+
+        app.world.send_event(change("missing.level.ron"));
+        update_until(&mut app, |world| {
+            !world.resource::<Transition>().failed().is_empty()
+        });
+        let failed = app.world.resource::<Transition>().failed();
+        assert_eq!(failed[0].path, "missing.level.ron");
+        assert!(failed[0].missing);
+        app.world.resource_mut::<Ticks>().0.clear();
+        for _ in 0..5 {
+            app.update();
+        }
+        assert!(app.world.resource::<Ticks>().0.is_empty());
+
+        let back = app.world.resource::<CurrentLevel>().change.clone().unwrap();
+        app.world.send_event(back);
+        update_until(&mut app, |world| {
+            !world.resource::<Transition>().is_active()
+        });
+        assert_eq!(
+            app.world.resource::<CurrentLevel>().id.as_deref(),
+            Some("test")
+        );
+        assert_eq!(
+            *app.world.resource::<State<GameState>>().get(),
+            GameState::Playing
+        );
+        assert!(!app.world.resource::<Ticks>().0.is_empty());
+    }
+}
@@ -1,19 +1,236 @@
 //! Astraliminal library.
 
+mod boat;
+mod bounds;
+mod checkpoint;
+mod dialogue;
+mod dialogue_ui;
+mod dimension;
+mod duplicate;
+mod ending;
+mod expression;
+mod fade;
+#[cfg(feature = "dev")]
+mod hot_reload;
+mod interact;
+mod level;
+mod loading_screen;
+mod logic;
+mod manifest;
+mod mover;
+mod new_game;
+mod npc;
+mod painting;
+mod plate;
+mod player;
+mod portal;
+mod replay;
+mod rewind;
+mod ron_loader;
+mod room;
+mod room_graph;
+mod save;
+mod schedule;
+mod settings;
+mod story;
+mod timeline;
+mod trigger;
+mod viewpoint;
+mod water;
 mod window;
 
 pub mod prelude {
     use super::*;
     pub use bevy::prelude::*;
-    pub use window::AstraliminalWindowPlugin;
+    pub use boat::{AstraliminalBoatPlugin, BoatArrived, Deck, Rowing, RowingState};
+    pub use bounds::{
+        AstraliminalBoundsPlugin, Critical, KillVolume, LostReason, ObjectLost, RespawnPoint,
+        SpawnPoint, WorldBounds,
+    };
+    pub use checkpoint::{
+        AstraliminalCheckpointPlugin, BodySnapshot, Checkpoint, CheckpointReached,
+        CheckpointRestored, Checkpoints, RestartFromCheckpoint, Snapshot,
+    };
+    pub use dialogue::{
+        AdvanceDialogue, AstraliminalDialoguePlugin, ChooseDialogue, Conversation, Dialogue,
+        DialogueBranch, DialogueChoice, DialogueChoices, DialogueEnded, DialogueLine, DialogueNode,
+        DialogueScript, DialogueSpoken, DialogueStarted, LevelDialogue, StartDialogue,
+    };
+    pub use dialogue_ui::{
+        AstraliminalDialogueUiPlugin, DialogueReply, DialogueView, HistoryEntry,
+    };
+    pub use dimension::{
+        ActiveDimension, AstraliminalDimensionPlugin, DimensionArtifact, DimensionLayer,
+        DimensionShifted, ShiftDimension,
+    };
+    pub use duplicate::{
+        AstraliminalDuplicatePlugin, CloneBudget, CloneOf, Duplicable, Duplicated,
+    };
+    pub use ending::{
+        AstraliminalEndingPlugin, Credits, CreditsFinished, Ending, EndingCompletion,
+        EndingReached, Endings, EndingsSeen, GameEndings, ReachEnding, SkipCredits,
+    };
+    pub use expression::{
+        Condition, Effect, Effects, Expr, ExpressionError, Scope, ScopeMut, Value,
+    };
+    pub use fade::{AstraliminalFadePlugin, ScreenFade};
+    #[cfg(feature = "dev")]
+    pub use hot_reload::{AstraliminalHotReloadPlugin, ReloadLog, ReloadMessage};
+    pub use interact::{AstraliminalInteractPlugin, Interactable, Interacted, InteractionFocus};
+    pub use level::{
+        AssetFailure, AstraliminalLevelPlugin, ChangeLevel, ContinueLevel, CurrentLevel, GameState,
+        LevelAssets, LevelEntity, LevelLoadFailed, LevelLoaded, LoadingSettings, Transition,
+        TransitionPhase,
+    };
+    pub use loading_screen::{
+        AstraliminalLoadingScreenPlugin, GameTips, LoadingScreen, LoadingTips,
+    };
+    pub use logic::{
+        AstraliminalLogicPlugin, LevelLogic, LogicError, LogicGraph, LogicKind, LogicNode,
+        LogicOrder, LogicSignal, LogicSpawner, LogicState,
+    };
+    pub use manifest::{AssetGroups, GameAssetGroups, LevelManifest, ManifestIssue};
+    pub use mover::{
+        AstraliminalMoverPlugin, MoveMover, Mover, MoverBlocked, MoverMode, MoverPath, MoverState,
+        OnBlocked,
+    };
+    pub use new_game::{
+        fresh_save, AstraliminalNewGamePlugin, BranchStart, CarryOver, NewGameStarted, StartNewGame,
+    };
+    pub use npc::{AstraliminalNpcPlugin, Npc, NpcMemories};
+    pub use painting::{AstraliminalPaintingPlugin, Materialized, Painting, PaintingMaterialized};
+    pub use plate::{
+        AstraliminalPlatePlugin, Balance, BalanceScale, BalanceState, BalanceTipped, PlateMeasure,
+        PlatePressed, PlateReleased, PlateState, PressurePlate,
+    };
+    pub use player::{
+        input_allowed, AstraliminalPlayerPlugin, Carriable, DropHeld, Grab, Held, InputBlocked,
+        Player, PlayerCamera,
+    };
+    pub use portal::{
+        crossed, portal_affine, portal_camera_transform, signed_distance, straddles,
+        teleport_angular_velocity, teleport_transform, teleport_velocity, AstraliminalPortalPlugin,
+        Portal, PortalCamera, PortalCrossed, PortalGhost, PortalTraveller,
+    };
+    pub use replay::{
+        run_replay, world_hash, AstraliminalReplayPlugin, BuildInfo, RecordedInput, Replay,
+        ReplayError, ReplayFinished, ReplayFrame, ReplayMode, ReplaySet, RngSeed,
+    };
+    pub use rewind::{
+        AstraliminalRewindPlugin, RewindBuffer, RewindEntry, RewindFrame, RewindSettings,
+        Rewindable,
+    };
+    pub use ron_loader::{RonLoader, RonLoaderError};
+    pub use room::{AstraliminalRoomPlugin, CurrentRoom, Room, RoomChanged};
+    pub use room_graph::{
+        ActiveRooms, AstraliminalRoomGraphPlugin, GraphRoom, InRoom, LevelRooms, RoomDef,
+        RoomGraph, RoomGraphSettings, SeamDef, SeamEnd, SeamIssue, SeamPortal,
+    };
+    pub use save::{
+        AstraliminalSavePlugin, SaveData, SaveError, SaveGame, SaveLoaded, SaveSet,
+        SavedCheckpoint, SavedClone, SavedNpc,
+    };
+    pub use schedule::{AstralSet, AstraliminalSchedulePlugin, Interpolated, TickRate};
+    pub use settings::{AstraliminalSettingsPlugin, GameSettings, GameSettingsFile};
+    pub use story::{
+        AchievementUnlocked, Achievements, AstraliminalStoryPlugin, Blackboard, Story, StoryFlags,
+    };
+    pub use timeline::{
+        AstraliminalTimelinePlugin, PlayTimeline, Sequencer, SkipTimeline, SpokenLine, Timeline,
+        TimelineAction, TimelineCamera, TimelineClip, TimelineFinished, TimelineTrack,
+    };
+    pub use trigger::{
+        AstraliminalTriggerPlugin, Tags, TriggerEntered, TriggerExited, TriggerFilter,
+        TriggerOccupants, TriggerStayed, TriggerStory, TriggerVolume,
+    };
+    pub use viewpoint::{AstraliminalViewpointPlugin, Viewpoint, ViewpointAligned};
+    pub use water::{AstraliminalWaterPlugin, Buoyant, Water, Wave};
+    pub use window::{AstraliminalHeadlessPlugin, AstraliminalWindowPlugin};
 }
 
+use bevy_xpbd_3d::prelude::PhysicsPlugins;
 use prelude::*;
 
+/// Everything the game needs, in a window.
 pub struct AstraliminalPlugins;
 
 impl Plugin for AstraliminalPlugins {
     fn build(&self, app: &mut App) {
-        app.add_plugins((AstraliminalWindowPlugin,));
+        app.add_plugins((AstraliminalWindowPlugin, AstraliminalGameplayPlugins));
+    }
+}
+
+/// Everything the game needs, without a window. See `AstraliminalHeadlessPlugin`.
+pub struct AstraliminalHeadlessPlugins;
+
+impl Plugin for AstraliminalHeadlessPlugins {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((AstraliminalHeadlessPlugin, AstraliminalGameplayPlugins));
+    }
+}
+
+/// The game itself, on top of Bevy's default plugins.
+///
+/// Gameplay runs in `FixedUpdate` in the order of `AstralSet`, followed by physics in
+/// `FixedPostUpdate`:
+///
+/// - `Setup`: level data from glTF extras, saves, logic graphs and room graphs.
+/// - `Player`: portals and moving between rooms.
+/// - `Sense`: triggers, the current room, plates and balance scales, viewpoints.
+/// - `React`: the logic graph, dimensions, paintings, duplicates, checkpoints.
+/// - `Move`: movers, floating and rowing boats, and what rides on decks.
+/// - `Resolve`: lost objects and active rooms, then rewind.
+///
+/// The sets only run in `GameState::Playing`, not while a level change loads the next level.
+///
+/// Input, focus, screen fades and level changes run in `Update`, portal cameras and interpolation
+/// in `PostUpdate`, and saving and replays in `Last`.
+///
+/// With the `dev` feature, changed assets are reloaded while the game runs.
+struct AstraliminalGameplayPlugins;
+
+impl Plugin for AstraliminalGameplayPlugins {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((
+            PhysicsPlugins::new(FixedPostUpdate),
+            AstraliminalSchedulePlugin,
+            AstraliminalReplayPlugin,
+            AstraliminalSavePlugin,
+            AstraliminalPlayerPlugin,
+            AstraliminalViewpointPlugin,
+            AstraliminalPaintingPlugin,
+            AstraliminalRoomPlugin,
+            AstraliminalDuplicatePlugin,
+            AstraliminalPortalPlugin,
+            AstraliminalRoomGraphPlugin,
+            AstraliminalStoryPlugin,
+        ))
+        .add_plugins((
+            AstraliminalDimensionPlugin,
+            AstraliminalTriggerPlugin,
+            AstraliminalInteractPlugin,
+            AstraliminalPlatePlugin,
+            AstraliminalLogicPlugin,
+            AstraliminalMoverPlugin,
+            AstraliminalFadePlugin,
+            AstraliminalBoundsPlugin,
+            AstraliminalCheckpointPlugin,
+            AstraliminalRewindPlugin,
+            AstraliminalTimelinePlugin,
+            AstraliminalWaterPlugin,
+            AstraliminalBoatPlugin,
+        ))
+        .add_plugins((
+            AstraliminalDialoguePlugin,
+            AstraliminalDialogueUiPlugin,
+            AstraliminalNpcPlugin,
+            AstraliminalEndingPlugin,
+            AstraliminalNewGamePlugin,
+            AstraliminalLevelPlugin,
+            AstraliminalLoadingScreenPlugin,
+            AstraliminalSettingsPlugin,
+        ));
+        #[cfg(feature = "dev")]
+        app.add_plugins(AstraliminalHotReloadPlugin);
     }
 }
@@ -0,0 +1,402 @@
+//! Astraliminal's Loading Screen plugin.
+//!
+//! Covers the screen while a level change loads the next level: the level's name, a bar showing
+//! how much of it has loaded and a tip from the game's `LoadingTips`, changing every few seconds.
+//! With `LoadingSettings::press_to_continue`, it waits for the player once the level has loaded.
+//! If some of the level's assets fail to load, it lists which are missing and which are broken
+//! instead, and the player can go back to the level they were in, if any, or quit.
+//!
+//! All of it is kept in `LoadingScreen`, which only follows the `Transition` and time, so it can be
+//! checked without rendering anything.
+
+use bevy::{app::AppExit, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    level::{AssetFailure, ChangeLevel, ContinueLevel, CurrentLevel, Transition, TransitionPhase},
+    ron_loader::RonLoader,
+};
+
+/// Seconds each tip is shown.
+const TIP_SECONDS: f32 = 8.0;
+
+const BACKGROUND_COLOR: Color = Color::rgb(0.02, 0.02, 0.05);
+const BAR_COLOR: Color = Color::rgba(1.0, 1.0, 1.0, 0.15);
+const FILL_COLOR: Color = Color::rgb(1.0, 0.85, 0.5);
+const ERROR_COLOR: Color = Color::rgb(1.0, 0.45, 0.4);
+
+/// Lore and hints shown while loading, from a `.tips.ron` file.
+#[derive(Asset, TypePath, Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoadingTips {
+    pub tips: Vec<String>,
+}
+
+/// The tips of the game.
+#[derive(Resource, Debug, Default, Clone)]
+pub struct GameTips(pub Handle<LoadingTips>);
+
+/// What the loading screen shows.
+#[derive(Resource, Debug, Default, Clone, PartialEq)]
+pub struct LoadingScreen {
+    pub visible: bool,
+    /// Id of the level being loaded.
+    pub level: String,
+    /// How much of it has loaded, from 0 to 1.
+    pub progress: f32,
+    /// Index of the tip shown, wrapping around the game's tips.
+    pub tip: usize,
+    tip_left: f32,
+    /// Whether the screen has been shown before, so the first tip is shown first.
+    shown_before: bool,
+    /// Whether the level has loaded and waits for the player to continue.
+    pub ready: bool,
+    /// The assets that failed to load.
+    pub failed: Vec<AssetFailure>,
+}
+
+impl LoadingScreen {
+    /// Follow the level change, and show the next tip when the current one has been shown long
+    /// enough or the screen appears again.
+    pub fn update(&mut self, transition: &Transition, delta_seconds: f32) {
+        let visible = matches!(
+            transition.phase(),
+            TransitionPhase::Loading | TransitionPhase::Ready
+        );
+        if !visible {
+            self.visible = false;
+            return;
+        }
+
+        if !self.visible {
+            self.visible = true;
+            if self.shown_before {
+                self.next_tip();
+            } else {
+                self.shown_before = true;
+                self.tip_left = TIP_SECONDS;
+            }
+        }
+        self.level = transition.level_name().to_string();
+        self.progress = transition.progress();
+        self.ready = transition.phase() == TransitionPhase::Ready;
+        if self.failed != transition.failed() {
+            self.failed = transition.failed().to_vec();
+        }
+        self.tip_left -= delta_seconds;
+        if self.tip_left <= 0.0 {
+            self.next_tip();
+        }
+    }
+
+    fn next_tip(&mut self) {
+        self.tip = self.tip.wrapping_add(1);
+        self.tip_left = TIP_SECONDS;
+    }
+
+    /// Whether loading failed.
+    pub fn has_failed(&self) -> bool {
+        !self.failed.is_empty()
+    }
+}
+
+/// Parts of the loading screen.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+enum LoadingUiPart {
+    Screen,
+    Title,
+    Bar,
+    Fill,
+    Tip,
+    Errors,
+    Prompt,
+}
+
+pub struct AstraliminalLoadingScreenPlugin;
+
+impl Plugin for AstraliminalLoadingScreenPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<LoadingTips>()
+            .register_asset_loader(RonLoader::<LoadingTips>::new(&["tips.ron"]))
+            .init_resource::<LoadingScreen>()
+            .add_systems(Startup, spawn_loading_ui)
+            .add_systems(
+                Update,
+                (follow_transition, loading_input, update_loading_ui).chain(),
+            );
+    }
+}
+
+fn follow_transition(
+    mut screen: ResMut<LoadingScreen>,
+    transition: Res<Transition>,
+    time: Res<Time>,
+) {
+    if transition.is_active() || screen.visible {
+        screen.update(&transition, time.delta_seconds());
+    }
+}
+
+/// Continue into the loaded level, or go back or quit if it failed to load.
+#[allow(clippy::too_many_arguments)]
+fn loading_input(
+    screen: Res<LoadingScreen>,
+    current: Res<CurrentLevel>,
+    mut continues: EventWriter<ContinueLevel>,
+    mut levels: EventWriter<ChangeLevel>,
+    mut exits: EventWriter<AppExit>,
+    keys: Res<ButtonInput<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<ButtonInput<GamepadButton>>,
+) {
+    if !screen.visible {
+        return;
+    }
+    let pad = |kind: GamepadButtonType| {
+        gamepads
+            .iter()
+            .any(|gamepad| gamepad_buttons.just_pressed(GamepadButton::new(gamepad, kind)))
+    };
+
+    // TODO: pull key codes from config.
+    if screen.has_failed() {
+        let back = keys.just_pressed(KeyCode::Enter) || pad(GamepadButtonType::South);
+        let quit = keys.just_pressed(KeyCode::Escape) || pad(GamepadButtonType::East);
+        match &current.change {
+            Some(change) if back => {
+                levels.send(change.clone());
+            }
+            _ if quit => {
+                exits.send(AppExit);
+            }
+            _ => {}
+        }
+    } else if screen.ready
+        && (keys.any_just_pressed([KeyCode::Space, KeyCode::Enter])
+            || pad(GamepadButtonType::South))
+    {
+        continues.send(ContinueLevel);
+    }
+}
+
+/// Spawn the hidden loading screen, above everything including the screen fade.
+fn spawn_loading_ui(mut commands: Commands) {
+    let text = |part: LoadingUiPart, size: f32, color: Color| {
+        (
+            part,
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font_size: size,
+                    color,
+                    ..default()
+                },
+            )
+            .with_text_justify(JustifyText::Center)
+            .with_style(Style {
+                max_width: Val::Percent(60.0),
+                ..default()
+            }),
+        )
+    };
+
+    commands
+        .spawn((
+            LoadingUiPart::Screen,
+            NodeBundle {
+                style: Style {
+                    display: Display::None,
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    row_gap: Val::Px(24.0),
+                    ..default()
+                },
+                background_color: BACKGROUND_COLOR.into(),
+                z_index: ZIndex::Global(i32::MAX),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn(text(LoadingUiPart::Title, 36.0, Color::WHITE));
+            parent
+                .spawn((
+                    LoadingUiPart::Bar,
+                    NodeBundle {
+                        style: Style {
+                            width: Val::Percent(50.0),
+                            height: Val::Px(6.0),
+                            ..default()
+                        },
+                        background_color: BAR_COLOR.into(),
+                        ..default()
+                    },
+                ))
+                .with_children(|bar| {
+                    bar.spawn((
+                        LoadingUiPart::Fill,
+                        NodeBundle {
+                            style: Style {
+                                width: Val::Percent(0.0),
+                                height: Val::Percent(100.0),
+                                ..default()
+                            },
+                            background_color: FILL_COLOR.into(),
+                            ..default()
+                        },
+                    ));
+                });
+            parent.spawn(text(LoadingUiPart::Tip, 22.0, Color::rgb(0.8, 0.8, 0.8)));
+            parent.spawn(text(LoadingUiPart::Errors, 20.0, ERROR_COLOR));
+            parent.spawn(text(LoadingUiPart::Prompt, 20.0, FILL_COLOR));
+        });
+}
+
+/// The text of the error list.
+fn errors_text(failed: &[AssetFailure]) -> String {
+    failed
+        .iter()
+        .map(|failure| {
+            if failure.missing {
+                format!("Missing: {}", failure.path)
+            } else {
+                format!("Broken: {} ({})", failure.path, failure.error)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Show the loading screen's state.
+fn update_loading_ui(
+    screen: Res<LoadingScreen>,
+    current: Res<CurrentLevel>,
+    tips: Option<Res<GameTips>>,
+    tip_assets: Res<Assets<LoadingTips>>,
+    mut parts: Query<(&LoadingUiPart, &mut Style, Option<&mut Text>)>,
+) {
+    if !screen.is_changed() {
+        return;
+    }
+
+    let display = |shown: bool| if shown { Display::Flex } else { Display::None };
+    let failed = screen.has_failed();
+    let tip = tips
+        .and_then(|tips| tip_assets.get(&tips.0))
+        .filter(|tips| !tips.tips.is_empty())
+        .map(|tips| tips.tips[screen.tip % tips.tips.len()].clone());
+    for (part, mut style, text) in &mut parts {
+        let value = match part {
+            LoadingUiPart::Screen => {
+                style.display = display(screen.visible);
+                continue;
+            }
+            LoadingUiPart::Bar => {
+                style.display = display(!failed);
+                continue;
+            }
+            LoadingUiPart::Fill => {
+                style.width = Val::Percent(100.0 * screen.progress.clamp(0.0, 1.0));
+                continue;
+            }
+            LoadingUiPart::Title if failed => format!("Couldn't load {}", screen.level),
+            LoadingUiPart::Title => format!("Loading {}", screen.level),
+            LoadingUiPart::Tip => {
+                style.display = display(!failed && tip.is_some());
+                tip.clone().unwrap_or_default()
+            }
+            LoadingUiPart::Errors => {
+                style.display = display(failed);
+                errors_text(&screen.failed)
+            }
+            LoadingUiPart::Prompt if failed && current.change.is_some() => {
+                "Press Enter to go back, or Escape to quit".to_string()
+            }
+            LoadingUiPart::Prompt if failed => "Press Escape to quit".to_string(),
+            LoadingUiPart::Prompt if screen.ready => "Press Space to continue".to_string(),
+            LoadingUiPart::Prompt => String::new(),
+        };
+        if let Some(mut text) = text {
+            if text.sections[0].value != value {
+                text.sections[0].value = value;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loading() -> Transition {
+        Transition::at(TransitionPhase::Loading, "island.level.ron", Vec::new())
+    }
+
+    #[test]
+    fn tips_rotate() {
+        let mut screen = LoadingScreen::default();
+        screen.update(&loading(), 0.1);
+        assert!(screen.visible);
+        assert_eq!(screen.level, "island.level.ron");
+        assert_eq!(screen.tip, 0);
+
+        screen.update(&loading(), TIP_SECONDS - 1.0);
+        assert_eq!(screen.tip, 0);
+        screen.update(&loading(), 1.0);
+        assert_eq!(screen.tip, 1);
+
+        // Each loading screen starts with a new tip.
+        screen.update(&Transition::default(), 0.1);
+        assert!(!screen.visible);
+        screen.update(&loading(), 0.1);
+        assert_eq!(screen.tip, 2);
+    }
+
+    #[test]
+    fn ready_to_continue() {
+        let mut screen = LoadingScreen::default();
+        screen.update(&loading(), 0.1);
+        assert!(!screen.ready);
+        let ready = Transition::at(TransitionPhase::Ready, "island.level.ron", Vec::new());
+        screen.update(&ready, 0.1);
+        assert!(screen.visible && screen.ready);
+
+        let placing = Transition::at(TransitionPhase::Placing, "island.level.ron", Vec::new());
+        screen.update(&placing, 0.1);
+        assert!(!screen.visible);
+    }
+
+    #[test]
+    fn failures_are_listed() {
+        let failures = vec![
+            AssetFailure {
+                path: "levels/island.glb".to_string(),
+                missing: true,
+                error: "not found".to_string(),
+            },
+            AssetFailure {
+                path: "island.logic.ron".to_string(),
+                missing: false,
+                error: "expected `)`".to_string(),
+            },
+        ];
+        let mut screen = LoadingScreen::default();
+        screen.update(&loading(), 0.1);
+        assert!(!screen.has_failed());
+
+        let failed = Transition::at(
+            TransitionPhase::Loading,
+            "island.level.ron",
+            failures.clone(),
+        );
+        screen.update(&failed, 0.1);
+        assert!(screen.has_failed());
+        assert_eq!(screen.failed, failures);
+        assert_eq!(
+            errors_text(&screen.failed),
+            "Missing: levels/island.glb\nBroken: island.logic.ron (expected `)`)"
+        );
+    }
+}
@@ -0,0 +1,632 @@
+//! Astraliminal's Logic plugin.
+//!
+//! Puzzle elements are wired together with a `LogicGraph`, loaded from a `.logic.ron` file.
+//! Sources read the world (plates, buttons, triggers, viewpoints, story flags and conditions),
+//! gates combine them and sinks drive entities by name through their `LogicSignal` or change the
+//! story with their effects.
+//!
+//! The graph is sorted once when it loads, which also rejects cycles, and then evaluated every
+//! frame in that order, so results never depend on system or entity order.
+
+use std::{collections::VecDeque, fmt};
+
+use bevy::{prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    expression::{Condition, Effects},
+    interact::Interacted,
+    level::LevelEntity,
+    plate::{PlateState, PressurePlate},
+    ron_loader::RonLoader,
+    schedule::AstralSet,
+    story::Story,
+    trigger::{TriggerOccupants, TriggerVolume},
+    viewpoint::ViewpointAligned,
+};
+
+/// Logic nodes, loaded from a `.logic.ron` file.
+#[derive(Asset, TypePath, Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogicGraph {
+    pub nodes: Vec<LogicNode>,
+}
+
+/// A named node in a `LogicGraph`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogicNode {
+    /// Unique id, used by other nodes to refer to this one's output.
+    pub id: String,
+    pub kind: LogicKind,
+}
+
+/// What a node does. Gates and sinks refer to their inputs by node id.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum LogicKind {
+    /// Always the given value.
+    Constant(bool),
+    /// On while the `PressurePlate` with this id is pressed.
+    Plate(String),
+    /// On for one step when the `Interactable` with this id is used.
+    Button(String),
+    /// On while something is in the `TriggerVolume` with this id.
+    Trigger(String),
+    /// On while the player is aligned with the `Viewpoint` of the entity with this name.
+    Viewpoint(String),
+    /// On while the story flag is set.
+    Flag(String),
+    /// On while the story condition holds, e.g. `trust >= 3 && !flag(met_seekers)`.
+    Condition(Condition),
+    /// On while all inputs are on.
+    And(Vec<String>),
+    /// On while any input is on.
+    Or(Vec<String>),
+    /// On while the input is off.
+    Not(String),
+    /// Turns on when `set` is on and stays on until `reset` is on. `reset` wins.
+    Latch { set: String, reset: String },
+    /// On once the input has been on for `seconds` without a break.
+    Timer { input: String, seconds: f32 },
+    /// Counts the times the input turns on. On once the count reaches `target`.
+    Counter {
+        input: String,
+        target: u32,
+        reset: Option<String>,
+    },
+    /// Passes the input to the `LogicSignal` of every entity with the name `target`.
+    Sink { input: String, target: String },
+    /// Applies the effects every time the input turns on.
+    Effects { input: String, effects: Effects },
+}
+
+impl LogicKind {
+    /// Ids of the nodes this node reads.
+    pub fn inputs(&self) -> Vec<&str> {
+        match self {
+            Self::Constant(_)
+            | Self::Plate(_)
+            | Self::Button(_)
+            | Self::Trigger(_)
+            | Self::Viewpoint(_)
+            | Self::Flag(_)
+            | Self::Condition(_) => Vec::new(),
+            Self::And(inputs) | Self::Or(inputs) => inputs.iter().map(String::as_str).collect(),
+            Self::Not(input)
+            | Self::Timer { input, .. }
+            | Self::Sink { input, .. }
+            | Self::Effects { input, .. } => vec![input],
+            Self::Latch { set, reset } => vec![set, reset],
+            Self::Counter { input, reset, .. } => {
+                let mut inputs = vec![input.as_str()];
+                inputs.extend(reset.as_deref());
+                inputs
+            }
+        }
+    }
+}
+
+/// Problems that keep a `LogicGraph` from being used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogicError {
+    /// Two nodes share an id.
+    DuplicateId(String),
+    /// A node reads a node that does not exist.
+    UnknownInput { node: String, input: String },
+    /// These nodes feed into each other.
+    Cycle(Vec<String>),
+}
+
+impl fmt::Display for LogicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DuplicateId(id) => write!(f, "logic node {} is defined more than once", id),
+            Self::UnknownInput { node, input } => {
+                write!(f, "logic node {} reads unknown node {}", node, input)
+            }
+            Self::Cycle(nodes) => write!(f, "logic nodes form a cycle: {}", nodes.join(", ")),
+        }
+    }
+}
+
+impl std::error::Error for LogicError {}
+
+impl LogicGraph {
+    /// The order to evaluate the nodes in, as indices into `nodes`. Every node comes after its
+    /// inputs; otherwise nodes keep the order they were written in.
+    pub fn evaluation_order(&self) -> Result<Vec<usize>, LogicError> {
+        let mut index = HashMap::default();
+        for (i, node) in self.nodes.iter().enumerate() {
+            if index.insert(node.id.as_str(), i).is_some() {
+                return Err(LogicError::DuplicateId(node.id.clone()));
+            }
+        }
+
+        let mut pending = vec![0; self.nodes.len()];
+        let mut readers = vec![Vec::new(); self.nodes.len()];
+        for (i, node) in self.nodes.iter().enumerate() {
+            for input in node.kind.inputs() {
+                let Some(&source) = index.get(input) else {
+                    return Err(LogicError::UnknownInput {
+                        node: node.id.clone(),
+                        input: input.to_string(),
+                    });
+                };
+                pending[i] += 1;
+                readers[source].push(i);
+            }
+        }
+
+        let mut ready: VecDeque<usize> =
+            (0..self.nodes.len()).filter(|&i| pending[i] == 0).collect();
+        let mut order = Vec::with_capacity(self.nodes.len());
+        while let Some(i) = ready.pop_front() {
+            order.push(i);
+            for &reader in &readers[i] {
+                pending[reader] -= 1;
+                if pending[reader] == 0 {
+                    ready.push_back(reader);
+                }
+            }
+        }
+
+        if order.len() < self.nodes.len() {
+            let cycle = (0..self.nodes.len())
+                .filter(|&i| pending[i] > 0)
+                .map(|i| self.nodes[i].id.clone())
+                .collect();
+            return Err(LogicError::Cycle(cycle));
+        }
+
+        Ok(order)
+    }
+}
+
+/// The values of every node and what the gates remember between steps.
+#[derive(Resource, Reflect, Debug, Default, Clone, PartialEq)]
+#[reflect(Resource)]
+pub struct LogicState {
+    /// Output of every node by id.
+    pub values: HashMap<String, bool>,
+    /// How long each timer's input has been on.
+    pub timers: HashMap<String, f32>,
+    /// How many times each counter's input turned on.
+    pub counters: HashMap<String, u32>,
+    /// Each counter's input at the last step, to find when it turns on.
+    pub counter_inputs: HashMap<String, bool>,
+}
+
+impl LogicState {
+    /// The output of a node, off if it was never evaluated.
+    pub fn value(&self, id: &str) -> bool {
+        self.values.get(id).copied().unwrap_or_default()
+    }
+
+    /// Evaluate the graph once, in `order`, advancing timers by `delta` seconds. `source` gives
+    /// the value of source nodes.
+    pub fn step(
+        &mut self,
+        graph: &LogicGraph,
+        order: &[usize],
+        delta: f32,
+        source: impl Fn(&LogicKind) -> bool,
+    ) {
+        for &i in order {
+            let node = &graph.nodes[i];
+            let value = match &node.kind {
+                LogicKind::Constant(value) => *value,
+                LogicKind::And(inputs) => inputs.iter().all(|input| self.value(input)),
+                LogicKind::Or(inputs) => inputs.iter().any(|input| self.value(input)),
+                LogicKind::Not(input) => !self.value(input),
+                LogicKind::Latch { set, reset } => {
+                    !self.value(reset) && (self.value(set) || self.value(&node.id))
+                }
+                LogicKind::Timer { input, seconds } => {
+                    let input = self.value(input);
+                    let held = self.timers.entry(node.id.clone()).or_default();
+                    *held = if input { *held + delta } else { 0.0 };
+                    *held >= *seconds
+                }
+                LogicKind::Counter {
+                    input,
+                    target,
+                    reset,
+                } => {
+                    let input = self.value(input);
+                    let reset = reset.as_deref().is_some_and(|reset| self.value(reset));
+                    let was_on = self.counter_inputs.insert(node.id.clone(), input);
+                    let count = self.counters.entry(node.id.clone()).or_default();
+                    if reset {
+                        *count = 0;
+                    } else if input && was_on != Some(true) {
+                        *count += 1;
+                    }
+                    *count >= *target
+                }
+                LogicKind::Sink { input, .. } | LogicKind::Effects { input, .. } => {
+                    self.value(input)
+                }
+                source_kind => source(source_kind),
+            };
+            self.values.insert(node.id.clone(), value);
+        }
+    }
+
+    /// The effects of the `Effects` nodes that turned on since the node values were `previous`.
+    pub fn fired_effects<'a>(
+        &self,
+        graph: &'a LogicGraph,
+        previous: &HashMap<String, bool>,
+    ) -> Vec<&'a Effects> {
+        graph
+            .nodes
+            .iter()
+            .filter_map(|node| match &node.kind {
+                LogicKind::Effects { effects, .. }
+                    if self.value(&node.id) && previous.get(&node.id) != Some(&true) =>
+                {
+                    Some(effects)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// The logic graph of the current level.
+#[derive(Resource, Debug, Clone)]
+pub struct LevelLogic(pub Handle<LogicGraph>);
+
+/// The evaluation order of the current level's graph, or `None` if it isn't loaded or is broken.
+#[derive(Resource, Debug, Default, Clone)]
+pub struct LogicOrder(pub Option<Vec<usize>>);
+
+/// The value a `Sink` node passes to entities with its target name.
+#[derive(Component, Reflect, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[reflect(Component)]
+pub struct LogicSignal(pub bool);
+
+/// Spawns a scene at its transform every time its `LogicSignal` turns on.
+#[derive(Component, Reflect, Debug, Default, Clone)]
+#[reflect(Component)]
+pub struct LogicSpawner {
+    /// Asset path of the scene to spawn.
+    pub scene: String,
+}
+
+pub struct AstraliminalLogicPlugin;
+
+impl Plugin for AstraliminalLogicPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<LogicGraph>()
+            .register_asset_loader(RonLoader::<LogicGraph>::new(&["logic.ron"]))
+            .register_type::<LogicState>()
+            .register_type::<LogicSignal>()
+            .register_type::<LogicSpawner>()
+            .init_resource::<LogicState>()
+            .init_resource::<LogicOrder>()
+            .add_systems(
+                FixedUpdate,
+                (
+                    sort_graph.in_set(AstralSet::Setup),
+                    (evaluate, drive_sinks, (switch_lights, run_spawners))
+                        .chain()
+                        .in_set(AstralSet::React),
+                )
+                    .run_if(resource_exists::<LevelLogic>),
+            );
+    }
+}
+
+/// Sort the graph whenever it loads or changes. A broken graph is reported and not run.
+fn sort_graph(
+    mut events: EventReader<AssetEvent<LogicGraph>>,
+    mut order: ResMut<LogicOrder>,
+    mut state: ResMut<LogicState>,
+    level: Res<LevelLogic>,
+    graphs: Res<Assets<LogicGraph>>,
+) {
+    let reload = level.is_changed()
+        || events.read().any(|event| {
+            event.is_loaded_with_dependencies(&level.0) || event.is_modified(&level.0)
+        });
+    if !reload {
+        return;
+    }
+
+    let Some(graph) = graphs.get(&level.0) else {
+        order.0 = None;
+        return;
+    };
+    order.0 = match graph.evaluation_order() {
+        Ok(sorted) => Some(sorted),
+        Err(err) => {
+            error!("Logic graph is broken: {}", err);
+            None
+        }
+    };
+    *state = LogicState::default();
+}
+
+/// Read the sources, evaluate the graph and apply the effects of nodes that turned on.
+#[allow(clippy::too_many_arguments)]
+fn evaluate(
+    mut state: ResMut<LogicState>,
+    mut interacted: EventReader<Interacted>,
+    time: Res<Time>,
+    order: Res<LogicOrder>,
+    level: Res<LevelLogic>,
+    graphs: Res<Assets<LogicGraph>>,
+    mut story: Story,
+    plates: Query<(&PressurePlate, &PlateState)>,
+    triggers: Query<(&TriggerVolume, &TriggerOccupants)>,
+    viewpoints: Query<(&Name, Has<ViewpointAligned>)>,
+) {
+    let pressed: Vec<String> = interacted.read().map(|event| event.id.clone()).collect();
+    let (Some(order), Some(graph)) = (&order.0, graphs.get(&level.0)) else {
+        return;
+    };
+
+    let previous = state.values.clone();
+    state.step(graph, order, time.delta_seconds(), |kind| match kind {
+        LogicKind::Plate(id) => plates
+            .iter()
+            .any(|(plate, plate_state)| &plate.id == id && plate_state.pressed),
+        LogicKind::Button(id) => pressed.contains(id),
+        LogicKind::Trigger(id) => triggers
+            .iter()
+            .any(|(trigger, occupants)| &trigger.id == id && !occupants.0.is_empty()),
+        LogicKind::Viewpoint(name) => viewpoints
+            .iter()
+            .any(|(entity_name, aligned)| entity_name.as_str() == name && aligned),
+        LogicKind::Flag(flag) => story.flags.is_set(flag),
+        LogicKind::Condition(condition) => story.check(condition),
+        _ => false,
+    });
+
+    for effects in state.fired_effects(graph, &previous) {
+        story.apply(effects);
+    }
+
+    if cfg!(debug_assertions) {
+        for (id, value) in &state.values {
+            if previous.get(id) != Some(value) {
+                debug!(
+                    "Logic node {} is now {}",
+                    id,
+                    if *value { "on" } else { "off" }
+                );
+            }
+        }
+    }
+}
+
+/// Pass the value of every sink to the entities it targets.
+pub(crate) fn drive_sinks(
+    mut commands: Commands,
+    state: Res<LogicState>,
+    order: Res<LogicOrder>,
+    level: Res<LevelLogic>,
+    graphs: Res<Assets<LogicGraph>>,
+    mut targets: Query<(Entity, &Name, Option<&mut LogicSignal>)>,
+) {
+    let (Some(_), Some(graph)) = (&order.0, graphs.get(&level.0)) else {
+        return;
+    };
+
+    for node in &graph.nodes {
+        let LogicKind::Sink { target, .. } = &node.kind else {
+            continue;
+        };
+        let value = state.value(&node.id);
+        for (entity, name, signal) in &mut targets {
+            if name.as_str() != target {
+                continue;
+            }
+            match signal {
+                Some(mut signal) => {
+                    signal.set_if_neq(LogicSignal(value));
+                }
+                None => {
+                    commands.entity(entity).insert(LogicSignal(value));
+                }
+            }
+        }
+    }
+}
+
+/// Lights with a `LogicSignal` are shown while it is on.
+#[allow(clippy::type_complexity)]
+fn switch_lights(
+    mut lights: Query<
+        (&LogicSignal, &mut Visibility),
+        (
+            Changed<LogicSignal>,
+            Or<(With<PointLight>, With<SpotLight>, With<DirectionalLight>)>,
+        ),
+    >,
+) {
+    for (signal, mut visibility) in &mut lights {
+        *visibility = if signal.0 {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
+
+/// Spawn a scene every time a spawner's signal turns on.
+fn run_spawners(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    spawners: Query<(&LogicSpawner, &LogicSignal, &GlobalTransform), Changed<LogicSignal>>,
+) {
+    for (spawner, signal, transform) in &spawners {
+        if !signal.0 {
+            continue;
+        }
+        commands.spawn((
+            SceneBundle {
+                scene: asset_server.load(&spawner.scene),
+                transform: transform.compute_transform(),
+                ..default()
+            },
+            LevelEntity,
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(source: &str) -> LogicGraph {
+        ron::from_str(source).unwrap()
+    }
+
+    /// Step the graph once with the given source nodes on.
+    fn step(state: &mut LogicState, graph: &LogicGraph, delta: f32, on: &[&str]) {
+        let order = graph.evaluation_order().unwrap();
+        state.step(graph, &order, delta, |kind| match kind {
+            LogicKind::Plate(id) | LogicKind::Button(id) => on.contains(&id.as_str()),
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn order_puts_inputs_first() {
+        let graph = graph(
+            r#"(nodes: [
+                (id: "door", kind: Sink(input: "both", target: "Door")),
+                (id: "both", kind: And(["a", "b"])),
+                (id: "a", kind: Plate("a")),
+                (id: "b", kind: Not("a")),
+            ])"#,
+        );
+        let order = graph.evaluation_order().unwrap();
+        let position = |id: &str| order.iter().position(|&i| graph.nodes[i].id == id).unwrap();
+        assert!(position("a") < position("b"));
+        assert!(position("b") < position("both"));
+        assert!(position("both") < position("door"));
+    }
+
+    #[test]
+    fn order_rejects_broken_graphs() {
+        let cycle = graph(
+            r#"(nodes: [
+                (id: "plate", kind: Plate("plate")),
+                (id: "a", kind: Or(["plate", "b"])),
+                (id: "b", kind: Not("a")),
+            ])"#,
+        );
+        assert_eq!(
+            cycle.evaluation_order(),
+            Err(LogicError::Cycle(vec!["a".to_string(), "b".to_string()]))
+        );
+
+        let unknown = graph(r#"(nodes: [(id: "a", kind: Not("missing"))])"#);
+        assert_eq!(
+            unknown.evaluation_order(),
+            Err(LogicError::UnknownInput {
+                node: "a".to_string(),
+                input: "missing".to_string()
+            })
+        );
+
+        let duplicate = graph(
+            r#"(nodes: [(id: "a", kind: Constant(true)), (id: "a", kind: Constant(false))])"#,
+        );
+        assert_eq!(
+            duplicate.evaluation_order(),
+            Err(LogicError::DuplicateId("a".to_string()))
+        );
+    }
+
+    #[test]
+    fn latch_holds_until_reset() {
+        let graph = graph(
+            r#"(nodes: [
+                (id: "set", kind: Button("set")),
+                (id: "reset", kind: Button("reset")),
+                (id: "latch", kind: Latch(set: "set", reset: "reset")),
+            ])"#,
+        );
+        let mut state = LogicState::default();
+        for (on, expected) in [
+            (&[][..], false),
+            (&["set"][..], true),
+            (&[][..], true),
+            (&["reset"][..], false),
+            (&[][..], false),
+            (&["set", "reset"][..], false),
+        ] {
+            step(&mut state, &graph, 0.1, on);
+            assert_eq!(state.value("latch"), expected, "after {on:?}");
+        }
+    }
+
+    #[test]
+    fn timer_needs_an_unbroken_input() {
+        let graph = graph(
+            r#"(nodes: [
+                (id: "plate", kind: Plate("plate")),
+                (id: "timer", kind: Timer(input: "plate", seconds: 1.0)),
+            ])"#,
+        );
+        let mut state = LogicState::default();
+        for _ in 0..3 {
+            step(&mut state, &graph, 0.25, &["plate"]);
+        }
+        assert!(!state.value("timer"));
+        // Stepping off starts over.
+        step(&mut state, &graph, 0.25, &[]);
+        for _ in 0..3 {
+            step(&mut state, &graph, 0.25, &["plate"]);
+        }
+        assert!(!state.value("timer"));
+        step(&mut state, &graph, 0.25, &["plate"]);
+        assert!(state.value("timer"));
+        step(&mut state, &graph, 0.25, &[]);
+        assert!(!state.value("timer"));
+    }
+
+    #[test]
+    fn counter_counts_rising_edges() {
+        let graph = graph(
+            r#"(nodes: [
+                (id: "button", kind: Button("button")),
+                (id: "reset", kind: Button("reset")),
+                (id: "count", kind: Counter(input: "button", target: 2, reset: Some("reset"))),
+            ])"#,
+        );
+        let mut state = LogicState::default();
+        // Holding the input counts once.
+        step(&mut state, &graph, 0.1, &["button"]);
+        step(&mut state, &graph, 0.1, &["button"]);
+        assert_eq!(state.counters["count"], 1);
+        assert!(!state.value("count"));
+        step(&mut state, &graph, 0.1, &[]);
+        step(&mut state, &graph, 0.1, &["button"]);
+        assert!(state.value("count"));
+
+        step(&mut state, &graph, 0.1, &["reset"]);
+        assert_eq!(state.counters["count"], 0);
+        assert!(!state.value("count"));
+    }
+
+    #[test]
+    fn effects_fire_when_turning_on() {
+        let graph = graph(
+            r#"(nodes: [
+                (id: "plate", kind: Plate("plate")),
+                (id: "reward", kind: Effects(input: "plate", effects: "inc(trust)")),
+            ])"#,
+        );
+        let mut state = LogicState::default();
+        let mut fired = Vec::new();
+        for on in [&[][..], &["plate"], &["plate"], &[], &["plate"]] {
+            let previous = state.values.clone();
+            step(&mut state, &graph, 0.1, on);
+            fired.push(state.fired_effects(&graph, &previous).len());
+        }
+        assert_eq!(fired, [0, 1, 0, 0, 1]);
+    }
+}
@@ -0,0 +1,348 @@
+//! Level manifests.
+//!
+//! A `.level.ron` manifest lists everything a level needs: its scene, room graph, logic graph and
+//! dialogue script, and any other assets such as audio and textures. Assets several levels share
+//! are listed once in a group of the game's `.groups.ron` file, and manifests name the groups they
+//! use. The level plugin loads all of it before the player enters, and keeps it loaded until the
+//! next level no longer needs it.
+//!
+//! `LevelManifest::validate` checks that every file exists and that the level's data files can be
+//! read without starting the game.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs,
+    path::Path,
+};
+
+use bevy::prelude::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    dialogue::DialogueScript,
+    logic::{LogicError, LogicGraph},
+    ron_loader::RonLoaderError,
+    room_graph::{RoomGraph, SeamIssue},
+};
+
+/// What a level is made of, loaded from a `.level.ron` file. Every path is an asset path.
+#[derive(Asset, TypePath, Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LevelManifest {
+    /// Unique id of the level, e.g. `island`.
+    pub id: String,
+    /// The scene to spawn, e.g. `levels/island.glb#Scene0`.
+    pub scene: Option<String>,
+    /// The level's `RoomGraph`.
+    pub rooms: Option<String>,
+    /// The level's `LogicGraph`.
+    pub logic: Option<String>,
+    /// The level's `DialogueScript`.
+    pub dialogue: Option<String>,
+    /// Names of the shared groups of assets the level uses.
+    pub groups: Vec<String>,
+    /// Other assets to load before the level starts, e.g. `audio/waves.ogg`.
+    pub assets: Vec<String>,
+}
+
+/// Named lists of asset paths that several levels use, loaded from a `.groups.ron` file.
+#[derive(Asset, TypePath, Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AssetGroups(pub BTreeMap<String, Vec<String>>);
+
+/// The game's shared asset groups.
+#[derive(Resource, Debug, Default, Clone)]
+pub struct GameAssetGroups(pub Handle<AssetGroups>);
+
+/// Problems found by `LevelManifest::validate`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ManifestIssue {
+    /// The manifest has no id.
+    MissingId,
+    /// The manifest uses a group that doesn't exist.
+    UnknownGroup(String),
+    /// An asset's file doesn't exist.
+    MissingFile(String),
+    /// A data file exists but can't be read.
+    Unreadable { path: String, error: String },
+    /// The room graph has a broken seam or room.
+    Rooms(SeamIssue),
+    /// The logic graph can't be evaluated.
+    Logic(LogicError),
+}
+
+impl fmt::Display for ManifestIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingId => write!(f, "level has no id"),
+            Self::UnknownGroup(group) => write!(f, "unknown asset group {}", group),
+            Self::MissingFile(path) => write!(f, "{} does not exist", path),
+            Self::Unreadable { path, error } => write!(f, "{}: {}", path, error),
+            Self::Rooms(issue) => write!(f, "room graph: {}", issue),
+            Self::Logic(err) => write!(f, "logic graph: {}", err),
+        }
+    }
+}
+
+/// The file an asset path refers to, without the label of an asset inside it.
+fn file_path(path: &str) -> &str {
+    path.split_once('#').map_or(path, |(file, _)| file)
+}
+
+/// Read a data file of a level, noting an issue if it can't be parsed. Missing files are left for
+/// the check of every asset.
+fn read_data<T: DeserializeOwned>(
+    assets: &Path,
+    path: &str,
+    issues: &mut Vec<ManifestIssue>,
+) -> Option<T> {
+    let text = fs::read_to_string(assets.join(path)).ok()?;
+    ron::from_str(&text)
+        .map_err(|err| {
+            issues.push(ManifestIssue::Unreadable {
+                path: path.to_string(),
+                error: RonLoaderError::from(err).to_string(),
+            });
+        })
+        .ok()
+}
+
+impl AssetGroups {
+    /// Read asset groups from a RON file, e.g. for validation outside of the game.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RonLoaderError> {
+        Ok(ron::from_str(&fs::read_to_string(path)?)?)
+    }
+}
+
+impl LevelManifest {
+    /// Read a manifest from a RON file, e.g. for validation outside of the game.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RonLoaderError> {
+        Ok(ron::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Groups the manifest uses that don't exist.
+    pub fn unknown_groups<'a>(&'a self, groups: &AssetGroups) -> Vec<&'a str> {
+        self.groups
+            .iter()
+            .filter(|group| !groups.0.contains_key(*group))
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Every asset path the level needs, each once. Unknown groups are skipped.
+    pub fn asset_paths(&self, groups: &AssetGroups) -> Vec<String> {
+        let mut seen = BTreeSet::new();
+        let mut paths = Vec::new();
+        let mut add = |path: &String| {
+            if seen.insert(path.clone()) {
+                paths.push(path.clone());
+            }
+        };
+
+        [&self.scene, &self.rooms, &self.logic, &self.dialogue]
+            .into_iter()
+            .flatten()
+            .for_each(&mut add);
+        self.groups
+            .iter()
+            .filter_map(|group| groups.0.get(group))
+            .flatten()
+            .for_each(&mut add);
+        self.assets.iter().for_each(add);
+        paths
+    }
+
+    /// Look for anything that would stop the level from loading, with asset paths relative to
+    /// `assets`. The data files are parsed, and the room graph's rooms and seams and the logic
+    /// graph's nodes are checked too.
+    pub fn validate(&self, groups: &AssetGroups, assets: &Path) -> Vec<ManifestIssue> {
+        let mut issues = Vec::new();
+        if self.id.is_empty() {
+            issues.push(ManifestIssue::MissingId);
+        }
+        issues.extend(
+            self.unknown_groups(groups)
+                .into_iter()
+                .map(|group| ManifestIssue::UnknownGroup(group.to_string())),
+        );
+
+        let mut paths = self.asset_paths(groups);
+        if let Some(rooms) = &self.rooms {
+            if let Some(graph) = read_data::<RoomGraph>(assets, rooms, &mut issues) {
+                issues.extend(graph.validate().into_iter().map(ManifestIssue::Rooms));
+                paths.extend(graph.rooms.into_iter().map(|room| room.scene));
+            }
+        }
+        if let Some(logic) = &self.logic {
+            if let Some(graph) = read_data::<LogicGraph>(assets, logic, &mut issues) {
+                if let Err(err) = graph.evaluation_order() {
+                    issues.push(ManifestIssue::Logic(err));
+                }
+            }
+        }
+        if let Some(dialogue) = &self.dialogue {
+            read_data::<DialogueScript>(assets, dialogue, &mut issues);
+        }
+
+        let mut checked = BTreeSet::new();
+        for path in &paths {
+            let file = file_path(path);
+            if checked.insert(file) && !assets.join(file).is_file() {
+                issues.push(ManifestIssue::MissingFile(file.to_string()));
+            }
+        }
+        issues
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn groups() -> AssetGroups {
+        AssetGroups(BTreeMap::from([
+            (
+                "boat".to_string(),
+                vec![
+                    "models/boat.glb#Scene0".to_string(),
+                    "audio/waves.ogg".to_string(),
+                ],
+            ),
+            ("water".to_string(), vec!["audio/waves.ogg".to_string()]),
+        ]))
+    }
+
+    fn island() -> LevelManifest {
+        LevelManifest {
+            id: "island".to_string(),
+            scene: Some("levels/island.glb#Scene0".to_string()),
+            logic: Some("island.logic.ron".to_string()),
+            dialogue: Some("island.dialogue.ron".to_string()),
+            groups: vec!["boat".to_string(), "water".to_string()],
+            assets: vec![
+                "audio/waves.ogg".to_string(),
+                "levels/island.glb#Scene0".to_string(),
+                "levels/island.glb#Animation0".to_string(),
+            ],
+            ..default()
+        }
+    }
+
+    #[test]
+    fn asset_paths_are_listed_once() {
+        assert_eq!(
+            island().asset_paths(&groups()),
+            [
+                "levels/island.glb#Scene0",
+                "island.logic.ron",
+                "island.dialogue.ron",
+                "models/boat.glb#Scene0",
+                "audio/waves.ogg",
+                "levels/island.glb#Animation0",
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_groups_are_skipped() {
+        let mut level = island();
+        level.groups = vec!["boat".to_string(), "forest".to_string()];
+        assert_eq!(level.unknown_groups(&groups()), ["forest"]);
+        assert!(!level
+            .asset_paths(&groups())
+            .iter()
+            .any(|path| path.contains("forest")));
+        assert!(island().unknown_groups(&groups()).is_empty());
+    }
+
+    /// A temporary assets directory with the given files.
+    fn assets(test: &str, files: &[(&str, &str)]) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("astral_{}_{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (path, contents) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn valid_levels_have_no_issues() {
+        let dir = assets(
+            "manifest_valid",
+            &[
+                ("levels/island.glb", ""),
+                ("models/boat.glb", ""),
+                ("audio/waves.ogg", ""),
+                (
+                    "island.logic.ron",
+                    "(nodes: [(id: \"on\", kind: Constant(true))])",
+                ),
+                ("island.dialogue.ron", "(nodes: [(id: \"Z.a\")])"),
+            ],
+        );
+        assert_eq!(island().validate(&groups(), &dir), []);
+    }
+
+    #[test]
+    fn issues_are_found() {
+        let dir = assets(
+            "manifest_issues",
+            &[
+                ("levels/island.glb", ""),
+                (
+                    "island.logic.ron",
+                    "(nodes: [(id: \"not\", kind: Not(\"missing\"))])",
+                ),
+                (
+                    "island.dialogue.ron",
+                    "(nodes: [(id: \"Z.a\", next_time: 3)])",
+                ),
+            ],
+        );
+        let mut level = island();
+        level.id.clear();
+        level.groups.push("forest".to_string());
+
+        let issues = level.validate(&groups(), &dir);
+        assert_eq!(issues[0], ManifestIssue::MissingId);
+        assert_eq!(issues[1], ManifestIssue::UnknownGroup("forest".to_string()));
+        assert_eq!(
+            issues[2],
+            ManifestIssue::Logic(LogicError::UnknownInput {
+                node: "not".to_string(),
+                input: "missing".to_string(),
+            })
+        );
+        assert!(matches!(
+            &issues[3],
+            ManifestIssue::Unreadable { path, .. } if path == "island.dialogue.ron"
+        ));
+        // Labels are stripped, so each file is only checked once.
+        assert_eq!(
+            issues[4..],
+            [
+                ManifestIssue::MissingFile("models/boat.glb".to_string()),
+                ManifestIssue::MissingFile("audio/waves.ogg".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_data_files_are_only_missing() {
+        let dir = assets("manifest_missing", &[]);
+        let level = LevelManifest {
+            id: "empty".to_string(),
+            rooms: Some("empty.rooms.ron".to_string()),
+            logic: Some("empty.logic.ron".to_string()),
+            ..default()
+        };
+        assert_eq!(
+            level.validate(&AssetGroups::default(), &dir),
+            [
+                ManifestIssue::MissingFile("empty.rooms.ron".to_string()),
+                ManifestIssue::MissingFile("empty.logic.ron".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,592 @@
+//! Astraliminal's Mover plugin.
+//!
+//! A `Mover` is a kinematic body that travels along a `MoverPath`: sliding and hinged doors,
+//! platforms and elevators. It is driven by its `LogicSignal` or by `MoveMover` events, e.g. from
+//! a timeline.
+//!
+//! Bodies resting on a mover, and the player, are moved along with it in the same frame, so they
+//! don't slide off or jitter. Before every step the mover checks that neither it nor what it
+//! carries would end up inside something solid; if it would, it stops or reverses and sends
+//! `MoverBlocked` instead of crushing the object. Sensors never block.
+
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+
+use crate::{
+    logic::LogicSignal,
+    plate::{resting_bodies, BodyQuery},
+    player::{Held, Player},
+    schedule::AstralSet,
+};
+
+/// Scale of the collider a carried body is checked with, so resting contacts with things next to
+/// the mover don't stop it.
+const CARRIED_SKIN: f32 = 0.98;
+
+/// The way a mover travels, relative to where it was placed.
+#[derive(Reflect, Debug, Clone, PartialEq)]
+pub enum MoverPath {
+    /// Slide by this offset, in the mover's local space.
+    Slide(Vec3),
+    /// Swing around a local axis through the mover's origin by `angle` radians. Place the origin
+    /// of a hinged door on its hinge.
+    Hinge { axis: Vec3, angle: f32 },
+    /// Follow a smooth curve through these points, in the mover's local space, starting from its
+    /// origin.
+    Spline(Vec<Vec3>),
+}
+
+impl Default for MoverPath {
+    fn default() -> Self {
+        Self::Slide(Vec3::ZERO)
+    }
+}
+
+impl MoverPath {
+    /// The mover's transform at `progress` along the path, from 0 at `home` to 1 at the end.
+    pub fn pose(&self, home: &Transform, progress: f32) -> Transform {
+        let progress = progress.clamp(0.0, 1.0);
+        let mut pose = *home;
+        match self {
+            Self::Slide(offset) => {
+                pose.translation += home.rotation * (*offset * progress);
+            }
+            Self::Hinge { axis, angle } => {
+                let axis = axis.try_normalize().unwrap_or(Vec3::Y);
+                pose.rotation = home.rotation * Quat::from_axis_angle(axis, angle * progress);
+            }
+            Self::Spline(points) => {
+                pose.translation += home.rotation * spline_point(points, progress);
+            }
+        }
+        pose
+    }
+}
+
+/// The point at `progress` along a Catmull-Rom spline from the origin through `points`. Every
+/// segment takes the same share of `progress`.
+pub(crate) fn spline_point(points: &[Vec3], progress: f32) -> Vec3 {
+    if points.is_empty() {
+        return Vec3::ZERO;
+    }
+
+    let point = |i: isize| -> Vec3 {
+        match i {
+            i if i <= 0 => Vec3::ZERO,
+            i => points[(i as usize - 1).min(points.len() - 1)],
+        }
+    };
+
+    let scaled = progress * points.len() as f32;
+    let segment = (scaled.floor() as isize).min(points.len() as isize - 1);
+    let t = scaled - segment as f32;
+    let (p0, p1, p2, p3) = (
+        point(segment - 1),
+        point(segment),
+        point(segment + 1),
+        point(segment + 2),
+    );
+
+    let t2 = t * t;
+    let t3 = t2 * t;
+    0.5 * (2.0 * p1
+        + (p2 - p0) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+}
+
+/// How a mover picks where to go.
+#[derive(Reflect, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MoverMode {
+    /// Go to the end of the path while the `LogicSignal` is on and back while it is off. For
+    /// doors and elevators.
+    #[default]
+    Signal,
+    /// Travel back and forth while the `LogicSignal` is on, or all the time without one. For
+    /// platforms.
+    PingPong,
+}
+
+/// What a mover does when something is in its way.
+#[derive(Reflect, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OnBlocked {
+    /// Wait until the way is clear.
+    #[default]
+    Stop,
+    /// Turn around and go back.
+    Reverse,
+}
+
+/// A kinematic body that travels along a path. Needs a `Collider` to be blocked; `RigidBody` and
+/// `MoverState` are added for you.
+#[derive(Component, Reflect, Debug, Clone)]
+#[reflect(Component)]
+pub struct Mover {
+    pub path: MoverPath,
+    /// Seconds to travel the whole path.
+    pub duration: f32,
+    pub mode: MoverMode,
+    pub on_blocked: OnBlocked,
+}
+
+impl Default for Mover {
+    fn default() -> Self {
+        Self {
+            path: MoverPath::default(),
+            duration: 2.0,
+            mode: MoverMode::Signal,
+            on_blocked: OnBlocked::Stop,
+        }
+    }
+}
+
+/// Where a mover is along its path and where it is going.
+#[derive(Component, Reflect, Debug, Default, Clone, Copy, PartialEq)]
+#[reflect(Component)]
+pub struct MoverState {
+    /// The transform the path is relative to.
+    pub home: Transform,
+    /// How far along the path the mover is, from 0 to 1.
+    pub progress: f32,
+    /// How far along the path the mover is going.
+    pub target: f32,
+    /// Whether the mover was blocked last step.
+    pub blocked: bool,
+}
+
+/// Send to make a mover go to `to` along its path, from 0 to 1.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct MoveMover {
+    pub mover: Entity,
+    pub to: f32,
+}
+
+/// Sent when a mover gets blocked.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct MoverBlocked {
+    pub mover: Entity,
+    /// The entity in the way.
+    pub by: Entity,
+}
+
+pub struct AstraliminalMoverPlugin;
+
+impl Plugin for AstraliminalMoverPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Mover>()
+            .register_type::<MoverState>()
+            .add_event::<MoveMover>()
+            .add_event::<MoverBlocked>()
+            .add_systems(
+                FixedUpdate,
+                (
+                    prepare_movers.in_set(AstralSet::Setup),
+                    (set_targets, drive_movers).chain().in_set(AstralSet::Move),
+                ),
+            );
+    }
+}
+
+/// Make new movers kinematic and remember where they were placed.
+#[allow(clippy::type_complexity)]
+fn prepare_movers(
+    mut commands: Commands,
+    movers: Query<(Entity, &Transform), (Added<Mover>, Without<MoverState>)>,
+) {
+    for (entity, transform) in &movers {
+        commands.entity(entity).insert((
+            RigidBody::Kinematic,
+            MoverState {
+                home: *transform,
+                ..default()
+            },
+        ));
+    }
+}
+
+/// Pick where every mover is going from its signal and `MoveMover` events.
+fn set_targets(
+    mut movers: Query<(&Mover, &mut MoverState, Option<Ref<LogicSignal>>)>,
+    mut move_events: EventReader<MoveMover>,
+) {
+    for (mover, mut state, signal) in &mut movers {
+        match mover.mode {
+            MoverMode::Signal => {
+                if let Some(signal) = signal.filter(Ref::is_changed) {
+                    state.target = if signal.0 { 1.0 } else { 0.0 };
+                }
+            }
+            MoverMode::PingPong => {
+                let running = signal.is_none_or(|signal| signal.0);
+                if !running {
+                    state.target = state.progress;
+                } else if state.progress == state.target {
+                    state.target = if state.progress >= 1.0 { 0.0 } else { 1.0 };
+                }
+            }
+        }
+    }
+
+    for event in move_events.read() {
+        if let Ok((_, mut state, _)) = movers.get_mut(event.mover) {
+            state.target = event.to.clamp(0.0, 1.0);
+        }
+    }
+}
+
+/// What is in the way of a mover or what it carries.
+type BlockerQuery<'w, 's> = Query<'w, 's, (Option<&'static RigidBody>, Has<Player>, Has<Sensor>)>;
+
+/// Whether an entity should stop a mover when it is in the way.
+fn stops_mover(entity: Entity, blockers: &BlockerQuery) -> bool {
+    blockers.get(entity).is_ok_and(|(body, is_player, sensor)| {
+        !sensor && (is_player || body.is_some_and(RigidBody::is_dynamic))
+    })
+}
+
+/// Whether an entity should stop a mover when something it carries would be pushed into it.
+fn stops_carried(entity: Entity, blockers: &BlockerQuery) -> bool {
+    blockers.get(entity).is_ok_and(|(_, _, sensor)| !sensor)
+}
+
+/// Apply the change from `from` to `to` to a transform riding along.
+pub(crate) fn carry(transform: &mut Transform, from: &Transform, to: &Transform) {
+    let rotation = to.rotation * from.rotation.inverse();
+    transform.translation = to.translation + rotation * (transform.translation - from.translation);
+    transform.rotation = rotation * transform.rotation;
+}
+
+/// Move every mover one step towards its target, along with what rests on it.
+#[allow(clippy::too_many_arguments)]
+fn drive_movers(
+    mut movers: Query<(Entity, &Mover, &mut MoverState, Option<&Collider>)>,
+    mut transforms: Query<&mut Transform>,
+    mut blocked_events: EventWriter<MoverBlocked>,
+    time: Res<Time>,
+    collisions: Res<Collisions>,
+    bodies: BodyQuery,
+    blockers: BlockerQuery,
+    colliders: Query<&Collider>,
+    held: Query<(), With<Held>>,
+    spatial_query: SpatialQuery,
+) {
+    for (entity, mover, mut state, collider) in &mut movers {
+        if state.progress == state.target {
+            continue;
+        }
+
+        let step = time.delta_seconds() / mover.duration.max(f32::EPSILON);
+        let progress = if state.target > state.progress {
+            (state.progress + step).min(state.target)
+        } else {
+            (state.progress - step).max(state.target)
+        };
+        let Ok(from) = transforms.get(entity).copied() else {
+            continue;
+        };
+        let to = mover.path.pose(&state.home, progress);
+
+        let carried: Vec<Entity> = resting_bodies(entity, &collisions, &bodies)
+            .into_iter()
+            .filter(|&body| !held.contains(body))
+            .collect();
+        let ignored = || {
+            SpatialQueryFilter::default()
+                .with_excluded_entities(carried.iter().copied().chain([entity]))
+        };
+
+        // The mover itself may not push into dynamic bodies or the player.
+        let mut blocker = collider.and_then(|collider| {
+            spatial_query
+                .shape_intersections(collider, to.translation, to.rotation, ignored())
+                .into_iter()
+                .find(|&other| stops_mover(other, &blockers))
+        });
+        // What it carries may not be pushed into anything.
+        for &body in &carried {
+            if blocker.is_some() {
+                break;
+            }
+            let (Ok(body_collider), Ok(body_transform)) =
+                (colliders.get(body), transforms.get(body))
+            else {
+                continue;
+            };
+            let mut moved = *body_transform;
+            carry(&mut moved, &from, &to);
+            // Shrunk a little, so what the body merely touches doesn't count.
+            let mut shape = body_collider.clone();
+            shape.set_scale(body_collider.scale() * CARRIED_SKIN, 8);
+            blocker = spatial_query
+                .shape_intersections(&shape, moved.translation, moved.rotation, ignored())
+                .into_iter()
+                .find(|&other| stops_carried(other, &blockers));
+        }
+
+        if let Some(by) = blocker {
+            if !state.blocked {
+                blocked_events.send(MoverBlocked { mover: entity, by });
+            }
+            state.blocked = true;
+            if mover.on_blocked == OnBlocked::Reverse {
+                state.target = if state.target > state.progress {
+                    0.0
+                } else {
+                    1.0
+                };
+            }
+            continue;
+        }
+
+        state.blocked = false;
+        state.progress = progress;
+        for &body in &carried {
+            if let Ok(mut transform) = transforms.get_mut(body) {
+                carry(&mut transform, &from, &to);
+            }
+        }
+        if let Ok(mut transform) = transforms.get_mut(entity) {
+            *transform = to;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{f32::consts::FRAC_PI_2, time::Duration};
+
+    use bevy::{ecs::event::ManualEventReader, time::TimeUpdateStrategy};
+
+    use super::*;
+
+    const STEP: Duration = Duration::from_millis(20);
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!(a.distance(b) < 1e-4, "{} is not {}", a, b);
+    }
+
+    #[test]
+    fn slide_and_hinge_poses() {
+        let home =
+            Transform::from_xyz(1.0, 0.0, 0.0).with_rotation(Quat::from_rotation_y(FRAC_PI_2));
+        let slide = MoverPath::Slide(Vec3::X * 2.0);
+        // The offset is in the mover's local space.
+        assert_near(
+            slide.pose(&home, 0.5).translation,
+            Vec3::new(1.0, 0.0, -1.0),
+        );
+        assert_eq!(slide.pose(&home, 0.0), home);
+        // Progress is clamped to the path.
+        assert_eq!(slide.pose(&home, 2.0), slide.pose(&home, 1.0));
+        assert_eq!(slide.pose(&home, -1.0), home);
+
+        let hinge = MoverPath::Hinge {
+            axis: Vec3::Y * 3.0,
+            angle: FRAC_PI_2,
+        };
+        let open = hinge.pose(&home, 1.0);
+        assert_eq!(open.translation, home.translation);
+        assert_near(open.rotation * Vec3::X, Vec3::NEG_X);
+        // A zero axis falls back to swinging around Y.
+        let no_axis = MoverPath::Hinge {
+            axis: Vec3::ZERO,
+            angle: FRAC_PI_2,
+        };
+        assert_near(no_axis.pose(&home, 1.0).rotation * Vec3::X, Vec3::NEG_X);
+    }
+
+    #[test]
+    fn spline_passes_through_its_points() {
+        let points = [Vec3::X, Vec3::new(1.0, 1.0, 0.0), Vec3::Y * 2.0];
+        assert_eq!(spline_point(&[], 0.5), Vec3::ZERO);
+        assert_near(spline_point(&points, 0.0), Vec3::ZERO);
+        assert_near(spline_point(&points, 1.0 / 3.0), points[0]);
+        assert_near(spline_point(&points, 2.0 / 3.0), points[1]);
+        assert_near(spline_point(&points, 1.0), points[2]);
+
+        // A straight line stays straight and moves evenly.
+        let line = [Vec3::X, Vec3::X * 2.0, Vec3::X * 3.0];
+        assert_near(spline_point(&line, 0.5), Vec3::X * 1.5);
+
+        let home = Transform::from_xyz(0.0, 5.0, 0.0);
+        let pose = MoverPath::Spline(points.to_vec()).pose(&home, 1.0);
+        assert_near(pose.translation, Vec3::new(0.0, 7.0, 0.0));
+    }
+
+    /// A headless app with physics and one tick per update.
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            HierarchyPlugin,
+            TransformPlugin,
+            PhysicsPlugins::new(FixedPostUpdate),
+            AstraliminalMoverPlugin,
+        ))
+        .insert_resource(Time::<Fixed>::from_duration(STEP))
+        .insert_resource(Time::new_with(Physics::fixed_once_hz(
+            1.0 / STEP.as_secs_f64(),
+        )))
+        .insert_resource(TimeUpdateStrategy::ManualDuration(STEP));
+        // Static ground, clear of everything that moves.
+        app.world.spawn((
+            RigidBody::Static,
+            Collider::cuboid(40.0, 1.0, 40.0),
+            TransformBundle::from_transform(Transform::from_xyz(0.0, -2.0, 0.0)),
+        ));
+        app
+    }
+
+    fn spawn_box(app: &mut App, at: Vec3) -> Entity {
+        app.world
+            .spawn((
+                RigidBody::Dynamic,
+                Collider::cuboid(1.0, 1.0, 1.0),
+                TransformBundle::from_transform(Transform::from_translation(at)),
+            ))
+            .id()
+    }
+
+    fn spawn_mover(app: &mut App, mover: Mover, size: Vec3, at: Vec3) -> Entity {
+        app.world
+            .spawn((
+                mover,
+                Collider::cuboid(size.x, size.y, size.z),
+                TransformBundle::from_transform(Transform::from_translation(at)),
+            ))
+            .id()
+    }
+
+    /// Start `mover` towards the end of its path.
+    fn start(app: &mut App, mover: Entity) {
+        app.world.send_event(MoveMover { mover, to: 1.0 });
+    }
+
+    fn translation(app: &App, entity: Entity) -> Vec3 {
+        app.world.get::<Transform>(entity).unwrap().translation
+    }
+
+    fn state(app: &App, mover: Entity) -> MoverState {
+        *app.world.get::<MoverState>(mover).unwrap()
+    }
+
+    /// A platform sliding 4 units along X in 1 second, with a box resting on top.
+    fn platform(app: &mut App) -> (Entity, Entity) {
+        let mover = Mover {
+            path: MoverPath::Slide(Vec3::X * 4.0),
+            duration: 1.0,
+            ..default()
+        };
+        let platform = spawn_mover(app, mover, Vec3::new(4.0, 0.5, 4.0), Vec3::ZERO);
+        let box_ = spawn_box(app, Vec3::new(0.0, 0.75, 0.0));
+        // Let the box settle before the platform starts.
+        for _ in 0..5 {
+            app.update();
+        }
+        (platform, box_)
+    }
+
+    #[test]
+    fn carries_bodies_without_jitter() {
+        let mut app = app();
+        let (platform, box_) = platform(&mut app);
+        let offset = translation(&app, box_) - translation(&app, platform);
+
+        start(&mut app, platform);
+        for _ in 0..60 {
+            app.update();
+            let riding = translation(&app, box_) - translation(&app, platform);
+            assert!(riding.distance(offset) < 0.02, "box slid to {}", riding);
+        }
+        assert_eq!(state(&app, platform).progress, 1.0);
+        assert!((translation(&app, box_).x - 4.0).abs() < 0.02);
+    }
+
+    #[test]
+    fn carries_bodies_through_sensors() {
+        let mut app = app();
+        // A trigger the box passes through on its way, clear of the platform itself.
+        app.world.spawn((
+            Sensor,
+            Collider::cuboid(1.0, 2.0, 4.0),
+            TransformBundle::from_transform(Transform::from_xyz(2.0, 1.75, 0.0)),
+        ));
+        let (platform, box_) = platform(&mut app);
+        let mut blocked = ManualEventReader::<MoverBlocked>::default();
+
+        start(&mut app, platform);
+        for _ in 0..60 {
+            app.update();
+            let events = app.world.resource::<Events<MoverBlocked>>();
+            assert_eq!(blocked.read(events).count(), 0);
+        }
+        assert_eq!(state(&app, platform).progress, 1.0);
+        assert!((translation(&app, box_).x - 4.0).abs() < 0.02);
+    }
+
+    /// A door sliding 4 units along X in 1 second, with a box in the way at `x = 3`.
+    fn blocked_door(app: &mut App, on_blocked: OnBlocked) -> (Entity, Entity) {
+        let mover = Mover {
+            path: MoverPath::Slide(Vec3::X * 4.0),
+            duration: 1.0,
+            on_blocked,
+            ..default()
+        };
+        let door = spawn_mover(app, mover, Vec3::new(1.0, 2.0, 1.0), Vec3::Y);
+        let box_ = spawn_box(app, Vec3::new(3.0, 1.0, 0.0));
+        app.world.entity_mut(box_).insert(GravityScale(0.0));
+        for _ in 0..5 {
+            app.update();
+        }
+        (door, box_)
+    }
+
+    /// Run until the door is blocked, and return the `MoverBlocked` events sent by then.
+    fn run_into_box(app: &mut App, door: Entity) -> Vec<MoverBlocked> {
+        let mut reader = ManualEventReader::<MoverBlocked>::default();
+        let mut events = Vec::new();
+        start(app, door);
+        for _ in 0..60 {
+            app.update();
+            events.extend(
+                reader
+                    .read(app.world.resource::<Events<MoverBlocked>>())
+                    .copied(),
+            );
+        }
+        events
+    }
+
+    #[test]
+    fn doors_stop_instead_of_crushing() {
+        let mut app = app();
+        let (door, box_) = blocked_door(&mut app, OnBlocked::Stop);
+
+        let events = run_into_box(&mut app, door);
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0].mover, events[0].by), (door, box_));
+
+        let state = state(&app, door);
+        assert!(state.blocked);
+        assert_eq!(state.target, 1.0);
+        // The door stops at the box, which stays where it was.
+        assert!(state.progress > 0.4);
+        assert!(translation(&app, door).x + 0.5 <= 2.5 + 0.01);
+        assert!(translation(&app, box_).distance(Vec3::new(3.0, 1.0, 0.0)) < 0.02);
+    }
+
+    #[test]
+    fn doors_reverse_when_blocked() {
+        let mut app = app();
+        let (door, box_) = blocked_door(&mut app, OnBlocked::Reverse);
+
+        let events = run_into_box(&mut app, door);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].by, box_);
+
+        let state = state(&app, door);
+        assert!(!state.blocked);
+        assert_eq!((state.progress, state.target), (0.0, 0.0));
+        assert!(translation(&app, box_).distance(Vec3::new(3.0, 1.0, 0.0)) < 0.02);
+    }
+}
@@ -0,0 +1,433 @@
+//! Astraliminal's New Game plugin.
+//!
+//! Send `StartNewGame` to start a fresh run. Achievements and the endings seen are always kept. A
+//! New Game+ also keeps what its `CarryOver` names, e.g. flags for mechanics the player unlocked,
+//! and can start at the branch point of an ending the player has seen, e.g. `Z.a` after the Zeta
+//! Dimension.
+//!
+//! The run starts with a level change to its first level. The save data is only replaced once the
+//! old level is gone, and the new run is only saved once the player stands in the new level, so
+//! nothing of the old run is written back into it.
+//!
+//! A New Game+ sets the `new_game_plus` story flag and counts runs in the `playthrough` variable,
+//! so dialogue can acknowledge repeat visits, e.g. with `flag(new_game_plus) && seen_ending(zeta)`.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    dialogue::{Dialogue, DialogueScript, LevelDialogue, StartDialogue},
+    ending::{Endings, GameEndings},
+    expression::Value,
+    level::{ChangeLevel, GameState, LevelLoaded},
+    save::{SaveData, SaveGame, SaveLoaded},
+};
+
+/// Story flag set in every New Game+.
+const PLUS_FLAG: &str = "new_game_plus";
+/// Story variable counting runs, from 2 for the first New Game+.
+const PLAYTHROUGH_VARIABLE: &str = "playthrough";
+
+/// What a New Game+ keeps from the run before it, besides achievements and endings seen.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CarryOver {
+    /// Story flags kept, e.g. mechanics the player unlocked such as `can_shift_dimensions`.
+    pub flags: Vec<String>,
+    /// Story variables kept.
+    pub variables: Vec<String>,
+    /// Whether NPCs remember their conversations with the player.
+    pub npc_memories: bool,
+}
+
+/// Send to start a fresh run.
+#[derive(Event, Debug, Clone, PartialEq, Eq)]
+pub struct StartNewGame {
+    /// The level the run starts in, and where in it.
+    pub level: ChangeLevel,
+    /// Start a New Game+ keeping this, or a plain new game without.
+    pub plus: Option<CarryOver>,
+    /// Id of an ending the player has seen whose branch point a New Game+ starts at.
+    pub from_ending: Option<String>,
+}
+
+/// Sent when a new run has started.
+#[derive(Event, Debug, Clone, PartialEq, Eq)]
+pub struct NewGameStarted {
+    pub plus: bool,
+    /// Dialogue node the run starts at, if not the beginning.
+    pub branch_point: Option<String>,
+}
+
+/// Dialogue node the run starts at, until the conversation there has started.
+#[derive(Resource, Debug, Default, Clone, PartialEq, Eq)]
+pub struct BranchStart(pub Option<String>);
+
+/// A new run being started.
+#[derive(Resource, Debug, Default, Clone, PartialEq)]
+enum PendingNewGame {
+    #[default]
+    None,
+    /// Waiting for the old level to unload.
+    Unloading {
+        plus: Option<CarryOver>,
+        branch_point: Option<String>,
+    },
+    /// Save data replaced, waiting for the new level.
+    Loading(NewGameStarted),
+}
+
+/// The save data of a new run after the one saved in `previous`.
+pub fn fresh_save(previous: &SaveData, plus: Option<&CarryOver>) -> SaveData {
+    let mut save = SaveData {
+        achievements: previous.achievements.clone(),
+        endings_seen: previous.endings_seen.clone(),
+        ..default()
+    };
+    let Some(carry_over) = plus else {
+        return save;
+    };
+
+    save.story_flags = carry_over
+        .flags
+        .iter()
+        .filter(|flag| previous.story_flags.contains(*flag))
+        .cloned()
+        .collect();
+    save.story_flags.insert(PLUS_FLAG.to_string());
+    save.variables = carry_over
+        .variables
+        .iter()
+        .filter_map(|name| Some((name.clone(), previous.variables.get(name)?.clone())))
+        .collect();
+    let playthrough = match previous.variables.get(PLAYTHROUGH_VARIABLE) {
+        Some(Value::Int(playthrough)) => playthrough + 1,
+        _ => 2,
+    };
+    save.variables
+        .insert(PLAYTHROUGH_VARIABLE.to_string(), Value::Int(playthrough));
+    if carry_over.npc_memories {
+        save.npcs = previous.npcs.clone();
+    }
+    save
+}
+
+pub struct AstraliminalNewGamePlugin;
+
+impl Plugin for AstraliminalNewGamePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BranchStart>()
+            .init_resource::<PendingNewGame>()
+            .add_event::<StartNewGame>()
+            .add_event::<NewGameStarted>()
+            .add_systems(OnEnter(GameState::Loading), replace_save)
+            .add_systems(
+                Update,
+                (
+                    start_new_game,
+                    save_new_game.run_if(on_event::<LevelLoaded>()),
+                    start_at_branch_point.run_if(resource_exists::<LevelDialogue>),
+                ),
+            );
+    }
+}
+
+/// Start a fresh run by changing to its first level.
+fn start_new_game(
+    mut requests: EventReader<StartNewGame>,
+    mut levels: EventWriter<ChangeLevel>,
+    mut pending: ResMut<PendingNewGame>,
+    save: Res<SaveData>,
+    game: Option<Res<GameEndings>>,
+    endings: Res<Assets<Endings>>,
+) {
+    let Some(request) = requests.read().last() else {
+        return;
+    };
+
+    let branch_point = request.from_ending.as_ref().and_then(|id| {
+        if request.plus.is_none() {
+            warn!("Only a New Game+ can start from ending {}", id);
+            return None;
+        }
+        if !save.endings_seen.contains(id) {
+            warn!(
+                "Ending {} has not been seen, starting from the beginning",
+                id
+            );
+            return None;
+        }
+        let ending = game
+            .as_ref()
+            .and_then(|game| endings.get(&game.0))
+            .and_then(|endings| endings.ending(id));
+        let branch_point = ending.and_then(|ending| ending.branch_point.clone());
+        if branch_point.is_none() {
+            warn!(
+                "Ending {} has no branch point, starting from the beginning",
+                id
+            );
+        }
+        branch_point
+    });
+
+    info!(
+        "Starting a new game{}",
+        if request.plus.is_some() { "+" } else { "" }
+    );
+    levels.send(request.level.clone());
+    *pending = PendingNewGame::Unloading {
+        plus: request.plus.clone(),
+        branch_point,
+    };
+}
+
+/// Replace the save data with a fresh run's once the old level is gone, and restore the world
+/// from it.
+fn replace_save(
+    mut pending: ResMut<PendingNewGame>,
+    mut loaded: EventWriter<SaveLoaded>,
+    mut save: ResMut<SaveData>,
+    mut branch: ResMut<BranchStart>,
+) {
+    let PendingNewGame::Unloading { plus, branch_point } = &*pending else {
+        return;
+    };
+    *save = fresh_save(&save, plus.as_ref());
+    branch.0 = branch_point.clone();
+    loaded.send(SaveLoaded);
+    *pending = PendingNewGame::Loading(NewGameStarted {
+        plus: plus.is_some(),
+        branch_point: branch_point.clone(),
+    });
+}
+
+/// Save the new run once the player stands in its first level.
+fn save_new_game(
+    mut pending: ResMut<PendingNewGame>,
+    mut saves: EventWriter<SaveGame>,
+    mut started: EventWriter<NewGameStarted>,
+) {
+    // Only a new run's level change is waited for.
+    match std::mem::take(&mut *pending) {
+        PendingNewGame::Loading(event) => {
+            saves.send(SaveGame);
+            started.send(event);
+        }
+        other => *pending = other,
+    }
+}
+
+/// Start the conversation at the branch point once the level's dialogue has loaded.
+fn start_at_branch_point(
+    mut branch: ResMut<BranchStart>,
+    mut starts: EventWriter<StartDialogue>,
+    dialogue: Res<Dialogue>,
+    level: Res<LevelDialogue>,
+    scripts: Res<Assets<DialogueScript>>,
+) {
+    if branch.0.is_none() || dialogue.is_active() || !scripts.contains(&level.0) {
+        return;
+    }
+    if let Some(node) = branch.0.take() {
+        starts.send(StartDialogue { node, with: None });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::{ending::Ending, save::SavedNpc};
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    /// The save of a run that unlocked a mechanic, reached two endings and talked to an NPC.
+    fn previous() -> SaveData {
+        SaveData {
+            dimension: 1,
+            story_flags: names(&["can_shift_dimensions", "met_zeta"])
+                .into_iter()
+                .collect(),
+            variables: [
+                ("keys".to_string(), Value::Int(3)),
+                ("mood".to_string(), Value::Int(-1)),
+            ]
+            .into(),
+            visited_dialogue: names(&["Z.a"]).into_iter().collect(),
+            achievements: names(&["first_steps"]).into_iter().collect(),
+            endings_seen: names(&["zeta", "boat"]).into_iter().collect(),
+            npcs: [(
+                "zeta".to_string(),
+                SavedNpc {
+                    talks: 2,
+                    next: Some("Z.b".to_string()),
+                },
+            )]
+            .into(),
+            ..default()
+        }
+    }
+
+    #[test]
+    fn new_games_keep_only_achievements_and_endings() {
+        let previous = previous();
+        let save = fresh_save(&previous, None);
+        assert_eq!(
+            save,
+            SaveData {
+                achievements: previous.achievements.clone(),
+                endings_seen: previous.endings_seen.clone(),
+                ..default()
+            }
+        );
+    }
+
+    #[test]
+    fn new_game_plus_keeps_what_it_carries_over() {
+        let previous = previous();
+        let carry_over = CarryOver {
+            flags: names(&["can_shift_dimensions", "never_set"]),
+            variables: names(&["keys", "never_set"]),
+            npc_memories: false,
+        };
+        let save = fresh_save(&previous, Some(&carry_over));
+
+        assert_eq!(
+            save.story_flags,
+            names(&["can_shift_dimensions", PLUS_FLAG])
+                .into_iter()
+                .collect()
+        );
+        assert_eq!(
+            save.variables,
+            [
+                ("keys".to_string(), Value::Int(3)),
+                (PLAYTHROUGH_VARIABLE.to_string(), Value::Int(2)),
+            ]
+            .into()
+        );
+        assert!(save.npcs.is_empty());
+        assert!(save.visited_dialogue.is_empty());
+        assert_eq!(save.dimension, 0);
+        assert_eq!(save.achievements, previous.achievements);
+        assert_eq!(save.endings_seen, previous.endings_seen);
+
+        let remembering = CarryOver {
+            npc_memories: true,
+            ..default()
+        };
+        assert_eq!(
+            fresh_save(&previous, Some(&remembering)).npcs,
+            previous.npcs
+        );
+    }
+
+    #[test]
+    fn playthroughs_count_up() {
+        let plus = CarryOver::default();
+        let second = fresh_save(&previous(), Some(&plus));
+        let third = fresh_save(&second, Some(&plus));
+        let fourth = fresh_save(&third, Some(&plus));
+        assert_eq!(
+            fourth.variables.get(PLAYTHROUGH_VARIABLE),
+            Some(&Value::Int(4))
+        );
+        assert!(fourth.story_flags.contains(PLUS_FLAG));
+
+        // A plain new game starts counting again.
+        let plain = fresh_save(&fourth, None);
+        assert!(plain.variables.is_empty());
+        assert!(plain.story_flags.is_empty());
+    }
+
+    /// The branch point a new game from `ending` starts at, after `previous()`.
+    fn branch_point(ending: &str, plus: bool) -> Option<String> {
+        let mut app = App::new();
+        app.add_event::<StartNewGame>()
+            .add_event::<ChangeLevel>()
+            .init_resource::<PendingNewGame>()
+            .init_resource::<Assets<Endings>>()
+            .insert_resource(previous());
+        let endings = app.world.resource_mut::<Assets<Endings>>().add(Endings {
+            endings: vec![
+                Ending {
+                    id: "zeta".to_string(),
+                    branch_point: Some("Z.a".to_string()),
+                    ..default()
+                },
+                Ending {
+                    id: "island".to_string(),
+                    branch_point: Some("I.a".to_string()),
+                    ..default()
+                },
+                Ending {
+                    id: "boat".to_string(),
+                    ..default()
+                },
+            ],
+        });
+        app.insert_resource(GameEndings(endings));
+
+        app.world.send_event(StartNewGame {
+            level: ChangeLevel {
+                level: "levels/island.level.ron".to_string(),
+                spawn: None,
+            },
+            plus: plus.then(CarryOver::default),
+            from_ending: Some(ending.to_string()),
+        });
+        app.world.run_system_once(start_new_game);
+        match app.world.resource::<PendingNewGame>() {
+            PendingNewGame::Unloading { branch_point, .. } => branch_point.clone(),
+            pending => panic!("new game not started: {:?}", pending),
+        }
+    }
+
+    #[test]
+    fn new_game_plus_starts_at_branch_points_of_seen_endings() {
+        assert_eq!(branch_point("zeta", true), Some("Z.a".to_string()));
+        // Not seen yet.
+        assert_eq!(branch_point("island", true), None);
+        // Seen, but without a branch point.
+        assert_eq!(branch_point("boat", true), None);
+        assert_eq!(branch_point("cut", true), None);
+        // Only a New Game+ skips ahead.
+        assert_eq!(branch_point("zeta", false), None);
+    }
+
+    #[test]
+    fn other_level_loads_keep_the_new_game_waiting() {
+        let mut world = World::new();
+        world.init_resource::<Events<SaveGame>>();
+        world.init_resource::<Events<NewGameStarted>>();
+        let unloading = PendingNewGame::Unloading {
+            plus: None,
+            branch_point: None,
+        };
+        world.insert_resource(unloading.clone());
+
+        world.run_system_once(save_new_game);
+        assert_eq!(*world.resource::<PendingNewGame>(), unloading);
+        assert!(world.resource::<Events<SaveGame>>().is_empty());
+
+        let started = NewGameStarted {
+            plus: true,
+            branch_point: None,
+        };
+        world.insert_resource(PendingNewGame::Loading(started.clone()));
+        world.run_system_once(save_new_game);
+        assert_eq!(*world.resource::<PendingNewGame>(), PendingNewGame::None);
+        assert_eq!(world.resource::<Events<SaveGame>>().len(), 1);
+        let mut reader = world.resource::<Events<NewGameStarted>>().get_reader();
+        assert_eq!(
+            reader
+                .read(world.resource::<Events<NewGameStarted>>())
+                .collect::<Vec<_>>(),
+            vec![&started]
+        );
+    }
+}
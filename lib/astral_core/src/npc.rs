@@ -0,0 +1,335 @@
+//! Astraliminal's NPC plugin.
+//!
+//! An `Npc` is someone the player can talk to. Looking at them within `talk_radius` and pressing
+//! the interact key starts a conversation: at their `dialogue` node the first time, and after
+//! that wherever the script's `next_time` moved them on to, so the rower can answer "We're close
+//! to our destination" when spoken to again. What each NPC remembers is saved with the game.
+//!
+//! NPCs turn their head towards the player when near, face the player while talking and switch
+//! between their idle animations every few seconds.
+
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+
+use crate::{
+    dialogue::{Dialogue, DialogueEnded, DialogueScript, LevelDialogue, StartDialogue},
+    interact::{Interactable, Interacted},
+    player::{Player, PlayerCamera},
+    replay::RngSeed,
+    save::{SaveData, SaveSet, SavedNpc},
+};
+
+/// How fast heads follow the player, per second.
+const HEAD_SPEED: f32 = 6.0;
+/// How fast NPCs turn to face the player they talk to, in radians per second.
+const TURN_SPEED: f32 = 3.0;
+
+/// Someone the player can talk to. Needs a `Collider` to be looked at; an `Interactable` is added
+/// for you. NPCs face their local -Z.
+#[derive(Component, Reflect, Debug, Clone)]
+#[reflect(Component)]
+pub struct Npc {
+    /// Unique id, used to remember conversations.
+    pub id: String,
+    /// Name shown to the player.
+    pub name: String,
+    /// Dialogue node the first conversation starts at.
+    pub dialogue: String,
+    /// Largest distance from the camera at which the player can talk to them.
+    pub talk_radius: f32,
+    /// Largest distance at which they look at the player.
+    pub look_radius: f32,
+    /// `Name` of the head among their descendants, e.g. a bone. It turns around its local Y and X
+    /// axes, so its rest pose must face the same way as the NPC.
+    pub head: String,
+    /// Largest angle, in radians, the head turns away from where the body faces.
+    pub head_limit: f32,
+    /// Animation clips played in turn while idle, e.g. `"characters/rower.glb#Animation0"`.
+    pub idles: Vec<String>,
+    /// Animation clip played while talking.
+    pub talk_animation: Option<String>,
+    /// Seconds between idle animation changes.
+    pub idle_seconds: f32,
+}
+
+impl Default for Npc {
+    fn default() -> Self {
+        Self {
+            id: String::new(),
+            name: String::new(),
+            dialogue: String::new(),
+            talk_radius: 3.0,
+            look_radius: 6.0,
+            head: "Head".to_string(),
+            head_limit: 70.0_f32.to_radians(),
+            idles: Vec::new(),
+            talk_animation: None,
+            idle_seconds: 8.0,
+        }
+    }
+}
+
+/// What each NPC remembers of talking to the player, keyed by NPC id.
+#[derive(Resource, Debug, Default, Clone, PartialEq, Eq)]
+pub struct NpcMemories(pub BTreeMap<String, SavedNpc>);
+
+impl NpcMemories {
+    /// Whether the player has finished a conversation with the NPC.
+    pub fn talked_to(&self, id: &str) -> bool {
+        self.0.get(id).is_some_and(|memory| memory.talks > 0)
+    }
+
+    /// The dialogue node the next conversation with `npc` starts at.
+    pub fn start_node<'a>(&'a self, npc: &'a Npc) -> &'a str {
+        self.0
+            .get(&npc.id)
+            .and_then(|memory| memory.next.as_deref())
+            .unwrap_or(&npc.dialogue)
+    }
+}
+
+/// Where an NPC's head and animations are, added for you.
+#[derive(Component, Debug, Default, Clone)]
+struct NpcState {
+    /// The head entity and its rest rotation, once found.
+    head: Option<(Entity, Quat)>,
+    /// How the head is turned from its rest pose.
+    head_turn: Quat,
+    /// The animation clip playing.
+    animation: Option<String>,
+    /// Seconds until the next idle animation.
+    idle_left: f32,
+    /// Idle animations picked so far.
+    picks: u64,
+}
+
+pub struct AstraliminalNpcPlugin;
+
+impl Plugin for AstraliminalNpcPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Npc>()
+            .init_resource::<NpcMemories>()
+            .add_systems(PreUpdate, restore_memories.in_set(SaveSet::Restore))
+            .add_systems(
+                Update,
+                (prepare_npcs, talk, remember, look_at_player, animate).chain(),
+            )
+            .add_systems(Last, collect_memories.in_set(SaveSet::Collect));
+    }
+}
+
+/// Start with the memories from the save data.
+fn restore_memories(mut memories: ResMut<NpcMemories>, save: Res<SaveData>) {
+    memories.0 = save.npcs.clone();
+}
+
+/// Copy the memories into the save data.
+fn collect_memories(mut save: ResMut<SaveData>, memories: Res<NpcMemories>) {
+    save.npcs = memories.0.clone();
+}
+
+/// Make new NPCs interactable.
+fn prepare_npcs(
+    mut commands: Commands,
+    npcs: Query<(Entity, &Npc, Has<Interactable>), Added<Npc>>,
+) {
+    for (entity, npc, interactable) in &npcs {
+        let mut entity = commands.entity(entity);
+        entity.insert(NpcState::default());
+        if !interactable {
+            entity.insert(Interactable {
+                id: npc.id.clone(),
+                prompt: format!("Talk to {}", npc.name),
+                range: npc.talk_radius,
+            });
+        }
+    }
+}
+
+/// Start a conversation with NPCs the player interacts with.
+fn talk(
+    mut interacted: EventReader<Interacted>,
+    mut starts: EventWriter<StartDialogue>,
+    dialogue: Option<Res<Dialogue>>,
+    memories: Res<NpcMemories>,
+    npcs: Query<&Npc>,
+) {
+    for event in interacted.read() {
+        let Ok(npc) = npcs.get(event.entity) else {
+            continue;
+        };
+        if dialogue
+            .as_ref()
+            .is_some_and(|dialogue| dialogue.is_active())
+        {
+            continue;
+        }
+        starts.send(StartDialogue {
+            node: memories.start_node(npc).to_string(),
+            with: Some(event.entity),
+        });
+    }
+}
+
+/// Remember finished conversations and where the script says the next one starts.
+fn remember(
+    mut ended: EventReader<DialogueEnded>,
+    mut memories: ResMut<NpcMemories>,
+    level: Option<Res<LevelDialogue>>,
+    scripts: Res<Assets<DialogueScript>>,
+    npcs: Query<&Npc>,
+) {
+    let script = level.and_then(|level| scripts.get(&level.0));
+    for event in ended.read() {
+        let Some(npc) = event.with.and_then(|entity| npcs.get(entity).ok()) else {
+            continue;
+        };
+        let memory = memories.0.entry(npc.id.clone()).or_default();
+        memory.talks += 1;
+        let next = script.and_then(|script| {
+            event
+                .path
+                .iter()
+                .rev()
+                .find_map(|id| script.node(id)?.next_time.clone())
+        });
+        if next.is_some() {
+            memory.next = next;
+        }
+    }
+}
+
+/// Turn heads towards the player, and NPCs towards the player they talk to.
+#[allow(clippy::too_many_arguments)]
+fn look_at_player(
+    mut npcs: Query<(Entity, &Npc, &mut NpcState, &GlobalTransform)>,
+    mut transforms: Query<&mut Transform>,
+    globals: Query<&GlobalTransform>,
+    names: Query<(Entity, &Name)>,
+    children: Query<&Children>,
+    cameras: Query<&GlobalTransform, With<PlayerCamera>>,
+    players: Query<&GlobalTransform, With<Player>>,
+    dialogue: Option<Res<Dialogue>>,
+    time: Res<Time>,
+) {
+    let Some(eye) = cameras
+        .iter()
+        .next()
+        .or_else(|| players.iter().next())
+        .map(GlobalTransform::translation)
+    else {
+        return;
+    };
+    let talking_to = dialogue
+        .as_ref()
+        .and_then(|dialogue| dialogue.conversation())
+        .and_then(|conversation| conversation.with);
+    let delta = time.delta_seconds();
+
+    for (entity, npc, mut state, body) in &mut npcs {
+        let (_, body_rotation, body_position) = body.to_scale_rotation_translation();
+
+        if talking_to == Some(entity) {
+            let local = body_rotation.inverse() * (eye - body_position);
+            let yaw = (-local.x).atan2(-local.z);
+            let step = yaw.clamp(-TURN_SPEED * delta, TURN_SPEED * delta);
+            if let Ok(mut transform) = transforms.get_mut(entity) {
+                transform.rotate_local_y(step);
+            }
+        }
+
+        if state.head.is_none() {
+            state.head = children
+                .iter_descendants(entity)
+                .find(|&child| {
+                    names
+                        .get(child)
+                        .is_ok_and(|(_, name)| name.as_str() == npc.head)
+                })
+                .and_then(|head| Some((head, transforms.get(head).ok()?.rotation)));
+        }
+        let Some((head, rest)) = state.head else {
+            continue;
+        };
+        let Ok(head_position) = globals.get(head).map(GlobalTransform::translation) else {
+            continue;
+        };
+
+        let local = body_rotation.inverse() * (eye - head_position);
+        let yaw = (-local.x).atan2(-local.z);
+        let pitch = local.y.atan2(local.xz().length());
+        let desired = if eye.distance(head_position) <= npc.look_radius {
+            let limit = npc.head_limit;
+            Quat::from_euler(
+                EulerRot::YXZ,
+                yaw.clamp(-limit, limit),
+                pitch.clamp(-limit, limit),
+                0.0,
+            )
+        } else {
+            Quat::IDENTITY
+        };
+        state.head_turn = state
+            .head_turn
+            .slerp(desired, 1.0 - (-HEAD_SPEED * delta).exp());
+        if let Ok(mut transform) = transforms.get_mut(head) {
+            transform.rotation = rest * state.head_turn;
+        }
+    }
+}
+
+/// Play the talk animation while talking, and switch between idle animations otherwise.
+fn animate(
+    mut npcs: Query<(Entity, &Npc, &mut NpcState)>,
+    mut animation_players: Query<&mut AnimationPlayer>,
+    children: Query<&Children>,
+    asset_server: Res<AssetServer>,
+    dialogue: Option<Res<Dialogue>>,
+    seed: Option<Res<RngSeed>>,
+    time: Res<Time>,
+) {
+    let talking_to = dialogue
+        .as_ref()
+        .and_then(|dialogue| dialogue.conversation())
+        .and_then(|conversation| conversation.with);
+    let seed = seed.map(|seed| *seed).unwrap_or_default();
+
+    for (entity, npc, mut state) in &mut npcs {
+        state.idle_left -= time.delta_seconds();
+        let talking = talking_to == Some(entity) && npc.talk_animation.is_some();
+        let idling = state
+            .animation
+            .as_ref()
+            .is_some_and(|animation| npc.idles.contains(animation));
+
+        let wanted = if talking {
+            npc.talk_animation.clone()
+        } else if npc.idles.is_empty() || (idling && state.idle_left > 0.0) {
+            continue;
+        } else {
+            let roll = seed.roll(&npc.id, state.picks);
+            state.picks += 1;
+            state.idle_left = npc.idle_seconds;
+            npc.idles
+                .get((roll % npc.idles.len() as u64) as usize)
+                .cloned()
+        };
+        if wanted == state.animation {
+            continue;
+        }
+
+        if let Some(path) = &wanted {
+            let clip: Handle<AnimationClip> = asset_server.load(path.clone());
+            let entities = std::iter::once(entity)
+                .chain(children.iter_descendants(entity))
+                .collect::<Vec<_>>();
+            for entity in entities {
+                if let Ok(mut player) = animation_players.get_mut(entity) {
+                    player.play(clip.clone()).repeat();
+                }
+            }
+        }
+        state.animation = wanted;
+    }
+}
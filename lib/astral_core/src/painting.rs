@@ -0,0 +1,231 @@
+//! Astraliminal's Painting plugin.
+//!
+//! A trompe-l'oeil painting is a flat entity that shows an object as seen from its `Viewpoint`.
+//! Grabbing the painting while aligned with the viewpoint replaces it with the real object,
+//! placed along the same view rays so its silhouette does not change on screen.
+
+use bevy::prelude::*;
+
+use crate::{
+    level::LevelEntity,
+    player::{Grab, PlayerCamera},
+    save::{SaveData, SaveGame},
+    schedule::AstralSet,
+    viewpoint::ViewpointAligned,
+};
+
+/// A flat painting that can become a real object. Requires a `Viewpoint` and a collider so it
+/// can be grabbed.
+#[derive(Component, Reflect, Debug, Clone)]
+#[reflect(Component)]
+pub struct Painting {
+    /// Unique id, used to remember in the save data that this painting was materialized.
+    pub id: String,
+    /// Asset path of the scene to spawn, e.g. `levels/gallery.glb#Scene1`.
+    pub scene: String,
+    /// Transform of the object relative to the painting so that it sits on the canvas with the
+    /// same outline as the painted image.
+    pub object: Transform,
+    /// Distance from the camera at which the real object appears. The object is scaled so it
+    /// covers exactly what the painting covered.
+    pub depth: f32,
+}
+
+impl Painting {
+    /// The world transform of the real object when seen from `eye`.
+    ///
+    /// The object is first placed on the canvas, then pushed along the rays through `eye` until
+    /// the canvas would be `depth` away. Scaling by the same ratio keeps the projection the same.
+    pub fn materialized_transform(&self, eye: Vec3, painting: &GlobalTransform) -> Transform {
+        let on_canvas = painting.mul_transform(self.object).compute_transform();
+        let distance = eye.distance(painting.translation());
+        let ratio = if distance > f32::EPSILON {
+            self.depth / distance
+        } else {
+            1.0
+        };
+
+        Transform {
+            translation: eye + (on_canvas.translation - eye) * ratio,
+            rotation: on_canvas.rotation,
+            scale: on_canvas.scale * ratio,
+        }
+    }
+}
+
+/// Marker for an object spawned from a painting.
+#[derive(Component, Reflect, Debug, Default, Clone)]
+#[reflect(Component)]
+pub struct Materialized {
+    /// The id of the painting the object came from.
+    pub painting: String,
+}
+
+/// Sent when a painting turns into a real object.
+#[derive(Event, Debug, Clone)]
+pub struct PaintingMaterialized {
+    /// The id of the painting.
+    pub id: String,
+    /// The spawned object.
+    pub entity: Entity,
+}
+
+pub struct AstraliminalPaintingPlugin;
+
+impl Plugin for AstraliminalPaintingPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Painting>()
+            .register_type::<Materialized>()
+            .add_event::<PaintingMaterialized>()
+            .add_systems(
+                FixedUpdate,
+                (
+                    restore_materialized.in_set(AstralSet::Setup),
+                    materialize.in_set(AstralSet::React),
+                ),
+            );
+    }
+}
+
+/// Spawn the real object for a painting.
+fn spawn_object(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    painting: &Painting,
+    transform: Transform,
+) -> Entity {
+    commands
+        .spawn((
+            SceneBundle {
+                scene: asset_server.load(&painting.scene),
+                transform,
+                ..default()
+            },
+            Materialized {
+                painting: painting.id.clone(),
+            },
+            Name::new(painting.id.clone()),
+            LevelEntity,
+        ))
+        .id()
+}
+
+/// Paintings that were materialized in an earlier session are replaced as soon as they spawn.
+fn restore_materialized(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    save: Res<SaveData>,
+    paintings: Query<(Entity, &Painting), Added<Painting>>,
+) {
+    for (entity, painting) in &paintings {
+        if let Some(transform) = save.materialized_paintings.get(&painting.id) {
+            spawn_object(&mut commands, &asset_server, painting, *transform);
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+/// Replace grabbed, aligned paintings with the objects they depict.
+#[allow(clippy::too_many_arguments)]
+fn materialize(
+    mut commands: Commands,
+    mut grabs: EventReader<Grab>,
+    mut materialized: EventWriter<PaintingMaterialized>,
+    mut save_game: EventWriter<SaveGame>,
+    mut save: ResMut<SaveData>,
+    asset_server: Res<AssetServer>,
+    camera: Query<&GlobalTransform, With<PlayerCamera>>,
+    paintings: Query<(&Painting, &GlobalTransform), With<ViewpointAligned>>,
+) {
+    let Ok(camera) = camera.get_single() else {
+        grabs.clear();
+        return;
+    };
+
+    for grab in grabs.read() {
+        let Ok((painting, transform)) = paintings.get(grab.entity) else {
+            continue;
+        };
+
+        // Grabbing the same painting twice in a frame must not spawn two objects.
+        if save.materialized_paintings.contains_key(&painting.id) {
+            continue;
+        }
+
+        let object_transform = painting.materialized_transform(camera.translation(), transform);
+        let entity = spawn_object(&mut commands, &asset_server, painting, object_transform);
+        commands.entity(grab.entity).despawn_recursive();
+
+        save.materialized_paintings
+            .insert(painting.id.clone(), object_transform);
+        save_game.send(SaveGame);
+        materialized.send(PaintingMaterialized {
+            id: painting.id.clone(),
+            entity,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The angle between the rays from `eye` to two points.
+    fn angle(eye: Vec3, a: Vec3, b: Vec3) -> f32 {
+        (a - eye).angle_between(b - eye)
+    }
+
+    #[test]
+    fn materialized_object_covers_the_painting() {
+        let painting = Painting {
+            id: "apple".to_string(),
+            scene: "levels/gallery.glb#Scene1".to_string(),
+            object: Transform::from_xyz(0.3, 0.2, 0.0).with_scale(Vec3::splat(0.5)),
+            depth: 6.0,
+        };
+        let canvas = GlobalTransform::from(
+            Transform::from_xyz(1.0, 1.5, -2.0).looking_at(Vec3::new(0.0, 1.5, 0.0), Vec3::Y),
+        );
+        let eye = Vec3::new(0.0, 1.6, 0.5);
+
+        let on_canvas = canvas.mul_transform(painting.object).compute_transform();
+        let materialized = painting.materialized_transform(eye, &canvas);
+
+        // Further along the same ray, and scaled to match.
+        let ratio = painting.depth / eye.distance(canvas.translation());
+        assert!(
+            ((materialized.translation - eye).length()
+                - (on_canvas.translation - eye).length() * ratio)
+                .abs()
+                < 1e-4
+        );
+        assert!(angle(eye, on_canvas.translation, materialized.translation) < 1e-3);
+        assert_eq!(materialized.rotation, on_canvas.rotation);
+
+        // Opposite corners of the object's unit cube subtend the same angle from the eye.
+        let corners = [
+            Vec3::splat(-0.5),
+            Vec3::splat(0.5),
+            Vec3::new(0.5, -0.5, 0.5),
+        ];
+        for (a, b) in [(corners[0], corners[1]), (corners[0], corners[2])] {
+            let before = angle(eye, on_canvas * a, on_canvas * b);
+            let after = angle(eye, materialized * a, materialized * b);
+            assert!((before - after).abs() < 1e-4, "{} != {}", before, after);
+        }
+    }
+
+    #[test]
+    fn eye_on_the_canvas_keeps_the_object() {
+        let painting = Painting {
+            id: "pear".to_string(),
+            scene: "levels/gallery.glb#Scene2".to_string(),
+            object: Transform::from_xyz(0.1, 0.0, 0.0),
+            depth: 4.0,
+        };
+        let canvas = GlobalTransform::from(Transform::from_xyz(0.0, 1.0, 0.0));
+        let materialized = painting.materialized_transform(Vec3::new(0.0, 1.0, 0.0), &canvas);
+        assert_eq!(materialized.translation, Vec3::new(0.1, 1.0, 0.0));
+        assert_eq!(materialized.scale, Vec3::ONE);
+    }
+}
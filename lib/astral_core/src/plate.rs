@@ -0,0 +1,424 @@
+//! Astraliminal's Plate plugin.
+//!
+//! A `PressurePlate` adds up what rests on it, either by mass or by footprint, so a box that was
+//! made big enough can hold it down. Bodies stacked on top of each other all count. A
+//! `BalanceScale` compares the load of two plates.
+
+use std::cmp::Ordering;
+
+use bevy::{prelude::*, utils::HashSet};
+use bevy_xpbd_3d::prelude::*;
+
+use crate::{player::Player, schedule::AstralSet};
+
+/// Smallest change in radians for which a balance scale is rotated again.
+const TILT_EPSILON: f32 = 1e-4;
+
+/// What a plate measures.
+#[derive(Reflect, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PlateMeasure {
+    /// Sum of the `Mass` of the bodies on the plate.
+    #[default]
+    Mass,
+    /// Sum of the horizontal area covered by the bodies on the plate.
+    Footprint,
+}
+
+/// A plate that is pressed while enough rests on it. Needs a `Collider`.
+///
+/// The plate is pressed once the load reaches `press_at` and only released when it drops below
+/// `release_at`, so a body wobbling at the threshold doesn't flicker the plate.
+#[derive(Component, Reflect, Debug, Clone)]
+#[reflect(Component)]
+pub struct PressurePlate {
+    /// Id used by level data to refer to this plate.
+    pub id: String,
+    /// What the plate measures.
+    pub measure: PlateMeasure,
+    /// Load at which the plate gets pressed.
+    pub press_at: f32,
+    /// Load below which the plate gets released. Should not be more than `press_at`.
+    pub release_at: f32,
+}
+
+impl Default for PressurePlate {
+    fn default() -> Self {
+        Self {
+            id: String::new(),
+            measure: PlateMeasure::Mass,
+            press_at: 10.0,
+            release_at: 8.0,
+        }
+    }
+}
+
+impl PressurePlate {
+    /// Whether the plate is pressed under `load`, given whether it was pressed before.
+    pub fn is_pressed(&self, load: f32, was_pressed: bool) -> bool {
+        if was_pressed {
+            load >= self.release_at
+        } else {
+            load >= self.press_at
+        }
+    }
+}
+
+/// The current load on a plate and whether it is pressed.
+#[derive(Component, Reflect, Debug, Default, Clone, Copy, PartialEq)]
+#[reflect(Component)]
+pub struct PlateState {
+    pub load: f32,
+    pub pressed: bool,
+}
+
+/// Sent when a plate gets pressed.
+#[derive(Event, Debug, Clone)]
+pub struct PlatePressed {
+    pub plate: Entity,
+    pub id: String,
+}
+
+/// Sent when a plate gets released.
+#[derive(Event, Debug, Clone)]
+pub struct PlateReleased {
+    pub plate: Entity,
+    pub id: String,
+}
+
+/// Which side of a balance scale is heavier.
+#[derive(Reflect, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Balance {
+    #[default]
+    Level,
+    Left,
+    Right,
+}
+
+/// A balance scale comparing two `PressurePlate`s. The entity is rotated around its local Z
+/// axis to show which side is heavier.
+#[derive(Component, Reflect, Debug, Clone)]
+#[reflect(Component)]
+pub struct BalanceScale {
+    /// Id used by level data to refer to this scale.
+    pub id: String,
+    pub left: Entity,
+    pub right: Entity,
+    /// Largest load difference that still counts as level.
+    pub tolerance: f32,
+    /// Angle in radians the scale tips to when one side has all of the load.
+    pub max_tilt: f32,
+}
+
+impl FromWorld for BalanceScale {
+    fn from_world(_world: &mut World) -> Self {
+        Self {
+            id: String::new(),
+            left: Entity::PLACEHOLDER,
+            right: Entity::PLACEHOLDER,
+            tolerance: 0.5,
+            max_tilt: 15.0_f32.to_radians(),
+        }
+    }
+}
+
+impl BalanceScale {
+    /// Which side is heavier for the given loads.
+    pub fn balance(&self, left: f32, right: f32) -> Balance {
+        if (left - right).abs() <= self.tolerance {
+            return Balance::Level;
+        }
+        match left.partial_cmp(&right) {
+            Some(Ordering::Greater) => Balance::Left,
+            _ => Balance::Right,
+        }
+    }
+
+    /// How far the scale tips for the given loads. Positive tips the left side down. Never more
+    /// than `max_tilt` either way.
+    pub fn tilt(&self, left: f32, right: f32) -> f32 {
+        let total = left + right;
+        if total <= f32::EPSILON {
+            return 0.0;
+        }
+        ((left - right) / total).clamp(-1.0, 1.0) * self.max_tilt
+    }
+}
+
+/// The current balance of a scale.
+#[derive(Component, Reflect, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[reflect(Component)]
+pub struct BalanceState(pub Balance);
+
+/// Sent when a balance scale tips one way or the other, or levels out.
+#[derive(Event, Debug, Clone)]
+pub struct BalanceTipped {
+    pub scale: Entity,
+    pub id: String,
+    pub balance: Balance,
+}
+
+pub struct AstraliminalPlatePlugin;
+
+impl Plugin for AstraliminalPlatePlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<PressurePlate>()
+            .register_type::<PlateState>()
+            .register_type::<BalanceScale>()
+            .register_type::<BalanceState>()
+            .add_event::<PlatePressed>()
+            .add_event::<PlateReleased>()
+            .add_event::<BalanceTipped>()
+            .add_systems(
+                FixedUpdate,
+                (update_plates, update_scales)
+                    .chain()
+                    .in_set(AstralSet::Sense),
+            );
+    }
+}
+
+/// Bodies that can weigh down a plate.
+pub(crate) type BodyQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static GlobalTransform,
+        Option<&'static RigidBody>,
+        Option<&'static Mass>,
+        Option<&'static ColliderAabb>,
+        Has<Player>,
+    ),
+>;
+
+/// All bodies resting on `plate`, directly or on top of other bodies.
+pub(crate) fn resting_bodies(
+    plate: Entity,
+    collisions: &Collisions,
+    bodies: &BodyQuery,
+) -> Vec<Entity> {
+    let mut found = Vec::new();
+    let mut visited = HashSet::from([plate]);
+    let mut below = vec![plate];
+    let Ok((plate_transform, ..)) = bodies.get(plate) else {
+        return found;
+    };
+    let up = plate_transform.up();
+
+    while let Some(support) = below.pop() {
+        let Ok((support_transform, ..)) = bodies.get(support) else {
+            continue;
+        };
+
+        for contacts in collisions.collisions_with_entity(support) {
+            if !contacts.during_current_frame {
+                continue;
+            }
+            let other = if contacts.entity1 == support {
+                contacts.entity2
+            } else {
+                contacts.entity1
+            };
+            if visited.contains(&other) {
+                continue;
+            }
+
+            let Ok((transform, body, _, _, is_player)) = bodies.get(other) else {
+                continue;
+            };
+            let movable = is_player || body.is_some_and(RigidBody::is_dynamic);
+            let above = (transform.translation() - support_transform.translation()).dot(up) > 0.0;
+            if movable && above {
+                visited.insert(other);
+                found.push(other);
+                below.push(other);
+            }
+        }
+    }
+
+    found
+}
+
+/// Measure the load on every plate and press or release it.
+pub(crate) fn update_plates(
+    mut plates: Query<(Entity, &PressurePlate, Option<&mut PlateState>)>,
+    mut commands: Commands,
+    mut pressed_events: EventWriter<PlatePressed>,
+    mut released_events: EventWriter<PlateReleased>,
+    collisions: Res<Collisions>,
+    bodies: BodyQuery,
+) {
+    for (entity, plate, state) in &mut plates {
+        let load = resting_bodies(entity, &collisions, &bodies)
+            .into_iter()
+            .filter_map(|body| bodies.get(body).ok())
+            .map(|(_, _, mass, aabb, _)| match plate.measure {
+                PlateMeasure::Mass => mass.map_or(0.0, |mass| mass.0),
+                PlateMeasure::Footprint => aabb.map_or(0.0, |aabb| {
+                    let size = aabb.size();
+                    size.x * size.z
+                }),
+            })
+            .sum();
+
+        let was_pressed = state.as_ref().is_some_and(|state| state.pressed);
+        let pressed = plate.is_pressed(load, was_pressed);
+        if pressed && !was_pressed {
+            pressed_events.send(PlatePressed {
+                plate: entity,
+                id: plate.id.clone(),
+            });
+        } else if !pressed && was_pressed {
+            released_events.send(PlateReleased {
+                plate: entity,
+                id: plate.id.clone(),
+            });
+        }
+
+        let new_state = PlateState { load, pressed };
+        if let Some(mut state) = state {
+            state.set_if_neq(new_state);
+        } else {
+            commands.entity(entity).insert(new_state);
+        }
+    }
+}
+
+/// Compare the plates of every balance scale and tip it.
+fn update_scales(
+    mut scales: Query<(
+        Entity,
+        &BalanceScale,
+        &mut Transform,
+        Option<&mut BalanceState>,
+    )>,
+    mut commands: Commands,
+    mut tipped: EventWriter<BalanceTipped>,
+    plates: Query<&PlateState>,
+) {
+    for (entity, scale, mut transform, state) in &mut scales {
+        let left = plates.get(scale.left).map_or(0.0, |plate| plate.load);
+        let right = plates.get(scale.right).map_or(0.0, |plate| plate.load);
+
+        let (x, y, z) = transform.rotation.to_euler(EulerRot::XYZ);
+        let tilt = scale.tilt(left, right);
+        if (tilt - z).abs() > TILT_EPSILON {
+            transform.rotation = Quat::from_euler(EulerRot::XYZ, x, y, tilt);
+        }
+
+        let balance = scale.balance(left, right);
+        let previous = state.as_ref().map(|state| state.0);
+        if previous == Some(balance) {
+            continue;
+        }
+        if previous.is_some() || balance != Balance::Level {
+            tipped.send(BalanceTipped {
+                scale: entity,
+                id: scale.id.clone(),
+                balance,
+            });
+        }
+        match state {
+            Some(mut state) => state.0 = balance,
+            None => {
+                commands.entity(entity).insert(BalanceState(balance));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scale() -> BalanceScale {
+        BalanceScale {
+            id: "scale".to_string(),
+            left: Entity::PLACEHOLDER,
+            right: Entity::PLACEHOLDER,
+            tolerance: 0.5,
+            max_tilt: 0.2,
+        }
+    }
+
+    #[test]
+    fn plates_press_and_release_at_their_thresholds() {
+        let plate = PressurePlate::default();
+        assert!(!plate.is_pressed(9.9, false));
+        assert!(plate.is_pressed(10.0, false));
+        // Once pressed, it holds until the load drops below `release_at`.
+        assert!(plate.is_pressed(9.0, true));
+        assert!(plate.is_pressed(8.0, true));
+        assert!(!plate.is_pressed(7.9, true));
+        // Released in between the thresholds stays released.
+        assert!(!plate.is_pressed(9.0, false));
+    }
+
+    #[test]
+    fn balance_sides() {
+        let scale = scale();
+        assert_eq!(scale.balance(0.0, 0.0), Balance::Level);
+        assert_eq!(scale.balance(10.0, 10.5), Balance::Level);
+        assert_eq!(scale.balance(10.5, 10.0), Balance::Level);
+        assert_eq!(scale.balance(11.0, 10.0), Balance::Left);
+        assert_eq!(scale.balance(10.0, 11.0), Balance::Right);
+    }
+
+    #[test]
+    fn tilt_sign_and_limits() {
+        let scale = scale();
+        assert_eq!(scale.tilt(0.0, 0.0), 0.0);
+        assert_eq!(scale.tilt(5.0, 5.0), 0.0);
+        assert!(scale.tilt(6.0, 2.0) > 0.0);
+        assert!(scale.tilt(2.0, 6.0) < 0.0);
+        assert!((scale.tilt(6.0, 2.0) - 0.1).abs() < 1e-6);
+        assert_eq!(scale.tilt(4.0, 0.0), 0.2);
+        assert_eq!(scale.tilt(0.0, 4.0), -0.2);
+        // Odd loads don't tip it past `max_tilt`.
+        assert_eq!(scale.tilt(4.0, -1.0), 0.2);
+        assert_eq!(scale.tilt(-1.0, 4.0), -0.2);
+    }
+
+    #[test]
+    fn scales_only_rotate_when_the_tilt_changes() {
+        let mut app = App::new();
+        app.add_event::<BalanceTipped>()
+            .add_systems(Update, update_scales);
+        let left = app.world.spawn(PlateState::default()).id();
+        let right = app.world.spawn(PlateState::default()).id();
+        let balance = app
+            .world
+            .spawn((
+                BalanceScale {
+                    left,
+                    right,
+                    ..scale()
+                },
+                Transform::from_rotation(Quat::from_rotation_y(1.0)),
+            ))
+            .id();
+        let last_changed = |app: &App| {
+            app.world
+                .entity(balance)
+                .get_ref::<Transform>()
+                .unwrap()
+                .last_changed()
+        };
+
+        app.update();
+        let level = last_changed(&app);
+        app.update();
+        assert_eq!(last_changed(&app), level);
+
+        app.world.get_mut::<PlateState>(left).unwrap().load = 3.0;
+        app.update();
+        let tipped = last_changed(&app);
+        assert_ne!(tipped, level);
+        let rotation = app.world.get::<Transform>(balance).unwrap().rotation;
+        let (_, y, z) = rotation.to_euler(EulerRot::XYZ);
+        assert!((y - 1.0).abs() < 1e-5);
+        assert!((z - 0.2).abs() < 1e-5);
+
+        app.update();
+        assert_eq!(last_changed(&app), tipped);
+    }
+}
@@ -0,0 +1,168 @@
+//! Astraliminal's Player plugin.
+
+use bevy::{ecs::system::Command, math::primitives::Direction3d, prelude::*, utils::HashSet};
+use bevy_xpbd_3d::prelude::*;
+
+use crate::schedule::AstralSet;
+
+/// Maximum distance from the camera at which the player can grab an object.
+const GRAB_DISTANCE: f32 = 10.0;
+
+/// Marker for the player entity.
+#[derive(Component, Reflect, Debug, Default, Clone, Copy)]
+#[reflect(Component)]
+pub struct Player;
+
+/// Marker for the camera the player looks through.
+#[derive(Component, Reflect, Debug, Default, Clone, Copy)]
+#[reflect(Component)]
+pub struct PlayerCamera;
+
+/// An object the player can pick up by grabbing it.
+#[derive(Component, Reflect, Debug, Default, Clone, Copy)]
+#[reflect(Component)]
+pub struct Carriable;
+
+/// An object the player is carrying. A carried object may be attached to the player or their
+/// camera and made kinematic; `DropHeld` undoes both.
+#[derive(Component, Reflect, Debug, Default, Clone, Copy)]
+#[reflect(Component)]
+pub struct Held {
+    /// The object's body type before it was picked up, restored when it is dropped.
+    pub body: Option<RigidBody>,
+}
+
+/// Drop a held object where it is: it stops being `Held`, is detached from the player or their
+/// camera without moving, and gets back the body type it had before it was picked up.
+#[derive(Debug, Clone, Copy)]
+pub struct DropHeld(pub Entity);
+
+impl Command for DropHeld {
+    fn apply(self, world: &mut World) {
+        let attached = world
+            .get::<Parent>(self.0)
+            .and_then(|parent| world.get_entity(parent.get()))
+            .is_some_and(|parent| parent.contains::<Player>() || parent.contains::<PlayerCamera>());
+        let Some(mut entity) = world.get_entity_mut(self.0) else {
+            return;
+        };
+        let Some(held) = entity.take::<Held>() else {
+            return;
+        };
+
+        if attached {
+            let global = entity.get::<GlobalTransform>().copied().unwrap_or_default();
+            entity.remove_parent().insert(global.compute_transform());
+        }
+        if let (Some(before), Some(mut body)) = (held.body, entity.get_mut::<RigidBody>()) {
+            *body = before;
+        }
+    }
+}
+
+/// Sent when the player presses the grab button while looking at an entity with a collider.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct Grab {
+    /// The entity that was grabbed.
+    pub entity: Entity,
+    /// World-space point where the view ray hit the entity.
+    pub point: Vec3,
+}
+
+/// Why player input is ignored, e.g. `"timeline"` while a cutscene plays. Input is allowed while
+/// this is empty.
+#[derive(Resource, Debug, Default, Clone, PartialEq, Eq)]
+pub struct InputBlocked(pub HashSet<String>);
+
+impl InputBlocked {
+    /// Whether anything blocks player input.
+    pub fn is_blocked(&self) -> bool {
+        !self.0.is_empty()
+    }
+}
+
+/// Run condition for systems that read player input.
+pub fn input_allowed(blocked: Option<Res<InputBlocked>>) -> bool {
+    blocked.is_none_or(|blocked| !blocked.is_blocked())
+}
+
+pub struct AstraliminalPlayerPlugin;
+
+impl Plugin for AstraliminalPlayerPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Player>()
+            .register_type::<PlayerCamera>()
+            .register_type::<Carriable>()
+            .register_type::<Held>()
+            .init_resource::<InputBlocked>()
+            .add_event::<Grab>()
+            .add_systems(Update, grab_input.run_if(input_allowed))
+            .add_systems(FixedUpdate, pick_up.in_set(AstralSet::Player));
+    }
+}
+
+/// Cast a ray from the center of the player camera when the grab button is pressed and send a
+/// `Grab` event for the first entity hit.
+fn grab_input(
+    mouse: Res<ButtonInput<MouseButton>>,
+    camera: Query<&GlobalTransform, With<PlayerCamera>>,
+    player: Query<Entity, With<Player>>,
+    spatial_query: SpatialQuery,
+    mut grabs: EventWriter<Grab>,
+) {
+    // TODO: pull mouse button from config.
+    if !mouse.just_pressed(MouseButton::Left) {
+        return;
+    }
+
+    let Ok(camera) = camera.get_single() else {
+        return;
+    };
+
+    let origin = camera.translation();
+    let direction = Direction3d::new_unchecked(camera.forward());
+    let filter = SpatialQueryFilter::default().with_excluded_entities(player.iter());
+
+    if let Some(hit) = spatial_query.cast_ray(origin, direction, GRAB_DISTANCE, true, filter) {
+        grabs.send(Grab {
+            entity: hit.entity,
+            point: origin + *direction * hit.time_of_impact,
+        });
+    }
+}
+
+/// Pick up a grabbed `Carriable` object, attaching it to the player camera as a kinematic body.
+/// Grabbing the held object again drops it.
+pub(crate) fn pick_up(
+    mut commands: Commands,
+    mut grabs: EventReader<Grab>,
+    camera: Query<Entity, With<PlayerCamera>>,
+    carriables: Query<Option<&RigidBody>, With<Carriable>>,
+    held: Query<(), With<Held>>,
+) {
+    let mut carrying = !held.is_empty();
+
+    for grab in grabs.read() {
+        if held.contains(grab.entity) {
+            commands.add(DropHeld(grab.entity));
+            continue;
+        }
+        if carrying {
+            continue;
+        }
+        let (Ok(camera), Ok(body)) = (camera.get_single(), carriables.get(grab.entity)) else {
+            continue;
+        };
+
+        let mut entity = commands.entity(grab.entity);
+        entity
+            .insert(Held {
+                body: body.copied(),
+            })
+            .set_parent_in_place(camera);
+        if body.is_some() {
+            entity.insert(RigidBody::Kinematic);
+        }
+        carrying = true;
+    }
+}
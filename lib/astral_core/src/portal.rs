@@ -0,0 +1,528 @@
+//! Astraliminal's Portal plugin.
+//!
+//! A portal is a rectangle in its local XY plane, entered from its front (local +Z) side. Crossing
+//! it moves the traveller to the front of the linked portal, facing away from it. If the two
+//! portals have different scales, travellers are scaled by the same ratio.
+//!
+//! The math lives in plain functions so it can be used without an `App`.
+
+use std::f32::consts::PI;
+
+use bevy::{math::Affine3A, prelude::*, transform::TransformSystem};
+use bevy_xpbd_3d::prelude::*;
+
+use crate::{
+    player::{Held, Player, PlayerCamera},
+    schedule::AstralSet,
+};
+
+/// One side of a portal pair. Both portals of a pair point at each other.
+#[derive(Component, Reflect, Debug, Clone)]
+#[reflect(Component)]
+pub struct Portal {
+    /// The linked portal.
+    pub target: Entity,
+    /// Half of the portal's width and height, in its local units.
+    pub half_size: Vec2,
+}
+
+impl FromWorld for Portal {
+    fn from_world(_world: &mut World) -> Self {
+        Self {
+            target: Entity::PLACEHOLDER,
+            half_size: Vec2::ONE,
+        }
+    }
+}
+
+/// Something that can go through portals. Entities that are `Held` go through together with the
+/// player and don't need this.
+#[derive(Component, Reflect, Debug, Clone)]
+#[reflect(Component)]
+pub struct PortalTraveller {
+    /// Radius of a sphere around the entity, used to tell when it is half-way through.
+    pub radius: f32,
+    /// Position at the last check.
+    #[reflect(ignore)]
+    previous: Option<Vec3>,
+}
+
+impl Default for PortalTraveller {
+    fn default() -> Self {
+        Self::new(0.5)
+    }
+}
+
+impl PortalTraveller {
+    pub fn new(radius: f32) -> Self {
+        Self {
+            radius,
+            previous: None,
+        }
+    }
+}
+
+/// A copy of a traveller drawn on the far side of a portal while the traveller is half-way
+/// through it.
+#[derive(Component, Reflect, Debug, Clone, Copy)]
+#[reflect(Component)]
+pub struct PortalGhost {
+    /// The traveller being mirrored.
+    pub of: Entity,
+    /// The portal it is passing through.
+    pub portal: Entity,
+}
+
+impl FromWorld for PortalGhost {
+    fn from_world(_world: &mut World) -> Self {
+        Self {
+            of: Entity::PLACEHOLDER,
+            portal: Entity::PLACEHOLDER,
+        }
+    }
+}
+
+/// A camera that renders the view through a portal. Its transform follows the player camera as
+/// seen from the linked portal.
+#[derive(Component, Reflect, Debug, Clone, Copy)]
+#[reflect(Component)]
+pub struct PortalCamera {
+    /// The portal this camera looks through.
+    pub portal: Entity,
+}
+
+impl FromWorld for PortalCamera {
+    fn from_world(_world: &mut World) -> Self {
+        Self {
+            portal: Entity::PLACEHOLDER,
+        }
+    }
+}
+
+/// Sent when an entity goes through a portal.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct PortalCrossed {
+    /// The entity that went through.
+    pub entity: Entity,
+    /// The portal it entered.
+    pub from: Entity,
+    /// The portal it came out of.
+    pub to: Entity,
+}
+
+/// The affine map from space in front of `from` to space in front of `to`.
+pub fn portal_affine(from: &GlobalTransform, to: &GlobalTransform) -> Affine3A {
+    to.affine() * Affine3A::from_rotation_y(PI) * from.affine().inverse()
+}
+
+/// Where `transform` ends up after going through `from` and out of `to`.
+pub fn teleport_transform(
+    transform: &Transform,
+    from: &GlobalTransform,
+    to: &GlobalTransform,
+) -> Transform {
+    let affine = portal_affine(from, to) * transform.compute_affine();
+    Transform::from_matrix(Mat4::from(affine))
+}
+
+/// A linear velocity after going through `from` and out of `to`. Speed is scaled by the size
+/// ratio of the portals.
+pub fn teleport_velocity(velocity: Vec3, from: &GlobalTransform, to: &GlobalTransform) -> Vec3 {
+    portal_affine(from, to).transform_vector3(velocity)
+}
+
+/// An angular velocity after going through `from` and out of `to`.
+pub fn teleport_angular_velocity(
+    velocity: Vec3,
+    from: &GlobalTransform,
+    to: &GlobalTransform,
+) -> Vec3 {
+    let (_, from_rotation, _) = from.to_scale_rotation_translation();
+    let (_, to_rotation, _) = to.to_scale_rotation_translation();
+    (to_rotation * Quat::from_rotation_y(PI) * from_rotation.inverse()) * velocity
+}
+
+/// The transform of a camera that shows the view through `from`, given the viewer's camera.
+pub fn portal_camera_transform(
+    camera: &GlobalTransform,
+    from: &GlobalTransform,
+    to: &GlobalTransform,
+) -> Transform {
+    teleport_transform(&camera.compute_transform(), from, to)
+}
+
+/// Signed distance of `point` from the portal plane, in the portal's local units. Positive is in
+/// front.
+pub fn signed_distance(portal: &GlobalTransform, point: Vec3) -> f32 {
+    portal.affine().inverse().transform_point3(point).z
+}
+
+/// Whether moving from `previous` to `current` passes through the front of the portal.
+pub fn crossed(portal: &GlobalTransform, half_size: Vec2, previous: Vec3, current: Vec3) -> bool {
+    let inverse = portal.affine().inverse();
+    let a = inverse.transform_point3(previous);
+    let b = inverse.transform_point3(current);
+
+    if a.z <= 0.0 || b.z > 0.0 {
+        return false;
+    }
+
+    let hit = a.lerp(b, a.z / (a.z - b.z));
+    hit.x.abs() <= half_size.x && hit.y.abs() <= half_size.y
+}
+
+/// Whether a sphere at `center` touches the portal's opening.
+pub fn straddles(portal: &GlobalTransform, half_size: Vec2, center: Vec3, radius: f32) -> bool {
+    let (scale, _, _) = portal.to_scale_rotation_translation();
+    let local = portal.affine().inverse().transform_point3(center);
+    let radius = radius / scale.max_element();
+
+    local.z.abs() < radius
+        && local.x.abs() <= half_size.x + radius
+        && local.y.abs() <= half_size.y + radius
+}
+
+pub struct AstraliminalPortalPlugin;
+
+impl Plugin for AstraliminalPortalPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Portal>()
+            .register_type::<PortalTraveller>()
+            .register_type::<PortalGhost>()
+            .register_type::<PortalCamera>()
+            .add_event::<PortalCrossed>()
+            .add_systems(
+                FixedUpdate,
+                (cross_portals, update_ghosts)
+                    .chain()
+                    .in_set(AstralSet::Player),
+            )
+            .add_systems(
+                PostUpdate,
+                update_portal_cameras.after(TransformSystem::TransformPropagate),
+            );
+    }
+}
+
+/// Entities moved by `cross_portals`.
+type TravellerQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static mut Transform,
+        &'static GlobalTransform,
+        Option<&'static Parent>,
+        Option<&'static mut PortalTraveller>,
+        Option<&'static mut LinearVelocity>,
+        Option<&'static mut AngularVelocity>,
+        Has<Player>,
+        Has<Held>,
+    ),
+    Or<(With<PortalTraveller>, With<Held>)>,
+>;
+
+/// The local transform that puts an entity with the given parent at `global`.
+fn local_transform(global: Transform, parent: Option<&GlobalTransform>) -> Transform {
+    match parent {
+        Some(parent) => {
+            let affine = parent.affine().inverse() * global.compute_affine();
+            Transform::from_matrix(Mat4::from(affine))
+        }
+        None => global,
+    }
+}
+
+/// Teleport travellers that went through a portal since the last frame. When the player goes
+/// through, whatever they hold goes with them. Held objects attached to something move with it
+/// and are left alone.
+pub(crate) fn cross_portals(
+    mut travellers: TravellerQuery,
+    mut crossed_events: EventWriter<PortalCrossed>,
+    portals: Query<(Entity, &Portal, &GlobalTransform)>,
+    held: Query<Entity, (With<Held>, Without<Parent>)>,
+    targets: Query<&GlobalTransform, With<Portal>>,
+    parents: Query<&GlobalTransform>,
+) {
+    let mut crossings = Vec::new();
+
+    for (entity, _, global, _, traveller, _, _, is_player, is_held) in &mut travellers {
+        // Held objects go through with the player, never on their own.
+        let (Some(mut traveller), false) = (traveller, is_held) else {
+            continue;
+        };
+
+        let current = global.translation();
+        let previous = traveller.previous.replace(current);
+        let Some(previous) = previous else {
+            continue;
+        };
+
+        let Some((portal, link)) = portals
+            .iter()
+            .find(|(_, link, portal)| crossed(portal, link.half_size, previous, current))
+            .map(|(portal, link, _)| (portal, link))
+        else {
+            continue;
+        };
+
+        crossings.push((entity, portal, link.target));
+        if is_player {
+            crossings.extend(held.iter().map(|held| (held, portal, link.target)));
+        }
+    }
+
+    for (entity, from, to) in crossings {
+        let (Ok((_, _, from_transform)), Ok(to_transform)) = (portals.get(from), targets.get(to))
+        else {
+            continue;
+        };
+        let Ok((_, mut transform, global, parent, traveller, linear, angular, ..)) =
+            travellers.get_mut(entity)
+        else {
+            continue;
+        };
+
+        let global = teleport_transform(&global.compute_transform(), from_transform, to_transform);
+        let parent = parent.and_then(|parent| parents.get(parent.get()).ok());
+        *transform = local_transform(global, parent);
+        if let Some(mut traveller) = traveller {
+            // The traveller is now in front of the target portal; don't count that as a crossing.
+            traveller.previous = Some(global.translation);
+        }
+        if let Some(mut linear) = linear {
+            linear.0 = teleport_velocity(linear.0, from_transform, to_transform);
+        }
+        if let Some(mut angular) = angular {
+            angular.0 = teleport_angular_velocity(angular.0, from_transform, to_transform);
+        }
+
+        crossed_events.send(PortalCrossed { entity, from, to });
+    }
+}
+
+/// Travellers and the parts of them a ghost copies.
+type GhostSourceQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static GlobalTransform,
+        &'static PortalTraveller,
+        Option<&'static Handle<Mesh>>,
+        Option<&'static Handle<StandardMaterial>>,
+        Option<&'static Handle<Scene>>,
+    ),
+>;
+
+/// Keep a ghost copy on the far side of every portal a traveller is half-way through.
+fn update_ghosts(
+    mut commands: Commands,
+    travellers: GhostSourceQuery,
+    mut ghosts: Query<(Entity, &PortalGhost, &mut Transform), Without<PortalTraveller>>,
+    portals: Query<(Entity, &Portal, &GlobalTransform)>,
+    targets: Query<&GlobalTransform, With<Portal>>,
+) {
+    let mut wanted = Vec::new();
+    for (entity, transform, traveller, ..) in &travellers {
+        for (portal_entity, portal, portal_transform) in &portals {
+            if straddles(
+                portal_transform,
+                portal.half_size,
+                transform.translation(),
+                traveller.radius,
+            ) {
+                wanted.push((entity, portal_entity));
+            }
+        }
+    }
+
+    // Update or remove existing ghosts.
+    for (ghost_entity, ghost, mut ghost_transform) in &mut ghosts {
+        let Some(index) = wanted
+            .iter()
+            .position(|&(of, portal)| of == ghost.of && portal == ghost.portal)
+        else {
+            commands.entity(ghost_entity).despawn_recursive();
+            continue;
+        };
+        wanted.swap_remove(index);
+
+        let (Ok((_, transform, ..)), Ok((_, portal, from))) =
+            (travellers.get(ghost.of), portals.get(ghost.portal))
+        else {
+            continue;
+        };
+        if let Ok(to) = targets.get(portal.target) {
+            *ghost_transform = teleport_transform(&transform.compute_transform(), from, to);
+        }
+    }
+
+    // Spawn the missing ones.
+    for (of, portal_entity) in wanted {
+        let Ok((_, transform, _, mesh, material, scene)) = travellers.get(of) else {
+            continue;
+        };
+        let Ok((_, portal, from)) = portals.get(portal_entity) else {
+            continue;
+        };
+        let Ok(to) = targets.get(portal.target) else {
+            continue;
+        };
+
+        let mut ghost = commands.spawn((
+            SpatialBundle::from_transform(teleport_transform(
+                &transform.compute_transform(),
+                from,
+                to,
+            )),
+            PortalGhost {
+                of,
+                portal: portal_entity,
+            },
+            Name::new("Portal ghost"),
+        ));
+        if let Some(mesh) = mesh {
+            ghost.insert(mesh.clone());
+        }
+        if let Some(material) = material {
+            ghost.insert(material.clone());
+        }
+        if let Some(scene) = scene {
+            ghost.insert(scene.clone());
+        }
+    }
+}
+
+/// Move portal cameras to where the player camera would be on the far side of their portal.
+fn update_portal_cameras(
+    player_camera: Query<&GlobalTransform, (With<PlayerCamera>, Without<PortalCamera>)>,
+    mut cameras: Query<(&PortalCamera, &mut Transform)>,
+    portals: Query<(&Portal, &GlobalTransform)>,
+) {
+    let Ok(player_camera) = player_camera.get_single() else {
+        return;
+    };
+
+    for (camera, mut transform) in &mut cameras {
+        let Ok((portal, from)) = portals.get(camera.portal) else {
+            continue;
+        };
+        let Ok((_, to)) = portals.get(portal.target) else {
+            continue;
+        };
+        *transform = portal_camera_transform(player_camera, from, to);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::*;
+
+    /// Two portals: one facing +Z at the origin, one twice as big, turned and far away.
+    fn portals() -> (GlobalTransform, GlobalTransform) {
+        let from = GlobalTransform::IDENTITY;
+        let to = GlobalTransform::from(
+            Transform::from_xyz(10.0, 2.0, -5.0)
+                .with_rotation(Quat::from_rotation_y(FRAC_PI_2))
+                .with_scale(Vec3::splat(2.0)),
+        );
+        (from, to)
+    }
+
+    fn assert_transform_eq(a: Transform, b: Transform) {
+        assert!(
+            a.translation.abs_diff_eq(b.translation, 1e-4),
+            "{a:?} != {b:?}"
+        );
+        assert!(
+            a.rotation.abs_diff_eq(b.rotation, 1e-4) || a.rotation.abs_diff_eq(-b.rotation, 1e-4),
+            "{a:?} != {b:?}"
+        );
+        assert!(a.scale.abs_diff_eq(b.scale, 1e-4), "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn teleport_round_trips() {
+        let (from, to) = portals();
+        let transform = Transform::from_xyz(0.3, -0.2, 0.5).with_rotation(Quat::from_euler(
+            EulerRot::YXZ,
+            0.4,
+            0.2,
+            0.1,
+        ));
+        let there = teleport_transform(&transform, &from, &to);
+        assert_transform_eq(teleport_transform(&there, &to, &from), transform);
+
+        let affine = portal_affine(&to, &from) * portal_affine(&from, &to);
+        assert!(Mat4::from(affine).abs_diff_eq(Mat4::IDENTITY, 1e-4));
+    }
+
+    #[test]
+    fn teleport_comes_out_of_the_front() {
+        let (from, to) = portals();
+        // Just behind the entrance, walking into it.
+        let transform = Transform::from_xyz(0.0, 0.0, -0.1);
+        let there = teleport_transform(&transform, &from, &to);
+        assert!(signed_distance(&to, there.translation) > 0.0);
+        assert!(there.scale.abs_diff_eq(Vec3::splat(2.0), 1e-4));
+
+        // Moving into the entrance means moving out of the exit, twice as fast.
+        let velocity = teleport_velocity(Vec3::NEG_Z, &from, &to);
+        let (_, rotation, _) = to.to_scale_rotation_translation();
+        assert!(
+            velocity.abs_diff_eq(rotation * Vec3::Z * 2.0, 1e-4),
+            "{velocity}"
+        );
+        let angular = teleport_angular_velocity(Vec3::Y, &from, &to);
+        assert!(angular.abs_diff_eq(Vec3::Y, 1e-4), "{angular}");
+    }
+
+    #[test]
+    fn camera_sees_through_the_exit() {
+        let (from, to) = portals();
+        let camera = Transform::from_xyz(1.0, 1.5, 4.0).looking_at(Vec3::ZERO, Vec3::Y);
+        assert_transform_eq(
+            portal_camera_transform(&GlobalTransform::from(camera), &from, &to),
+            teleport_transform(&camera, &from, &to),
+        );
+    }
+
+    #[test]
+    fn crossing_needs_the_front_and_the_opening() {
+        let (from, to) = portals();
+        let half_size = Vec2::new(1.0, 2.0);
+        let front = Vec3::new(0.5, 1.0, 0.3);
+        let back = Vec3::new(0.5, 1.0, -0.3);
+        assert!(crossed(&from, half_size, front, back));
+        // Leaving through the back, standing still or going around the edge isn't a crossing.
+        assert!(!crossed(&from, half_size, back, front));
+        assert!(!crossed(&from, half_size, front, front));
+        assert!(!crossed(
+            &from,
+            half_size,
+            front + Vec3::X * 2.0,
+            back + Vec3::X * 2.0
+        ));
+
+        // The same crossing, seen at the exit.
+        let there = |point: Vec3| portal_affine(&from, &to).transform_point3(point);
+        assert!(crossed(&to, half_size, there(back), there(front)));
+    }
+
+    #[test]
+    fn straddling_uses_the_radius() {
+        let (from, to) = portals();
+        let half_size = Vec2::ONE;
+        assert!(straddles(&from, half_size, Vec3::new(0.0, 0.0, 0.2), 0.5));
+        assert!(straddles(&from, half_size, Vec3::new(1.3, 0.0, -0.2), 0.5));
+        assert!(!straddles(&from, half_size, Vec3::new(0.0, 0.0, 0.6), 0.5));
+        assert!(!straddles(&from, half_size, Vec3::new(1.6, 0.0, 0.0), 0.5));
+        // The radius is in world units, and the exit is twice as big.
+        let (_, _, center) = to.to_scale_rotation_translation();
+        assert!(straddles(&to, half_size, center + Vec3::X * 0.4, 0.5));
+        assert!(!straddles(&to, half_size, center + Vec3::X * 0.6, 0.5));
+    }
+}
@@ -0,0 +1,558 @@
+//! Astraliminal's Replay plugin.
+//!
+//! In `ReplayMode::Record`, every input event is written to a `Replay` file together with the
+//! frame it happened in, the gameplay ticks that ran in the frame, the `RngSeed` and the build. In
+//! `ReplayMode::Play`, live input is dropped and the recorded events are fed back, frame by frame,
+//! and each frame advances time by exactly the fixed timesteps it ran when recorded, so the session
+//! plays out the same no matter how fast the machine is. When the replay runs out,
+//! `ReplayFinished` carries a hash of the world state to compare against the one recorded.
+//!
+//! Gameplay randomness must come from `RngSeed::roll`, which depends only on the recorded seed.
+
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bevy::{
+    app::{AppExit, PluginsState},
+    input::{
+        gamepad::GamepadEvent,
+        keyboard::KeyboardInput,
+        mouse::{MouseButtonInput, MouseMotion, MouseWheel},
+        InputSystem,
+    },
+    prelude::*,
+    time::TimeUpdateStrategy,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    dimension::ActiveDimension,
+    logic::LogicState,
+    story::{Blackboard, StoryFlags},
+    window::{ASTRAL_COMPILE_DATETIME, ASTRAL_VERSION},
+};
+
+/// Frames between writes of a recording, so a crash loses little.
+const RECORD_FLUSH_FRAMES: u64 = 600;
+
+/// Seed for all gameplay randomness. Recorded in replays so they reproduce.
+#[derive(Resource, Reflect, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[reflect(Resource)]
+pub struct RngSeed(pub u64);
+
+impl RngSeed {
+    /// A random number for the `count`th pick of `key`, e.g. an NPC's id. It only depends on the
+    /// seed, `key` and `count`, so it is the same on every machine and build.
+    pub fn roll(&self, key: &str, count: u64) -> u64 {
+        let mut hash = Fnv::new();
+        hash.write(&self.0.to_le_bytes());
+        hash.write(key.as_bytes());
+        hash.write(&count.to_le_bytes());
+        hash.0
+    }
+}
+
+/// The build a replay was recorded with.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildInfo {
+    pub version: String,
+    pub compiled: String,
+}
+
+impl BuildInfo {
+    /// The running build.
+    pub fn current() -> Self {
+        Self {
+            version: ASTRAL_VERSION.to_string(),
+            compiled: ASTRAL_COMPILE_DATETIME.to_string(),
+        }
+    }
+}
+
+/// An input event as it was received.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RecordedInput {
+    Keyboard(KeyboardInput),
+    MouseButton(MouseButtonInput),
+    MouseMotion(MouseMotion),
+    MouseWheel(MouseWheel),
+    Gamepad(GamepadEvent),
+}
+
+/// One frame of a replay.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplayFrame {
+    pub frame: u64,
+    /// Gameplay time the frame advanced: the fixed timestep times the ticks that ran in it.
+    pub delta: Duration,
+    pub inputs: Vec<RecordedInput>,
+}
+
+/// A recorded session, stored as RON.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Replay {
+    pub build: BuildInfo,
+    pub seed: u64,
+    pub frames: Vec<ReplayFrame>,
+    /// `world_hash` at the end of the last frame, once the recording is finished.
+    pub final_hash: Option<u64>,
+}
+
+impl Replay {
+    /// Read a replay from a RON file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ReplayError> {
+        let contents = fs::read_to_string(path)?;
+        Ok(ron::from_str(&contents)?)
+    }
+
+    /// Write a replay to a RON file.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ReplayError> {
+        fs::write(path, ron::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+/// Errors that can happen while reading or writing a replay.
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    Parse(ron::error::SpannedError),
+    Serialize(ron::Error),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "replay file I/O error: {}", err),
+            Self::Parse(err) => write!(f, "replay file is corrupt: {}", err),
+            Self::Serialize(err) => write!(f, "could not serialize replay: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<io::Error> for ReplayError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<ron::error::SpannedError> for ReplayError {
+    fn from(err: ron::error::SpannedError) -> Self {
+        Self::Parse(err)
+    }
+}
+
+impl From<ron::Error> for ReplayError {
+    fn from(err: ron::Error) -> Self {
+        Self::Serialize(err)
+    }
+}
+
+/// Whether input is being recorded or played back. Insert before adding the plugins.
+#[derive(Resource, Debug, Default, Clone)]
+pub enum ReplayMode {
+    #[default]
+    Off,
+    /// Record into the replay file at this path.
+    Record(PathBuf),
+    /// Play this replay back.
+    Play(Replay),
+}
+
+/// Sent after the last frame of a replay was played back, and kept as a resource from then on.
+#[derive(Event, Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayFinished {
+    /// `world_hash` after the last frame.
+    pub hash: u64,
+    /// The hash the recording ended with, if it was finished.
+    pub expected: Option<u64>,
+}
+
+/// Systems that record, or play back and finish, a replay. Read `ReplayFinished` after this set.
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ReplaySet;
+
+/// The replay being recorded or played back and how far along it is.
+#[derive(Resource, Debug, Default, Clone)]
+struct ReplayProgress {
+    replay: Replay,
+    frame: u64,
+    finished: bool,
+}
+
+pub struct AstraliminalReplayPlugin;
+
+impl Plugin for AstraliminalReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<RngSeed>()
+            .init_resource::<ReplayMode>()
+            .add_event::<ReplayFinished>();
+
+        let mode = app.world.resource::<ReplayMode>().clone();
+        match mode {
+            ReplayMode::Off => {
+                app.insert_resource(RngSeed(seed_from_clock()));
+            }
+            ReplayMode::Record(_) => {
+                let seed = seed_from_clock();
+                app.insert_resource(RngSeed(seed))
+                    .insert_resource(ReplayProgress {
+                        replay: Replay {
+                            build: BuildInfo::current(),
+                            seed,
+                            ..default()
+                        },
+                        ..default()
+                    })
+                    .add_systems(
+                        PreUpdate,
+                        record_inputs.after(InputSystem).in_set(ReplaySet),
+                    )
+                    .add_systems(FixedFirst, record_tick.in_set(ReplaySet))
+                    .add_systems(Last, write_recording.in_set(ReplaySet));
+            }
+            ReplayMode::Play(replay) => {
+                if replay.build != BuildInfo::current() {
+                    warn!(
+                        "Replay was recorded with build {:?}, it may not reproduce",
+                        replay.build
+                    );
+                }
+                let first_delta = replay.frames.first().map_or(Duration::ZERO, |f| f.delta);
+                app.insert_resource(RngSeed(replay.seed))
+                    .insert_resource(TimeUpdateStrategy::ManualDuration(first_delta))
+                    .insert_resource(ReplayProgress {
+                        replay,
+                        ..default()
+                    })
+                    .add_systems(Startup, unlimit_frame_time)
+                    .add_systems(PreUpdate, play_inputs.before(InputSystem).in_set(ReplaySet))
+                    .add_systems(Last, finish_frame.in_set(ReplaySet));
+            }
+        }
+    }
+}
+
+/// Run a headless app until it exits, as `ScheduleRunnerPlugin` would, and return how its replay
+/// ended. Unlike `App::run`, the app is kept, so the result can be read from it.
+pub fn run_replay(app: &mut App) -> Option<ReplayFinished> {
+    while app.plugins_state() == PluginsState::Adding {
+        bevy::tasks::tick_global_task_pools_on_main_thread();
+    }
+    app.finish();
+    app.cleanup();
+
+    while app.world.resource::<Events<AppExit>>().is_empty() {
+        app.update();
+    }
+    app.world.get_resource::<ReplayFinished>().copied()
+}
+
+/// A seed from the current time.
+fn seed_from_clock() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos() as u64)
+}
+
+/// Append this frame's input events to the recording.
+#[allow(clippy::too_many_arguments)]
+fn record_inputs(
+    mut progress: ResMut<ReplayProgress>,
+    mut keyboard: EventReader<KeyboardInput>,
+    mut mouse_buttons: EventReader<MouseButtonInput>,
+    mut mouse_motion: EventReader<MouseMotion>,
+    mut mouse_wheel: EventReader<MouseWheel>,
+    mut gamepad: EventReader<GamepadEvent>,
+) {
+    let inputs = keyboard
+        .read()
+        .cloned()
+        .map(RecordedInput::Keyboard)
+        .chain(
+            mouse_buttons
+                .read()
+                .cloned()
+                .map(RecordedInput::MouseButton),
+        )
+        .chain(mouse_motion.read().cloned().map(RecordedInput::MouseMotion))
+        .chain(mouse_wheel.read().cloned().map(RecordedInput::MouseWheel))
+        .chain(gamepad.read().cloned().map(RecordedInput::Gamepad))
+        .collect();
+
+    let frame = progress.frame;
+    progress.replay.frames.push(ReplayFrame {
+        frame,
+        delta: Duration::ZERO,
+        inputs,
+    });
+    progress.frame += 1;
+}
+
+/// Add a gameplay tick to the frame being recorded.
+fn record_tick(mut progress: ResMut<ReplayProgress>, time: Res<Time<Fixed>>) {
+    if let Some(frame) = progress.replay.frames.last_mut() {
+        frame.delta += time.delta();
+    }
+}
+
+/// Write the recording every now and then, and with the final hash when the app exits.
+fn write_recording(world: &mut World) {
+    let exiting = !world.resource::<Events<AppExit>>().is_empty();
+    if exiting {
+        let hash = world_hash(world);
+        world.resource_mut::<ReplayProgress>().replay.final_hash = Some(hash);
+    }
+
+    let progress = world.resource::<ReplayProgress>();
+    if !exiting && !progress.frame.is_multiple_of(RECORD_FLUSH_FRAMES) {
+        return;
+    }
+    let ReplayMode::Record(path) = world.resource::<ReplayMode>() else {
+        return;
+    };
+    if let Err(err) = progress.replay.save(path) {
+        error!("Could not write {}: {}", path.display(), err);
+    }
+}
+
+/// Drop live input and send the recorded events of this frame instead.
+#[allow(clippy::too_many_arguments)]
+fn play_inputs(
+    progress: Res<ReplayProgress>,
+    mut keyboard: ResMut<Events<KeyboardInput>>,
+    mut mouse_buttons: ResMut<Events<MouseButtonInput>>,
+    mut mouse_motion: ResMut<Events<MouseMotion>>,
+    mut mouse_wheel: ResMut<Events<MouseWheel>>,
+    mut gamepad: ResMut<Events<GamepadEvent>>,
+) {
+    keyboard.clear();
+    mouse_buttons.clear();
+    mouse_motion.clear();
+    mouse_wheel.clear();
+    gamepad.clear();
+
+    let Some(frame) = progress.replay.frames.get(progress.frame as usize) else {
+        return;
+    };
+    for input in &frame.inputs {
+        match input.clone() {
+            RecordedInput::Keyboard(event) => {
+                keyboard.send(event);
+            }
+            RecordedInput::MouseButton(event) => {
+                mouse_buttons.send(event);
+            }
+            RecordedInput::MouseMotion(event) => {
+                mouse_motion.send(event);
+            }
+            RecordedInput::MouseWheel(event) => {
+                mouse_wheel.send(event);
+            }
+            RecordedInput::Gamepad(event) => {
+                gamepad.send(event);
+            }
+        }
+    }
+}
+
+/// Let frames advance by as many ticks as they did when recorded, even after a hitch.
+fn unlimit_frame_time(mut time: ResMut<Time<Virtual>>) {
+    time.set_max_delta(Duration::from_secs(3600));
+}
+
+/// Set up the timestep of the next frame, or finish once the replay ran out.
+fn finish_frame(world: &mut World) {
+    let mut progress = world.resource_mut::<ReplayProgress>();
+    if progress.finished {
+        return;
+    }
+    progress.frame += 1;
+
+    let next = progress.replay.frames.get(progress.frame as usize);
+    if let Some(delta) = next.map(|next| next.delta) {
+        world.insert_resource(TimeUpdateStrategy::ManualDuration(delta));
+        return;
+    }
+
+    progress.finished = true;
+    let expected = progress.replay.final_hash;
+    let hash = world_hash(world);
+    match expected {
+        Some(expected) if expected != hash => {
+            error!(
+                "Replay ended with hash {:016x}, expected {:016x}",
+                hash, expected
+            )
+        }
+        _ => info!("Replay ended with hash {:016x}", hash),
+    }
+    let finished = ReplayFinished { hash, expected };
+    world.insert_resource(finished);
+    world.send_event(finished);
+    world.send_event(AppExit);
+}
+
+/// FNV-1a, which unlike `DefaultHasher` is the same on every machine and build.
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn write_f32s(&mut self, values: &[f32]) {
+        for value in values {
+            self.write(&value.to_bits().to_le_bytes());
+        }
+    }
+}
+
+/// A hash of the gameplay state: the transform of every named entity, the logic graph, story
+/// flags and the active dimension. Entity ids and order don't matter.
+pub fn world_hash(world: &mut World) -> u64 {
+    let mut named: Vec<(String, [f32; 10])> = world
+        .query::<(&Name, &Transform)>()
+        .iter(world)
+        .map(|(name, transform)| {
+            let (t, r, s) = (transform.translation, transform.rotation, transform.scale);
+            let values = [t.x, t.y, t.z, r.x, r.y, r.z, r.w, s.x, s.y, s.z];
+            (name.to_string(), values)
+        })
+        .collect();
+    named.sort_by(|a, b| {
+        a.0.cmp(&b.0)
+            .then_with(|| a.1.map(f32::to_bits).cmp(&b.1.map(f32::to_bits)))
+    });
+
+    let mut hash = Fnv::new();
+    for (name, values) in &named {
+        hash.write(name.as_bytes());
+        hash.write_f32s(values);
+    }
+    if let Some(logic) = world.get_resource::<LogicState>() {
+        let mut values: Vec<_> = logic.values.iter().collect();
+        values.sort();
+        for (id, value) in values {
+            hash.write(id.as_bytes());
+            hash.write(&[u8::from(*value)]);
+        }
+    }
+    if let Some(flags) = world.get_resource::<StoryFlags>() {
+        for flag in &flags.0 {
+            hash.write(flag.as_bytes());
+        }
+    }
+    if let Some(blackboard) = world.get_resource::<Blackboard>() {
+        for (name, value) in &blackboard.variables {
+            hash.write(name.as_bytes());
+            hash.write(value.to_string().as_bytes());
+        }
+    }
+    if let Some(dimension) = world.get_resource::<ActiveDimension>() {
+        hash.write(&[dimension.0]);
+    }
+    hash.0
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::input::{
+        keyboard::{Key, NativeKey},
+        ButtonState, InputPlugin,
+    };
+
+    use super::*;
+
+    /// Walk the "walker" right while D is held, and move the "wanderer" by a seeded random step.
+    fn walk(
+        keys: Res<ButtonInput<KeyCode>>,
+        seed: Res<RngSeed>,
+        mut ticks: Local<u64>,
+        mut entities: Query<(&Name, &mut Transform)>,
+    ) {
+        for (name, mut transform) in &mut entities {
+            match name.as_str() {
+                "walker" if keys.pressed(KeyCode::KeyD) => transform.translation.x += 0.1,
+                "wanderer" => {
+                    transform.translation.z += (seed.roll("wanderer", *ticks) % 7) as f32 * 0.01;
+                }
+                _ => {}
+            }
+        }
+        *ticks += 1;
+    }
+
+    fn app(mode: ReplayMode) -> App {
+        let mut app = App::new();
+        app.insert_resource(mode)
+            .add_plugins((MinimalPlugins, InputPlugin, AstraliminalReplayPlugin))
+            .add_systems(FixedUpdate, walk);
+        app.world.spawn((Name::new("walker"), Transform::default()));
+        app.world
+            .spawn((Name::new("wanderer"), Transform::default()));
+        app
+    }
+
+    fn key(key_code: KeyCode, state: ButtonState) -> KeyboardInput {
+        KeyboardInput {
+            key_code,
+            logical_key: Key::Unidentified(NativeKey::Unidentified),
+            state,
+            window: Entity::PLACEHOLDER,
+        }
+    }
+
+    #[test]
+    fn replay_reproduces_recording() {
+        let path = std::env::temp_dir().join(format!("astral_replay_{}.ron", std::process::id()));
+
+        // Record frames of uneven length, as a real machine would.
+        let mut recording = app(ReplayMode::Record(path.clone()));
+        for frame in 0..120_u64 {
+            let millis = [3, 17, 40, 9, 26][frame as usize % 5];
+            let delta = Duration::from_millis(millis);
+            recording.insert_resource(TimeUpdateStrategy::ManualDuration(delta));
+            let state = match frame {
+                10 => Some(ButtonState::Pressed),
+                50 => Some(ButtonState::Released),
+                _ => None,
+            };
+            if let Some(state) = state {
+                recording.world.send_event(key(KeyCode::KeyD, state));
+            }
+            if frame == 119 {
+                recording.world.send_event(AppExit);
+            }
+            recording.update();
+        }
+        let walked = recording
+            .world
+            .query::<(&Name, &Transform)>()
+            .iter(&recording.world)
+            .find(|(name, _)| name.as_str() == "walker")
+            .map(|(_, transform)| transform.translation.x);
+        assert!(walked.is_some_and(|x| x > 1.0), "walked {walked:?}");
+
+        let replay = Replay::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(replay.frames.len(), 120);
+        assert!(replay.final_hash.is_some());
+
+        let mut playback = app(ReplayMode::Play(replay.clone()));
+        let finished = run_replay(&mut playback).expect("replay finishes");
+        assert_eq!(Some(finished.hash), replay.final_hash);
+    }
+}
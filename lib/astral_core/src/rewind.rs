@@ -0,0 +1,349 @@
+//! Astraliminal's Rewind plugin.
+//!
+//! `Rewindable` entities and the logic graph are recorded at a fixed rate into a `RewindBuffer`
+//! that holds the last few seconds. While the rewind key is held, physics is paused and the
+//! recorded frames are played back newest first, one per step, so a wrong resize can be undone.
+//! Releasing the key continues from the frame reached; what came after it is forgotten.
+
+use std::{collections::VecDeque, time::Duration};
+
+use bevy::{prelude::*, utils::HashMap};
+use bevy_xpbd_3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{logic::LogicState, player::input_allowed, schedule::AstralSet};
+
+/// Marker for entities whose transform, scale and velocity are recorded.
+#[derive(Component, Reflect, Debug, Default, Clone, Copy)]
+#[reflect(Component)]
+pub struct Rewindable;
+
+/// How often and how long to record. Memory use grows with `rate * seconds` times the number of
+/// `Rewindable` entities.
+#[derive(Resource, Reflect, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[reflect(Resource)]
+#[serde(default)]
+pub struct RewindSettings {
+    /// Frames recorded, and played back, per second.
+    pub rate: f32,
+    /// Seconds of history to keep.
+    pub seconds: f32,
+}
+
+impl Default for RewindSettings {
+    fn default() -> Self {
+        Self {
+            rate: 30.0,
+            seconds: 10.0,
+        }
+    }
+}
+
+impl RewindSettings {
+    /// Time between recorded frames.
+    pub fn step(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / f64::from(self.rate.max(1.0)))
+    }
+
+    /// Number of frames to keep.
+    pub fn capacity(&self) -> usize {
+        (self.rate.max(1.0) * self.seconds.max(0.0)).ceil() as usize
+    }
+}
+
+/// The recorded state of one entity.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RewindEntry {
+    pub transform: Transform,
+    pub linear_velocity: Option<Vec3>,
+    pub angular_velocity: Option<Vec3>,
+}
+
+/// Everything recorded in one step.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RewindFrame {
+    pub entities: HashMap<Entity, RewindEntry>,
+    pub logic: Option<LogicState>,
+}
+
+/// The recorded frames, oldest first, and whether they are being played back.
+#[derive(Resource, Debug, Default, Clone)]
+pub struct RewindBuffer {
+    pub frames: VecDeque<RewindFrame>,
+    rewinding: bool,
+    accumulator: Duration,
+}
+
+impl RewindBuffer {
+    /// Whether the player is rewinding.
+    pub fn is_rewinding(&self) -> bool {
+        self.rewinding
+    }
+
+    /// Add a frame, dropping the oldest ones beyond `capacity`.
+    pub fn push(&mut self, frame: RewindFrame, capacity: usize) {
+        self.frames.push_back(frame);
+        while self.frames.len() > capacity {
+            self.frames.pop_front();
+        }
+    }
+
+    /// Advance the clock by `delta` and return how many steps of `step` have passed.
+    fn ticks(&mut self, delta: Duration, step: Duration) -> u32 {
+        self.accumulator += delta;
+        let mut ticks = 0;
+        while self.accumulator >= step {
+            self.accumulator -= step;
+            ticks += 1;
+        }
+        ticks
+    }
+}
+
+pub struct AstraliminalRewindPlugin;
+
+impl Plugin for AstraliminalRewindPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Rewindable>()
+            .register_type::<RewindSettings>()
+            .init_resource::<RewindSettings>()
+            .init_resource::<RewindBuffer>()
+            .add_systems(
+                FixedUpdate,
+                (
+                    rewind_input.run_if(input_allowed),
+                    record.run_if(not(is_rewinding)),
+                    play_back.run_if(is_rewinding),
+                )
+                    .chain()
+                    .after(AstralSet::Resolve),
+            );
+    }
+}
+
+/// Run condition for while the player is rewinding.
+fn is_rewinding(buffer: Res<RewindBuffer>) -> bool {
+    buffer.is_rewinding()
+}
+
+/// Start rewinding while the rewind key is held, and pause physics meanwhile.
+fn rewind_input(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut buffer: ResMut<RewindBuffer>,
+    physics_time: Option<ResMut<Time<Physics>>>,
+) {
+    // TODO: pull key code from config.
+    let held = keys.pressed(KeyCode::KeyQ);
+    if held == buffer.rewinding {
+        return;
+    }
+
+    buffer.rewinding = held;
+    buffer.accumulator = Duration::ZERO;
+    if let Some(mut physics_time) = physics_time {
+        if held {
+            physics_time.pause();
+        } else {
+            physics_time.unpause();
+        }
+    }
+}
+
+/// What is recorded of rewindable entities.
+type RecordedQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Transform,
+        Option<&'static LinearVelocity>,
+        Option<&'static AngularVelocity>,
+    ),
+    With<Rewindable>,
+>;
+
+/// What is played back onto rewindable entities.
+type PlaybackQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static mut Transform,
+        Option<&'static mut LinearVelocity>,
+        Option<&'static mut AngularVelocity>,
+    ),
+    With<Rewindable>,
+>;
+
+/// Record a frame every step.
+fn record(
+    mut buffer: ResMut<RewindBuffer>,
+    time: Res<Time>,
+    settings: Res<RewindSettings>,
+    logic: Option<Res<LogicState>>,
+    entities: RecordedQuery,
+) {
+    if buffer.ticks(time.delta(), settings.step()) == 0 {
+        return;
+    }
+
+    let entities = entities
+        .iter()
+        .map(|(entity, transform, linear, angular)| {
+            let entry = RewindEntry {
+                transform: *transform,
+                linear_velocity: linear.map(|velocity| velocity.0),
+                angular_velocity: angular.map(|velocity| velocity.0),
+            };
+            (entity, entry)
+        })
+        .collect();
+    let frame = RewindFrame {
+        entities,
+        logic: logic.map(|logic| logic.clone()),
+    };
+    buffer.push(frame, settings.capacity());
+}
+
+/// Step back one frame every step, keeping the oldest frame once the buffer runs out.
+fn play_back(
+    mut buffer: ResMut<RewindBuffer>,
+    time: Res<Time>,
+    settings: Res<RewindSettings>,
+    logic: Option<ResMut<LogicState>>,
+    mut entities: PlaybackQuery,
+) {
+    let ticks = buffer.ticks(time.delta(), settings.step());
+    let mut frame = None;
+    for _ in 0..ticks {
+        if buffer.frames.len() <= 1 {
+            frame = buffer.frames.back().cloned();
+            break;
+        }
+        frame = buffer.frames.pop_back();
+    }
+    let Some(frame) = frame else {
+        return;
+    };
+
+    for (&entity, entry) in &frame.entities {
+        let Ok((mut transform, linear, angular)) = entities.get_mut(entity) else {
+            continue;
+        };
+        *transform = entry.transform;
+        if let (Some(velocity), Some(mut linear)) = (entry.linear_velocity, linear) {
+            linear.0 = velocity;
+        }
+        if let (Some(velocity), Some(mut angular)) = (entry.angular_velocity, angular) {
+            angular.0 = velocity;
+        }
+    }
+    if let (Some(recorded), Some(mut logic)) = (frame.logic, logic) {
+        *logic = recorded;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::time::TimeUpdateStrategy;
+
+    use super::*;
+
+    /// Frames per second of both the app and the recording.
+    const RATE: f32 = 60.0;
+
+    /// Move every rewindable body a little and grow it, unless the player is rewinding.
+    fn drift(buffer: Res<RewindBuffer>, mut entities: Query<&mut Transform, With<Rewindable>>) {
+        if buffer.is_rewinding() {
+            return;
+        }
+        for mut transform in &mut entities {
+            transform.translation += Vec3::new(0.1, -0.05, 0.0);
+            transform.scale *= 1.01;
+        }
+    }
+
+    /// A headless app that records a drifting body every frame and keeps one second.
+    fn app() -> (App, Entity) {
+        let mut app = App::new();
+        let settings = RewindSettings {
+            rate: RATE,
+            seconds: 1.0,
+        };
+        app.add_plugins((MinimalPlugins, AstraliminalRewindPlugin))
+            .insert_resource(settings)
+            .insert_resource(Time::<Fixed>::from_duration(settings.step()))
+            .insert_resource(TimeUpdateStrategy::ManualDuration(settings.step()))
+            .init_resource::<ButtonInput<KeyCode>>()
+            .add_systems(FixedUpdate, drift.before(rewind_input));
+        let entity = app
+            .world
+            .spawn((Rewindable, Transform::default(), LinearVelocity(Vec3::X)))
+            .id();
+        // The first update has no delta.
+        app.update();
+        (app, entity)
+    }
+
+    fn transform(app: &App, entity: Entity) -> Transform {
+        *app.world.get::<Transform>(entity).unwrap()
+    }
+
+    #[test]
+    fn buffer_is_bounded() {
+        let (mut app, _) = app();
+        for _ in 0..200 {
+            app.update();
+        }
+        assert_eq!(app.world.resource::<RewindBuffer>().frames.len(), 60);
+    }
+
+    #[test]
+    fn playback_retraces_recording() {
+        let (mut app, entity) = app();
+        let mut recorded = Vec::new();
+        for _ in 0..30 {
+            app.update();
+            recorded.push(transform(&app, entity));
+        }
+
+        app.world
+            .resource_mut::<ButtonInput<KeyCode>>()
+            .press(KeyCode::KeyQ);
+        for expected in recorded.iter().rev() {
+            app.update();
+            assert_eq!(transform(&app, entity), *expected);
+        }
+
+        // Holding the key past the oldest frame stays there.
+        app.update();
+        assert_eq!(transform(&app, entity), recorded[0]);
+
+        app.world
+            .resource_mut::<ButtonInput<KeyCode>>()
+            .release(KeyCode::KeyQ);
+        // Gameplay sees the release one frame later.
+        app.update();
+        app.update();
+        assert!(transform(&app, entity).translation.x > recorded[0].translation.x);
+    }
+
+    #[test]
+    fn playback_is_deterministic() {
+        let run = || {
+            let (mut app, entity) = app();
+            for _ in 0..45 {
+                app.update();
+            }
+            app.world
+                .resource_mut::<ButtonInput<KeyCode>>()
+                .press(KeyCode::KeyQ);
+            (0..20)
+                .map(|_| {
+                    app.update();
+                    transform(&app, entity)
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(run(), run());
+    }
+}
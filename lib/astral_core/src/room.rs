@@ -0,0 +1,82 @@
+//! Astraliminal's Room plugin.
+//!
+//! A level is split into rooms. Each room has a sensor collider that covers it, and the room the
+//! player stands in is the `CurrentRoom`. Rooms spawned from a `RoomGraph` don't need sensors;
+//! the current room changes when the player goes through a seam.
+
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+
+use crate::{player::Player, schedule::AstralSet};
+
+/// A room of a level. Needs a `Collider` and a `Sensor` covering the room, unless it comes from a
+/// `RoomGraph`.
+#[derive(Component, Reflect, Debug, Default, Clone)]
+#[reflect(Component)]
+pub struct Room {
+    /// Unique id of the room.
+    pub id: String,
+}
+
+/// The id of the room the player is in, if any.
+#[derive(Resource, Reflect, Debug, Default, Clone, PartialEq, Eq)]
+#[reflect(Resource)]
+pub struct CurrentRoom(pub Option<String>);
+
+/// Sent when the player moves from one room to another.
+#[derive(Event, Debug, Clone, PartialEq, Eq)]
+pub struct RoomChanged {
+    /// The room the player left.
+    pub from: Option<String>,
+    /// The room the player entered.
+    pub to: Option<String>,
+}
+
+pub struct AstraliminalRoomPlugin;
+
+impl Plugin for AstraliminalRoomPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Room>()
+            .register_type::<CurrentRoom>()
+            .init_resource::<CurrentRoom>()
+            .add_event::<RoomChanged>()
+            .add_systems(FixedUpdate, track_current_room.in_set(AstralSet::Sense));
+    }
+}
+
+/// Update `CurrentRoom` from the room sensors the player touches. When the player is in two
+/// rooms at once, e.g. in a doorway, the room they were already in wins.
+pub(crate) fn track_current_room(
+    mut current: ResMut<CurrentRoom>,
+    mut changed: EventWriter<RoomChanged>,
+    player: Query<Entity, With<Player>>,
+    rooms: Query<(&Room, &CollidingEntities)>,
+) {
+    let Ok(player) = player.get_single() else {
+        return;
+    };
+
+    let in_room = |id: &str| {
+        rooms
+            .iter()
+            .any(|(room, colliding)| room.id == id && colliding.contains(&player))
+    };
+
+    if current.0.as_deref().is_some_and(in_room) {
+        return;
+    }
+
+    // Keep the last room while the player is between sensors.
+    let Some((next, _)) = rooms
+        .iter()
+        .find(|(_, colliding)| colliding.contains(&player))
+    else {
+        return;
+    };
+
+    let from = current.0.replace(next.id.clone());
+    changed.send(RoomChanged {
+        from,
+        to: Some(next.id.clone()),
+    });
+}
@@ -0,0 +1,587 @@
+//! Astraliminal's Room Graph plugin.
+//!
+//! Impossible spaces are built from rooms that each live in their own coordinate frame and are
+//! joined by seams. A seam is a pair of portals, one in each room, so a corridor can lead back
+//! into itself or a closet can open into a hall larger than the closet. Only rooms close to the
+//! player in the graph are spawned.
+
+use std::{
+    collections::{BTreeSet, VecDeque},
+    fmt, fs,
+    path::Path,
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    level::LevelEntity,
+    player::Player,
+    portal::{Portal, PortalCrossed},
+    ron_loader::{RonLoader, RonLoaderError},
+    room::{CurrentRoom, Room, RoomChanged},
+    schedule::AstralSet,
+};
+
+/// Default number of seams away from the current room at which rooms are still spawned.
+const ACTIVE_DEPTH: usize = 1;
+
+/// Rooms and the seams between them, loaded from a `.rooms.ron` file.
+#[derive(Asset, TypePath, Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoomGraph {
+    pub rooms: Vec<RoomDef>,
+    pub seams: Vec<SeamDef>,
+}
+
+/// A room in a `RoomGraph`.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoomDef {
+    /// Unique id of the room.
+    pub id: String,
+    /// Asset path of the room's scene, e.g. `levels/closet.glb#Scene0`.
+    pub scene: String,
+    /// Where the room's coordinate frame is placed in the world. Frames must not overlap.
+    pub origin: Transform,
+}
+
+/// A seam joining two rooms, or one room to itself.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SeamDef {
+    pub a: SeamEnd,
+    pub b: SeamEnd,
+}
+
+/// One side of a seam.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SeamEnd {
+    /// The room this side is in.
+    pub room: String,
+    /// The portal's transform in the room's frame. The portal faces local +Z.
+    pub transform: Transform,
+    /// Half of the opening's width and height.
+    pub half_size: Vec2,
+}
+
+/// Problems found by `RoomGraph::validate`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SeamIssue {
+    /// Two rooms share an id.
+    DuplicateRoom(String),
+    /// A seam refers to a room that does not exist.
+    UnknownRoom { seam: usize, room: String },
+    /// A room can't be reached through seams from the first room, where the player starts.
+    UnconnectedRoom(String),
+    /// A seam has an opening with no area.
+    EmptySeam { seam: usize },
+    /// The two openings of a seam have different proportions, so things would be stretched.
+    MismatchedShape { seam: usize },
+    /// Two seams use the same opening.
+    SharedOpening { first: usize, second: usize },
+}
+
+impl fmt::Display for SeamIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DuplicateRoom(room) => write!(f, "room {} is defined more than once", room),
+            Self::UnknownRoom { seam, room } => {
+                write!(f, "seam {} refers to unknown room {}", seam, room)
+            }
+            Self::UnconnectedRoom(room) => {
+                write!(f, "room {} can't be reached from the first room", room)
+            }
+            Self::EmptySeam { seam } => write!(f, "seam {} has an opening with no area", seam),
+            Self::MismatchedShape { seam } => {
+                write!(f, "seam {} has openings with different proportions", seam)
+            }
+            Self::SharedOpening { first, second } => {
+                write!(f, "seams {} and {} use the same opening", first, second)
+            }
+        }
+    }
+}
+
+impl SeamEnd {
+    /// The opening's size in world units, given the room it is in.
+    fn world_size(&self, room: &RoomDef) -> Vec2 {
+        let scale = room.origin.scale * self.transform.scale;
+        self.half_size * scale.truncate() * 2.0
+    }
+}
+
+impl RoomGraph {
+    /// Read a room graph from a RON file, e.g. for validation outside of the game.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RonLoaderError> {
+        Ok(ron::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// The room with the given id.
+    pub fn room(&self, id: &str) -> Option<&RoomDef> {
+        self.rooms.iter().find(|room| room.id == id)
+    }
+
+    /// Ids of the rooms reachable from `start` through at most `depth` seams, `start` included.
+    pub fn rooms_within(&self, start: &str, depth: usize) -> BTreeSet<String> {
+        let mut found = BTreeSet::from([start.to_string()]);
+        let mut queue = VecDeque::from([(start.to_string(), 0)]);
+
+        while let Some((room, distance)) = queue.pop_front() {
+            if distance == depth {
+                continue;
+            }
+            for seam in &self.seams {
+                let next = if seam.a.room == room {
+                    &seam.b.room
+                } else if seam.b.room == room {
+                    &seam.a.room
+                } else {
+                    continue;
+                };
+                if found.insert(next.clone()) {
+                    queue.push_back((next.clone(), distance + 1));
+                }
+            }
+        }
+
+        found
+    }
+
+    /// World transform of a seam end, or `None` if its room doesn't exist.
+    pub fn seam_end_transform(&self, end: &SeamEnd) -> Option<Transform> {
+        let room = self.room(&end.room)?;
+        Some(room.origin.mul_transform(end.transform))
+    }
+
+    /// Look for rooms and seams that can't work.
+    pub fn validate(&self) -> Vec<SeamIssue> {
+        let mut issues = Vec::new();
+
+        let mut ids = BTreeSet::new();
+        for room in &self.rooms {
+            if !ids.insert(room.id.as_str()) {
+                issues.push(SeamIssue::DuplicateRoom(room.id.clone()));
+            }
+        }
+
+        for (index, seam) in self.seams.iter().enumerate() {
+            let mut sizes = Vec::new();
+            for end in [&seam.a, &seam.b] {
+                match self.room(&end.room) {
+                    Some(room) => sizes.push(end.world_size(room)),
+                    None => issues.push(SeamIssue::UnknownRoom {
+                        seam: index,
+                        room: end.room.clone(),
+                    }),
+                }
+            }
+
+            if sizes.iter().any(|size| size.x <= 0.0 || size.y <= 0.0) {
+                issues.push(SeamIssue::EmptySeam { seam: index });
+            } else if let [a, b] = sizes[..] {
+                if (a.x / a.y - b.x / b.y).abs() > 1e-3 {
+                    issues.push(SeamIssue::MismatchedShape { seam: index });
+                }
+            }
+
+            for (other_index, other) in self.seams.iter().enumerate().skip(index + 1) {
+                let shared = [&seam.a, &seam.b]
+                    .iter()
+                    .any(|end| *end == &other.a || *end == &other.b);
+                if shared {
+                    issues.push(SeamIssue::SharedOpening {
+                        first: index,
+                        second: other_index,
+                    });
+                }
+            }
+        }
+
+        if let Some(first) = self.rooms.first() {
+            let mut reachable = self.rooms_within(&first.id, usize::MAX);
+            for room in &self.rooms {
+                // Inserting also reports a duplicated id only once.
+                if reachable.insert(room.id.clone()) {
+                    issues.push(SeamIssue::UnconnectedRoom(room.id.clone()));
+                }
+            }
+        }
+
+        issues
+    }
+}
+
+/// The room graph of the current level.
+#[derive(Resource, Debug, Clone)]
+pub struct LevelRooms(pub Handle<RoomGraph>);
+
+/// How much of the room graph is kept spawned.
+#[derive(Resource, Reflect, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[reflect(Resource)]
+#[serde(default)]
+pub struct RoomGraphSettings {
+    /// Rooms up to this many seams away from the current room are spawned.
+    pub active_depth: usize,
+}
+
+impl Default for RoomGraphSettings {
+    fn default() -> Self {
+        Self {
+            active_depth: ACTIVE_DEPTH,
+        }
+    }
+}
+
+/// Ids of the rooms that are currently spawned.
+#[derive(Resource, Debug, Default, Clone, PartialEq, Eq)]
+pub struct ActiveRooms(pub BTreeSet<String>);
+
+/// Marker for the root of a room spawned from the `RoomGraph`.
+#[derive(Component, Reflect, Debug, Default, Clone, Copy)]
+#[reflect(Component)]
+pub struct GraphRoom;
+
+/// One of the two portals of a seam.
+#[derive(Component, Reflect, Debug, Default, Clone)]
+#[reflect(Component)]
+pub struct SeamPortal {
+    /// Index of the seam in the `RoomGraph`.
+    pub seam: usize,
+    /// The room this portal is in.
+    pub room: String,
+    /// The room on the other side.
+    pub to_room: String,
+}
+
+/// The room a moving entity is in. Updated when it goes through a seam.
+#[derive(Component, Reflect, Debug, Default, Clone, PartialEq, Eq)]
+#[reflect(Component)]
+pub struct InRoom(pub String);
+
+pub struct AstraliminalRoomGraphPlugin;
+
+impl Plugin for AstraliminalRoomGraphPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<RoomGraph>()
+            .register_asset_loader(RonLoader::<RoomGraph>::new(&["rooms.ron"]))
+            .register_type::<RoomGraphSettings>()
+            .register_type::<GraphRoom>()
+            .register_type::<SeamPortal>()
+            .register_type::<InRoom>()
+            .init_resource::<RoomGraphSettings>()
+            .init_resource::<ActiveRooms>()
+            .add_systems(
+                FixedUpdate,
+                (
+                    enter_first_room.in_set(AstralSet::Setup),
+                    move_between_rooms
+                        .after(crate::portal::cross_portals)
+                        .in_set(AstralSet::Player),
+                    update_active_rooms.in_set(AstralSet::Resolve),
+                )
+                    .run_if(resource_exists::<LevelRooms>),
+            );
+    }
+}
+
+/// Put the player in the graph's first room when a level starts.
+fn enter_first_room(
+    mut current: ResMut<CurrentRoom>,
+    mut changed: EventWriter<RoomChanged>,
+    level: Res<LevelRooms>,
+    graphs: Res<Assets<RoomGraph>>,
+) {
+    if current.0.is_some() {
+        return;
+    }
+    let Some(first) = graphs.get(&level.0).and_then(|graph| graph.rooms.first()) else {
+        return;
+    };
+
+    current.0 = Some(first.id.clone());
+    changed.send(RoomChanged {
+        from: None,
+        to: Some(first.id.clone()),
+    });
+}
+
+/// Keep track of which room things are in as they go through seams.
+fn move_between_rooms(
+    mut commands: Commands,
+    mut crossed: EventReader<PortalCrossed>,
+    mut current: ResMut<CurrentRoom>,
+    mut changed: EventWriter<RoomChanged>,
+    seams: Query<&SeamPortal>,
+    player: Query<(), With<Player>>,
+) {
+    for crossing in crossed.read() {
+        let Ok(seam) = seams.get(crossing.from) else {
+            continue;
+        };
+
+        if player.contains(crossing.entity) {
+            let from = current.0.replace(seam.to_room.clone());
+            if from.as_ref() != Some(&seam.to_room) {
+                changed.send(RoomChanged {
+                    from,
+                    to: Some(seam.to_room.clone()),
+                });
+            }
+        } else {
+            commands
+                .entity(crossing.entity)
+                .insert(InRoom(seam.to_room.clone()));
+        }
+    }
+}
+
+/// Spawn the rooms near the current room and the seams between them, and despawn the rest.
+#[allow(clippy::too_many_arguments)]
+fn update_active_rooms(
+    mut commands: Commands,
+    mut active: ResMut<ActiveRooms>,
+    asset_server: Res<AssetServer>,
+    current: Res<CurrentRoom>,
+    settings: Res<RoomGraphSettings>,
+    level: Res<LevelRooms>,
+    graphs: Res<Assets<RoomGraph>>,
+    rooms: Query<(Entity, &Room), With<GraphRoom>>,
+    seam_portals: Query<(Entity, &SeamPortal)>,
+) {
+    let (Some(graph), Some(room)) = (graphs.get(&level.0), &current.0) else {
+        return;
+    };
+
+    let wanted = graph.rooms_within(room, settings.active_depth);
+    if wanted == active.0 {
+        return;
+    }
+
+    for (entity, room) in &rooms {
+        if !wanted.contains(&room.id) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+    for def in graph.rooms.iter().filter(|def| wanted.contains(&def.id)) {
+        if active.0.contains(&def.id) {
+            continue;
+        }
+        commands.spawn((
+            SceneBundle {
+                scene: asset_server.load(&def.scene),
+                transform: def.origin,
+                ..default()
+            },
+            Room { id: def.id.clone() },
+            GraphRoom,
+            LevelEntity,
+            Name::new(def.id.clone()),
+        ));
+    }
+
+    let mut spawned_seams = BTreeSet::new();
+    for (entity, portal) in &seam_portals {
+        if wanted.contains(&portal.room) && wanted.contains(&portal.to_room) {
+            spawned_seams.insert(portal.seam);
+        } else {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+    for (index, seam) in graph.seams.iter().enumerate() {
+        if spawned_seams.contains(&index)
+            || !wanted.contains(&seam.a.room)
+            || !wanted.contains(&seam.b.room)
+        {
+            continue;
+        }
+        let (Some(a_transform), Some(b_transform)) = (
+            graph.seam_end_transform(&seam.a),
+            graph.seam_end_transform(&seam.b),
+        ) else {
+            continue;
+        };
+
+        let a = commands.spawn_empty().id();
+        let b = commands.spawn_empty().id();
+        for (entity, target, end, other, transform) in [
+            (a, b, &seam.a, &seam.b, a_transform),
+            (b, a, &seam.b, &seam.a, b_transform),
+        ] {
+            commands.entity(entity).insert((
+                SpatialBundle::from_transform(transform),
+                Portal {
+                    target,
+                    half_size: end.half_size,
+                },
+                SeamPortal {
+                    seam: index,
+                    room: end.room.clone(),
+                    to_room: other.room.clone(),
+                },
+                Name::new(format!("Seam {} ({})", index, end.room)),
+                LevelEntity,
+            ));
+        }
+    }
+
+    active.0 = wanted;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn room(id: &str, x: f32) -> RoomDef {
+        RoomDef {
+            id: id.to_string(),
+            scene: format!("levels/{id}.glb#Scene0"),
+            origin: Transform::from_xyz(x, 0.0, 0.0),
+        }
+    }
+
+    fn end(room: &str, x: f32) -> SeamEnd {
+        SeamEnd {
+            room: room.to_string(),
+            transform: Transform::from_xyz(x, 0.0, 0.0),
+            half_size: Vec2::new(1.0, 2.0),
+        }
+    }
+
+    fn seam(a: &str, b: &str) -> SeamDef {
+        SeamDef {
+            a: end(a, 1.0),
+            b: end(b, -1.0),
+        }
+    }
+
+    /// A corridor of rooms, each joined to the next.
+    fn corridor() -> RoomGraph {
+        RoomGraph {
+            rooms: vec![
+                room("closet", 0.0),
+                room("hall", 100.0),
+                room("stairs", 200.0),
+                room("roof", 300.0),
+            ],
+            seams: vec![
+                seam("closet", "hall"),
+                seam("hall", "stairs"),
+                seam("stairs", "roof"),
+            ],
+        }
+    }
+
+    fn ids(ids: &[&str]) -> BTreeSet<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn valid_graph_has_no_issues() {
+        assert_eq!(corridor().validate(), Vec::new());
+    }
+
+    #[test]
+    fn rooms_within_stops_at_depth() {
+        let graph = corridor();
+        assert_eq!(graph.rooms_within("closet", 0), ids(&["closet"]));
+        assert_eq!(graph.rooms_within("closet", 1), ids(&["closet", "hall"]));
+        assert_eq!(
+            graph.rooms_within("hall", 1),
+            ids(&["closet", "hall", "stairs"])
+        );
+        assert_eq!(
+            graph.rooms_within("closet", 10),
+            ids(&["closet", "hall", "stairs", "roof"])
+        );
+    }
+
+    #[test]
+    fn seam_into_itself_is_one_room() {
+        let mut graph = corridor();
+        graph.seams.push(SeamDef {
+            a: end("roof", 5.0),
+            b: end("roof", -5.0),
+        });
+        assert_eq!(graph.validate(), Vec::new());
+        assert_eq!(
+            graph.rooms_within("roof", 2),
+            ids(&["hall", "roof", "stairs"])
+        );
+    }
+
+    #[test]
+    fn broken_seams_are_reported() {
+        let mut graph = corridor();
+        // Seams 3 to 5 each open somewhere new; seam 6 reuses the openings of seam 0.
+        let mut unknown = seam("hall", "attic");
+        unknown.a.transform.translation.y = 3.0;
+        graph.seams.push(unknown);
+        let mut empty = seam("closet", "roof");
+        empty.a.transform.translation.y = 3.0;
+        empty.b.transform.translation.y = 3.0;
+        empty.a.half_size = Vec2::new(0.0, 2.0);
+        graph.seams.push(empty);
+        let mut stretched = seam("closet", "stairs");
+        stretched.a.transform.translation.y = 6.0;
+        stretched.b.transform.translation.y = 6.0;
+        stretched.b.transform.scale = Vec3::new(2.0, 1.0, 1.0);
+        graph.seams.push(stretched);
+        graph.seams.push(seam("closet", "hall"));
+
+        assert_eq!(
+            graph.validate(),
+            vec![
+                SeamIssue::SharedOpening {
+                    first: 0,
+                    second: 6
+                },
+                SeamIssue::UnknownRoom {
+                    seam: 3,
+                    room: "attic".to_string()
+                },
+                SeamIssue::EmptySeam { seam: 4 },
+                SeamIssue::MismatchedShape { seam: 5 },
+            ]
+        );
+    }
+
+    #[test]
+    fn rooms_out_of_reach_are_reported() {
+        let mut graph = corridor();
+        graph.rooms.push(room("vault", 400.0));
+        graph.rooms.push(room("hall", 500.0));
+        assert_eq!(
+            graph.validate(),
+            vec![
+                SeamIssue::DuplicateRoom("hall".to_string()),
+                SeamIssue::UnconnectedRoom("vault".to_string()),
+            ]
+        );
+        assert_eq!(graph.rooms_within("vault", 5), ids(&["vault"]));
+
+        // A single room needs no seams.
+        let single = RoomGraph {
+            rooms: vec![room("vault", 0.0)],
+            seams: Vec::new(),
+        };
+        assert_eq!(single.validate(), Vec::new());
+    }
+
+    #[test]
+    fn islands_are_unreachable() {
+        let mut graph = corridor();
+        // Two rooms joined to each other, but not to the rest of the level.
+        graph.rooms.push(room("island", 400.0));
+        graph.rooms.push(room("lighthouse", 500.0));
+        graph.seams.push(seam("island", "lighthouse"));
+        assert_eq!(
+            graph.validate(),
+            vec![
+                SeamIssue::UnconnectedRoom("island".to_string()),
+                SeamIssue::UnconnectedRoom("lighthouse".to_string()),
+            ]
+        );
+        assert_eq!(
+            graph.rooms_within("island", usize::MAX),
+            ids(&["island", "lighthouse"])
+        );
+    }
+}
@@ -0,0 +1,183 @@
+//! Astraliminal's Save plugin.
+//!
+//! All persistent game state lives in `SaveData`, which is written to disk as RON.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs, io,
+    path::Path,
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::expression::Value;
+
+/// Default save file, relative to the working directory.
+// TODO: Store saves in the platform's data directory.
+const SAVE_FILE: &str = "astraliminal.sav.ron";
+
+/// Everything that persists between sessions.
+#[derive(Resource, Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SaveData {
+    /// Paintings that have been materialized, keyed by painting id, with the spawned transform.
+    pub materialized_paintings: BTreeMap<String, Transform>,
+    /// Duplicated objects that are alive in their room.
+    pub clones: Vec<SavedClone>,
+    /// The dimension layer the player is in.
+    pub dimension: u8,
+    /// Story flags that are set.
+    pub story_flags: BTreeSet<String>,
+    /// Story variables by name.
+    pub variables: BTreeMap<String, Value>,
+    /// Ids of the dialogue nodes reached.
+    pub visited_dialogue: BTreeSet<String>,
+    /// Achievements unlocked.
+    pub achievements: BTreeSet<String>,
+    /// Endings reached, in any playthrough.
+    pub endings_seen: BTreeSet<String>,
+    /// The last checkpoint the player reached.
+    pub checkpoint: Option<SavedCheckpoint>,
+    /// What each NPC remembers of talking to the player, keyed by NPC id.
+    pub npcs: BTreeMap<String, SavedNpc>,
+}
+
+/// A duplicated object, saved so it comes back where the player left it.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedClone {
+    /// The room the clone was made in.
+    pub room: String,
+    /// The `Name` of the object it was cloned from.
+    pub source: String,
+    /// The clone's transform, including its scale.
+    pub transform: Transform,
+}
+
+/// The last checkpoint, saved so the game continues from it.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedCheckpoint {
+    /// Id of the checkpoint.
+    pub id: String,
+    /// Where the player was.
+    pub player: Transform,
+}
+
+/// What an NPC remembers of talking to the player.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedNpc {
+    /// Conversations finished with them.
+    pub talks: u32,
+    /// Dialogue node the next conversation starts at, if the script moved it on.
+    pub next: Option<String>,
+}
+
+impl SaveData {
+    /// Read save data from a RON file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SaveError> {
+        let contents = fs::read_to_string(path)?;
+        Ok(ron::from_str(&contents)?)
+    }
+
+    /// Write save data to a RON file.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SaveError> {
+        let contents = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        fs::write(path, contents)?;
+        Ok(())
+    }
+}
+
+/// Errors that can happen while reading or writing save data.
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    Parse(ron::error::SpannedError),
+    Serialize(ron::Error),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "save file I/O error: {}", err),
+            Self::Parse(err) => write!(f, "save file is corrupt: {}", err),
+            Self::Serialize(err) => write!(f, "could not serialize save data: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+impl From<io::Error> for SaveError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<ron::error::SpannedError> for SaveError {
+    fn from(err: ron::error::SpannedError) -> Self {
+        Self::Parse(err)
+    }
+}
+
+impl From<ron::Error> for SaveError {
+    fn from(err: ron::Error) -> Self {
+        Self::Serialize(err)
+    }
+}
+
+/// Systems that write to disk run in `SaveSet::Write`. Systems that copy world state into
+/// `SaveData` right before a save should run in `SaveSet::Collect`, and systems that copy it back
+/// into world state in `SaveSet::Restore`, which runs in `PreUpdate` after `SaveLoaded`.
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SaveSet {
+    Restore,
+    Collect,
+    Write,
+}
+
+/// Send after loading or replacing `SaveData`, e.g. for a new game, to restore world state from it.
+#[derive(Event, Debug, Default, Clone, Copy)]
+pub struct SaveLoaded;
+
+/// Send to write the current `SaveData` to disk.
+#[derive(Event, Debug, Default, Clone, Copy)]
+pub struct SaveGame;
+
+pub struct AstraliminalSavePlugin;
+
+impl Plugin for AstraliminalSavePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SaveData>()
+            .add_event::<SaveGame>()
+            .add_event::<SaveLoaded>()
+            .configure_sets(PreUpdate, SaveSet::Restore.run_if(on_event::<SaveLoaded>()))
+            .configure_sets(
+                Last,
+                (SaveSet::Collect, SaveSet::Write)
+                    .chain()
+                    .run_if(on_event::<SaveGame>()),
+            )
+            .add_systems(PreStartup, load_save)
+            .add_systems(Last, write_save.in_set(SaveSet::Write));
+    }
+}
+
+/// Load the save file, if there is one.
+fn load_save(mut save: ResMut<SaveData>, mut loaded: EventWriter<SaveLoaded>) {
+    loaded.send(SaveLoaded);
+    if !Path::new(SAVE_FILE).exists() {
+        return;
+    }
+
+    match SaveData::load(SAVE_FILE) {
+        Ok(data) => *save = data,
+        Err(err) => error!("Could not load {}: {}", SAVE_FILE, err),
+    }
+}
+
+/// Write the save file. Only runs when a `SaveGame` event was sent this frame.
+fn write_save(save: Res<SaveData>) {
+    if let Err(err) = save.save(SAVE_FILE) {
+        error!("Could not write {}: {}", SAVE_FILE, err);
+    }
+}
@@ -0,0 +1,266 @@
+//! Astraliminal's Schedule plugin.
+//!
+//! Gameplay runs in `FixedUpdate` at `TickRate`, and physics right after it in
+//! `FixedPostUpdate`, so puzzles, replays and tests behave the same at any frame rate. Each tick
+//! runs the `AstralSet`s in order:
+//!
+//! 1. `Setup`: turn level data (glTF extras, saves, logic and room graphs) into components.
+//! 2. `Player`: move the player and what they carry, through portals and between rooms.
+//! 3. `Sense`: read the world: triggers, the current room, plates, viewpoints.
+//! 4. `React`: the logic graph, dimension shifts, materializing, duplicating, checkpoints.
+//! 5. `Move`: movers and what rests on them.
+//! 6. `Resolve`: lost objects and which rooms are loaded.
+//!
+//! Reading input stays in `Update`, which sends events such as `Grab` and `Interacted` for the
+//! next tick. Bevy's time plugin only clears events in frames after a tick ran, so a press is
+//! never dropped however many frames pass between ticks, and is seen by one tick only. Everything
+//! visual (fades, portal cameras) also runs every frame. `Interpolated` entities are drawn between
+//! their last two ticks so motion looks smooth at any frame rate.
+
+use bevy::{prelude::*, transform::TransformSystem};
+use bevy_xpbd_3d::prelude::*;
+
+use crate::player::Player;
+
+/// Default gameplay ticks per second.
+const TICK_RATE: f64 = 60.0;
+/// Distance an entity may move in one tick before it counts as a teleport and isn't smoothed.
+const SNAP_DISTANCE: f32 = 2.0;
+
+/// Gameplay system sets, run in this order in `FixedUpdate`.
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AstralSet {
+    Setup,
+    Player,
+    Sense,
+    React,
+    Move,
+    Resolve,
+}
+
+/// Gameplay ticks per second. Physics steps once per tick.
+#[derive(Resource, Reflect, Debug, Clone, Copy, PartialEq)]
+#[reflect(Resource)]
+pub struct TickRate(pub f64);
+
+impl Default for TickRate {
+    fn default() -> Self {
+        Self(TICK_RATE)
+    }
+}
+
+/// Marker for entities drawn between their last two gameplay ticks. Added to the player and
+/// non-static bodies for you.
+#[derive(Component, Reflect, Debug, Default, Clone, Copy)]
+#[reflect(Component)]
+pub struct Interpolated;
+
+/// The transforms an `Interpolated` entity had at its last two ticks, and the one drawn since.
+#[derive(Component, Debug, Clone, Copy)]
+struct InterpolationState {
+    previous: Transform,
+    current: Transform,
+    drawn: Option<Transform>,
+}
+
+impl InterpolationState {
+    fn new(transform: Transform) -> Self {
+        Self {
+            previous: transform,
+            current: transform,
+            drawn: None,
+        }
+    }
+
+    /// Whether something outside of the ticks moved the entity, e.g. a rewind or checkpoint.
+    fn moved_elsewhere(&self, transform: &Transform) -> bool {
+        *transform != self.drawn.unwrap_or(self.current)
+    }
+}
+
+pub struct AstraliminalSchedulePlugin;
+
+impl Plugin for AstraliminalSchedulePlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<TickRate>()
+            .register_type::<Interpolated>()
+            .init_resource::<TickRate>()
+            .configure_sets(
+                FixedUpdate,
+                (
+                    AstralSet::Setup,
+                    AstralSet::Player,
+                    AstralSet::Sense,
+                    AstralSet::React,
+                    AstralSet::Move,
+                    AstralSet::Resolve,
+                )
+                    .chain(),
+            )
+            .add_systems(First, apply_tick_rate)
+            .add_systems(FixedFirst, (add_interpolation, restore_current).chain())
+            .add_systems(FixedLast, record_current)
+            .add_systems(
+                PostUpdate,
+                interpolate.before(TransformSystem::TransformPropagate),
+            );
+    }
+}
+
+/// Set the gameplay and physics timesteps from `TickRate`.
+fn apply_tick_rate(
+    mut commands: Commands,
+    rate: Res<TickRate>,
+    mut fixed: ResMut<Time<Fixed>>,
+    physics: Option<Res<Time<Physics>>>,
+) {
+    if !rate.is_changed() {
+        return;
+    }
+
+    fixed.set_timestep_hz(rate.0);
+    let mut physics_time = Time::new_with(Physics::fixed_once_hz(rate.0));
+    if physics.is_some_and(|physics| physics.is_paused()) {
+        physics_time.pause();
+    }
+    commands.insert_resource(physics_time);
+}
+
+/// Start tracking new players and bodies.
+#[allow(clippy::type_complexity)]
+fn add_interpolation(
+    mut commands: Commands,
+    entities: Query<
+        (Entity, &Transform, Option<&RigidBody>, Has<Interpolated>),
+        (
+            Or<(Added<Interpolated>, Added<RigidBody>, Added<Player>)>,
+            Without<InterpolationState>,
+        ),
+    >,
+) {
+    for (entity, transform, body, interpolated) in &entities {
+        if interpolated || !body.is_some_and(RigidBody::is_static) {
+            commands
+                .entity(entity)
+                .insert((Interpolated, InterpolationState::new(*transform)));
+        }
+    }
+}
+
+/// Put entities back where gameplay left them before the next tick.
+fn restore_current(mut entities: Query<(&mut Transform, &mut InterpolationState)>) {
+    for (mut transform, mut state) in &mut entities {
+        if state.moved_elsewhere(&transform) {
+            *state = InterpolationState::new(*transform);
+        } else {
+            *transform = state.current;
+            state.drawn = None;
+        }
+    }
+}
+
+/// Remember where entities are after the tick.
+fn record_current(mut entities: Query<(&Transform, &mut InterpolationState)>) {
+    for (transform, mut state) in &mut entities {
+        state.previous = state.current;
+        state.current = *transform;
+        if state
+            .previous
+            .translation
+            .distance(state.current.translation)
+            > SNAP_DISTANCE
+        {
+            state.previous = state.current;
+        }
+    }
+}
+
+/// Draw entities between their last two ticks.
+fn interpolate(
+    fixed: Res<Time<Fixed>>,
+    mut entities: Query<(&mut Transform, &mut InterpolationState)>,
+) {
+    let t = fixed.overstep_fraction();
+    for (mut transform, mut state) in &mut entities {
+        if state.moved_elsewhere(&transform) {
+            *state = InterpolationState::new(*transform);
+            continue;
+        }
+
+        let drawn = Transform {
+            translation: state
+                .previous
+                .translation
+                .lerp(state.current.translation, t),
+            rotation: state.previous.rotation.slerp(state.current.rotation, t),
+            scale: state.previous.scale.lerp(state.current.scale, t),
+        };
+        *transform = drawn;
+        state.drawn = Some(drawn);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::time::TimeUpdateStrategy;
+
+    use super::*;
+    use crate::{interact::Interacted, player::Grab};
+
+    #[derive(Resource, Default)]
+    struct Seen(usize);
+
+    fn count(
+        mut grabs: EventReader<Grab>,
+        mut interacted: EventReader<Interacted>,
+        mut seen: ResMut<Seen>,
+    ) {
+        seen.0 += grabs.read().count() + interacted.read().count();
+    }
+
+    /// An app ticking at 60 Hz with frames of `frame_millis`, counting the input events ticks see.
+    fn app(frame_millis: u64) -> App {
+        let mut app = App::new();
+        let frame = Duration::from_millis(frame_millis);
+        app.add_plugins(MinimalPlugins)
+            .insert_resource(TimeUpdateStrategy::ManualDuration(frame))
+            .init_resource::<Seen>()
+            .add_event::<Grab>()
+            .add_event::<Interacted>()
+            .add_systems(FixedUpdate, count.in_set(AstralSet::React));
+        app.update();
+        app
+    }
+
+    fn press(app: &mut App) {
+        app.world.send_event(Grab {
+            entity: Entity::PLACEHOLDER,
+            point: Vec3::ZERO,
+        });
+        app.world.send_event(Interacted {
+            entity: Entity::PLACEHOLDER,
+            id: "lever".to_string(),
+        });
+    }
+
+    #[test]
+    fn ticks_see_every_press_once() {
+        // Many frames per tick.
+        let mut fast = app(1);
+        press(&mut fast);
+        for _ in 0..100 {
+            fast.update();
+        }
+        assert_eq!(fast.world.resource::<Seen>().0, 2);
+
+        // Many ticks per frame.
+        let mut slow = app(100);
+        press(&mut slow);
+        for _ in 0..5 {
+            slow.update();
+        }
+        assert_eq!(slow.world.resource::<Seen>().0, 2);
+    }
+}
@@ -0,0 +1,96 @@
+//! Astraliminal's Settings plugin.
+//!
+//! Tuning values are read from `astraliminal.settings.ron`, or the file set as `GameSettingsFile`
+//! before startup, and copied into their resources once it has loaded, and again whenever it
+//! changes. Anything the file leaves out keeps its default, and so does a value that can't be
+//! used, e.g. a tick rate of 0.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    level::LoadingSettings, rewind::RewindSettings, ron_loader::RonLoader,
+    room_graph::RoomGraphSettings, schedule::TickRate,
+};
+
+/// The settings file loaded unless another one is set.
+const SETTINGS_FILE: &str = "astraliminal.settings.ron";
+
+/// Tuning values, loaded from a `.settings.ron` file.
+#[derive(Asset, TypePath, Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameSettings {
+    /// Gameplay ticks per second.
+    pub tick_rate: Option<f64>,
+    pub rewind: Option<RewindSettings>,
+    pub rooms: Option<RoomGraphSettings>,
+    pub loading: Option<LoadingSettings>,
+}
+
+/// The game's settings file.
+#[derive(Resource, Debug, Default, Clone)]
+pub struct GameSettingsFile(pub Handle<GameSettings>);
+
+pub struct AstraliminalSettingsPlugin;
+
+impl Plugin for AstraliminalSettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<GameSettings>()
+            .register_asset_loader(RonLoader::<GameSettings>::new(&["settings.ron"]))
+            .add_systems(Startup, load_settings)
+            .add_systems(
+                Update,
+                apply_settings.run_if(resource_exists::<GameSettingsFile>),
+            );
+    }
+}
+
+/// Load the settings file, unless one is set already.
+fn load_settings(
+    mut commands: Commands,
+    file: Option<Res<GameSettingsFile>>,
+    asset_server: Res<AssetServer>,
+) {
+    if file.is_none() {
+        commands.insert_resource(GameSettingsFile(asset_server.load(SETTINGS_FILE)));
+    }
+}
+
+/// Copy the settings into their resources whenever the file loads or changes.
+fn apply_settings(
+    mut events: EventReader<AssetEvent<GameSettings>>,
+    mut tick_rate: ResMut<TickRate>,
+    mut rewind: ResMut<RewindSettings>,
+    mut rooms: ResMut<RoomGraphSettings>,
+    mut loading: ResMut<LoadingSettings>,
+    file: Res<GameSettingsFile>,
+    settings: Res<Assets<GameSettings>>,
+) {
+    let changed = events
+        .read()
+        .any(|event| event.is_loaded_with_dependencies(&file.0) || event.is_modified(&file.0));
+    let Some(settings) = settings.get(&file.0).filter(|_| changed) else {
+        return;
+    };
+
+    match settings.tick_rate {
+        Some(rate) if rate.is_finite() && rate > 0.0 => {
+            tick_rate.set_if_neq(TickRate(rate));
+        }
+        Some(rate) => warn!(
+            "Ignoring tick rate {}, it must be above 0, keeping {}",
+            rate, tick_rate.0
+        ),
+        None => {}
+    }
+    if let Some(settings) = &settings.rewind {
+        rewind.set_if_neq(*settings);
+    }
+    if let Some(settings) = &settings.rooms {
+        rooms.set_if_neq(settings.clone());
+    }
+    if let Some(settings) = &settings.loading {
+        loading.set_if_neq(*settings);
+    }
+    info!("Settings applied");
+}
@@ -0,0 +1,173 @@
+//! Astraliminal's Story plugin.
+//!
+//! Story flags record what has happened so far, e.g. `met_seekers`. The `Blackboard` holds typed
+//! story variables, e.g. `trust = 3`, and the dialogue nodes reached. Dialogue scripts, triggers
+//! and logic graphs read them with `Condition`s and change them with `Effects`, through the
+//! `Story` system parameter. Unlocking an achievement sends `AchievementUnlocked`, and reaching an
+//! ending sends `ReachEnding` for the ending plugin.
+//!
+//! Flags, the blackboard and achievements are saved with the game.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+
+use crate::{
+    ending::{EndingsSeen, ReachEnding},
+    expression::{Condition, Effects, Scope, ScopeMut, Value},
+    save::{SaveData, SaveSet},
+};
+
+/// The story flags that are set.
+#[derive(Resource, Debug, Default, Clone, PartialEq, Eq)]
+pub struct StoryFlags(pub BTreeSet<String>);
+
+impl StoryFlags {
+    /// Whether the flag is set.
+    pub fn is_set(&self, flag: &str) -> bool {
+        self.0.contains(flag)
+    }
+}
+
+/// Story variables and the dialogue nodes reached.
+#[derive(Resource, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Blackboard {
+    pub variables: BTreeMap<String, Value>,
+    /// Ids of the dialogue nodes reached.
+    pub visited: BTreeSet<String>,
+}
+
+/// The achievements unlocked. Unlike the rest of the story, checkpoints never take them back.
+#[derive(Resource, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Achievements(pub BTreeSet<String>);
+
+/// Sent when an achievement is unlocked for the first time.
+#[derive(Event, Debug, Clone, PartialEq, Eq)]
+pub struct AchievementUnlocked {
+    pub id: String,
+}
+
+/// Everything conditions read and effects change.
+#[derive(SystemParam)]
+pub struct Story<'w> {
+    pub flags: ResMut<'w, StoryFlags>,
+    pub blackboard: ResMut<'w, Blackboard>,
+    pub achievements: ResMut<'w, Achievements>,
+    pub endings_seen: Res<'w, EndingsSeen>,
+    unlocked: EventWriter<'w, AchievementUnlocked>,
+    endings: EventWriter<'w, ReachEnding>,
+}
+
+impl Story<'_> {
+    /// Whether the condition holds. Conditions that can't be evaluated are reported and don't.
+    pub fn check(&self, condition: &Condition) -> bool {
+        condition.evaluate(self).unwrap_or_else(|err| {
+            warn!("Story condition failed: {}", err);
+            false
+        })
+    }
+
+    /// Whether the condition holds, or true without one.
+    pub fn allows(&self, condition: Option<&Condition>) -> bool {
+        condition.is_none_or(|condition| self.check(condition))
+    }
+
+    /// Apply the effects. Effects that fail are reported, and the ones after them skipped.
+    pub fn apply(&mut self, effects: &Effects) {
+        if let Err(err) = effects.apply(self) {
+            warn!("Story effects failed: {}", err);
+        }
+    }
+}
+
+impl Scope for Story<'_> {
+    fn variable(&self, name: &str) -> Option<&Value> {
+        self.blackboard.variables.get(name)
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.flags.is_set(name)
+    }
+
+    fn visited(&self, node: &str) -> bool {
+        self.blackboard.visited.contains(node)
+    }
+
+    fn unlocked(&self, achievement: &str) -> bool {
+        self.achievements.0.contains(achievement)
+    }
+
+    fn seen_ending(&self, ending: &str) -> bool {
+        self.endings_seen.0.contains(ending)
+    }
+}
+
+impl ScopeMut for Story<'_> {
+    fn set_flag(&mut self, name: &str, set: bool) {
+        if set {
+            self.flags.0.insert(name.to_string());
+        } else {
+            self.flags.0.remove(name);
+        }
+    }
+
+    fn set_variable(&mut self, name: &str, value: Value) {
+        self.blackboard.variables.insert(name.to_string(), value);
+    }
+
+    fn unlock(&mut self, achievement: &str) {
+        if self.achievements.0.insert(achievement.to_string()) {
+            info!("Achievement unlocked: {}", achievement);
+            self.unlocked.send(AchievementUnlocked {
+                id: achievement.to_string(),
+            });
+        }
+    }
+
+    fn reach_ending(&mut self, ending: &str) {
+        self.endings.send(ReachEnding {
+            id: ending.to_string(),
+        });
+    }
+}
+
+pub struct AstraliminalStoryPlugin;
+
+impl Plugin for AstraliminalStoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<StoryFlags>()
+            .init_resource::<Blackboard>()
+            .init_resource::<Achievements>()
+            .init_resource::<EndingsSeen>()
+            .add_event::<AchievementUnlocked>()
+            .add_event::<ReachEnding>()
+            .add_systems(PreUpdate, restore_story.in_set(SaveSet::Restore))
+            .add_systems(Last, collect_story.in_set(SaveSet::Collect));
+    }
+}
+
+/// Start with the story from the save data.
+fn restore_story(
+    mut flags: ResMut<StoryFlags>,
+    mut blackboard: ResMut<Blackboard>,
+    mut achievements: ResMut<Achievements>,
+    save: Res<SaveData>,
+) {
+    flags.0 = save.story_flags.clone();
+    blackboard.variables = save.variables.clone();
+    blackboard.visited = save.visited_dialogue.clone();
+    achievements.0 = save.achievements.clone();
+}
+
+/// Copy the story into the save data.
+fn collect_story(
+    mut save: ResMut<SaveData>,
+    flags: Res<StoryFlags>,
+    blackboard: Res<Blackboard>,
+    achievements: Res<Achievements>,
+) {
+    save.story_flags = flags.0.clone();
+    save.variables = blackboard.variables.clone();
+    save.visited_dialogue = blackboard.visited.clone();
+    save.achievements = achievements.0.clone();
+}
@@ -0,0 +1,663 @@
+//! Astraliminal's Timeline plugin.
+//!
+//! Cutscenes are `Timeline`s, loaded from `.timeline.ron` files. Tracks play side by side and
+//! each track plays its clips one after the other: camera moves along splines, entity moves,
+//! animation clips, dialogue lines, audio cues, fades and waits.
+//!
+//! Send `PlayTimeline` to play one. While it plays, player input can be blocked and the skip key
+//! jumps to the end, where every move is finished but no more lines or sounds are played.
+//! `TimelineFinished` is sent either way. The sequencer only follows `Time`, so it can be stepped
+//! headless.
+
+use bevy::{audio::Volume, prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    fade::ScreenFade,
+    mover::spline_point,
+    player::{InputBlocked, PlayerCamera},
+    ron_loader::RonLoader,
+};
+
+/// Reason in `InputBlocked` while a timeline plays.
+const BLOCK_REASON: &str = "timeline";
+
+/// A cutscene, loaded from a `.timeline.ron` file.
+#[derive(Asset, TypePath, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Timeline {
+    /// Whether player input is ignored while the timeline plays.
+    pub block_input: bool,
+    /// Whether the player can skip the timeline.
+    pub skippable: bool,
+    pub tracks: Vec<TimelineTrack>,
+}
+
+impl Default for Timeline {
+    fn default() -> Self {
+        Self {
+            block_input: true,
+            skippable: true,
+            tracks: Vec::new(),
+        }
+    }
+}
+
+impl Timeline {
+    /// Seconds until the last clip ends.
+    pub fn duration(&self) -> f32 {
+        self.tracks
+            .iter()
+            .map(TimelineTrack::duration)
+            .fold(0.0, f32::max)
+    }
+
+    /// The clips that have started by `elapsed`, by track and index, with how far along they are
+    /// from 0 to 1.
+    pub fn clips_at(
+        &self,
+        elapsed: f32,
+    ) -> impl Iterator<Item = ((usize, usize), &TimelineClip, f32)> {
+        self.tracks
+            .iter()
+            .enumerate()
+            .flat_map(move |(track, clips)| {
+                let mut start = 0.0;
+                clips
+                    .clips
+                    .iter()
+                    .enumerate()
+                    .map_while(move |(index, clip)| {
+                        if elapsed < start {
+                            return None;
+                        }
+                        let seconds = clip.seconds.max(0.0);
+                        let progress = if seconds > 0.0 {
+                            ((elapsed - start) / seconds).min(1.0)
+                        } else {
+                            1.0
+                        };
+                        start += seconds;
+                        Some(((track, index), clip, progress))
+                    })
+            })
+    }
+
+    /// Whether any clip moves the camera.
+    fn moves_camera(&self) -> bool {
+        self.tracks
+            .iter()
+            .flat_map(|track| &track.clips)
+            .any(|clip| matches!(clip.action, TimelineAction::Camera { .. }))
+    }
+}
+
+/// Clips played one after the other.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TimelineTrack {
+    pub clips: Vec<TimelineClip>,
+}
+
+impl TimelineTrack {
+    /// Seconds until the last clip ends.
+    pub fn duration(&self) -> f32 {
+        self.clips.iter().map(|clip| clip.seconds.max(0.0)).sum()
+    }
+}
+
+/// Something a timeline does, and for how long.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TimelineClip {
+    /// Seconds the clip takes. The next clip on the track starts after them.
+    #[serde(default)]
+    pub seconds: f32,
+    pub action: TimelineAction,
+}
+
+/// What a clip does. Entities are found by `Name`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TimelineAction {
+    /// Move the `TimelineCamera` along a Catmull-Rom spline through `points`, looking at `look_at`
+    /// or else along the path.
+    Camera {
+        points: Vec<Vec3>,
+        #[serde(default)]
+        look_at: Option<Vec3>,
+    },
+    /// Move every entity named `target` from where it is to the given pose. Parts that aren't set
+    /// are kept. `rotation` is in degrees of yaw (Y), pitch (X) and roll (Z).
+    Move {
+        target: String,
+        #[serde(default)]
+        translation: Option<Vec3>,
+        #[serde(default)]
+        rotation: Option<Vec3>,
+        #[serde(default)]
+        scale: Option<Vec3>,
+    },
+    /// Play an animation clip, e.g. `"models/boat.glb#Animation0"`, on the `AnimationPlayer` of
+    /// every entity named `target` or their descendants.
+    Animation {
+        target: String,
+        clip: String,
+        #[serde(default)]
+        repeat: bool,
+    },
+    /// Send a `SpokenLine`, shown for the clip's seconds.
+    Dialogue { speaker: String, text: String },
+    /// Play a sound once.
+    Audio {
+        path: String,
+        #[serde(default)]
+        volume: Option<f32>,
+    },
+    /// Fade the screen to this opacity over the clip's seconds.
+    Fade(f32),
+    /// Do nothing for the clip's seconds.
+    Wait,
+}
+
+/// Marker for the camera that `TimelineAction::Camera` clips move. It is made the active camera
+/// while such a timeline plays.
+#[derive(Component, Reflect, Debug, Default, Clone, Copy)]
+#[reflect(Component)]
+pub struct TimelineCamera;
+
+/// Send to play a timeline. Ignored while another one plays.
+#[derive(Event, Debug, Clone)]
+pub struct PlayTimeline(pub Handle<Timeline>);
+
+/// Send to skip the playing timeline, if it is skippable.
+#[derive(Event, Debug, Default, Clone, Copy)]
+pub struct SkipTimeline;
+
+/// Sent when a timeline has ended.
+#[derive(Event, Debug, Clone)]
+pub struct TimelineFinished {
+    pub timeline: Handle<Timeline>,
+    pub skipped: bool,
+}
+
+/// Sent when a timeline speaks a line.
+#[derive(Event, Debug, Clone, PartialEq)]
+pub struct SpokenLine {
+    pub speaker: String,
+    pub text: String,
+    /// Seconds the line should be shown.
+    pub seconds: f32,
+}
+
+/// The timeline being played, if any.
+#[derive(Resource, Debug, Default)]
+pub struct Sequencer {
+    playing: Option<Playback>,
+}
+
+impl Sequencer {
+    /// Whether a timeline is playing.
+    pub fn is_playing(&self) -> bool {
+        self.playing.is_some()
+    }
+
+    /// The playing timeline and the seconds it has played.
+    pub fn playing(&self) -> Option<(&Handle<Timeline>, f32)> {
+        self.playing
+            .as_ref()
+            .map(|playback| (&playback.timeline, playback.elapsed))
+    }
+}
+
+/// A timeline being played.
+#[derive(Debug)]
+struct Playback {
+    timeline: Handle<Timeline>,
+    elapsed: f32,
+    /// Whether input was blocked and cameras switched. Waits for the timeline to load.
+    started: bool,
+    clips: HashMap<(usize, usize), ClipState>,
+}
+
+/// How far a started clip is.
+#[derive(Debug, Clone)]
+enum ClipState {
+    /// Playing, with where the moved entities were when it started.
+    Playing(Vec<(Entity, Transform)>),
+    Done,
+}
+
+pub struct AstraliminalTimelinePlugin;
+
+impl Plugin for AstraliminalTimelinePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<Timeline>()
+            .register_asset_loader(RonLoader::<Timeline>::new(&["timeline.ron"]))
+            .register_type::<TimelineCamera>()
+            .init_resource::<Sequencer>()
+            .add_event::<PlayTimeline>()
+            .add_event::<SkipTimeline>()
+            .add_event::<TimelineFinished>()
+            .add_event::<SpokenLine>()
+            .add_systems(
+                Update,
+                (skip_input, start_timelines, play_timelines).chain(),
+            );
+    }
+}
+
+/// Skip the playing timeline when the skip key is pressed.
+fn skip_input(
+    keys: Res<ButtonInput<KeyCode>>,
+    sequencer: Res<Sequencer>,
+    mut skips: EventWriter<SkipTimeline>,
+) {
+    // TODO: pull key code from config.
+    if sequencer.is_playing() && keys.just_pressed(KeyCode::Space) {
+        skips.send(SkipTimeline);
+    }
+}
+
+/// Start playing requested timelines.
+fn start_timelines(mut sequencer: ResMut<Sequencer>, mut requests: EventReader<PlayTimeline>) {
+    for PlayTimeline(timeline) in requests.read() {
+        if sequencer.is_playing() {
+            warn!("A timeline is already playing, ignoring {:?}", timeline);
+            continue;
+        }
+        sequencer.playing = Some(Playback {
+            timeline: timeline.clone(),
+            elapsed: 0.0,
+            started: false,
+            clips: HashMap::default(),
+        });
+    }
+}
+
+/// What timelines move by name.
+type TargetQuery<'w, 's> = Query<
+    'w,
+    's,
+    (Entity, &'static Name, &'static mut Transform),
+    (Without<TimelineCamera>, Without<PlayerCamera>),
+>;
+
+/// Cameras a timeline switches between.
+type CameraQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static mut Camera,
+        Option<&'static mut Transform>,
+        Has<TimelineCamera>,
+    ),
+    Or<(With<TimelineCamera>, With<PlayerCamera>)>,
+>;
+
+/// Advance the playing timeline and apply its clips.
+#[allow(clippy::too_many_arguments)]
+fn play_timelines(
+    mut commands: Commands,
+    mut sequencer: ResMut<Sequencer>,
+    mut skips: EventReader<SkipTimeline>,
+    mut finished: EventWriter<TimelineFinished>,
+    mut lines: EventWriter<SpokenLine>,
+    mut blocked: ResMut<InputBlocked>,
+    mut fade: ResMut<ScreenFade>,
+    time: Res<Time>,
+    timelines: Res<Assets<Timeline>>,
+    asset_server: Res<AssetServer>,
+    mut targets: TargetQuery,
+    mut cameras: CameraQuery,
+    children: Query<&Children>,
+    mut animation_players: Query<&mut AnimationPlayer>,
+) {
+    let skip_requested = skips.read().count() > 0;
+    let Some(playback) = &mut sequencer.playing else {
+        return;
+    };
+    let Some(timeline) = timelines.get(&playback.timeline) else {
+        // Still loading.
+        return;
+    };
+
+    if !playback.started {
+        playback.started = true;
+        if timeline.block_input {
+            blocked.0.insert(BLOCK_REASON.to_string());
+        }
+        if timeline.moves_camera() {
+            use_timeline_camera(&mut cameras, true);
+        }
+    }
+
+    let skipped = skip_requested && timeline.skippable;
+    let duration = timeline.duration();
+    playback.elapsed = if skipped {
+        duration
+    } else {
+        (playback.elapsed + time.delta_seconds()).min(duration)
+    };
+
+    for (id, clip, progress) in timeline.clips_at(playback.elapsed) {
+        let state = playback.clips.entry(id).or_insert_with(|| {
+            // Clips passed over by a skip only have their lasting effects.
+            match &clip.action {
+                TimelineAction::Move { target, .. } => {
+                    return ClipState::Playing(
+                        targets
+                            .iter()
+                            .filter(|(_, name, _)| name.as_str() == target)
+                            .map(|(entity, _, transform)| (entity, *transform))
+                            .collect(),
+                    );
+                }
+                TimelineAction::Animation {
+                    target,
+                    clip,
+                    repeat,
+                } if !skipped => {
+                    let clip: Handle<AnimationClip> = asset_server.load(clip.clone());
+                    for (entity, name, _) in &targets {
+                        if name.as_str() != target {
+                            continue;
+                        }
+                        let players = std::iter::once(entity)
+                            .chain(children.iter_descendants(entity))
+                            .collect::<Vec<_>>();
+                        for player in players {
+                            if let Ok(mut player) = animation_players.get_mut(player) {
+                                player.play(clip.clone());
+                                if *repeat {
+                                    player.repeat();
+                                }
+                            }
+                        }
+                    }
+                }
+                TimelineAction::Dialogue { speaker, text } if !skipped => {
+                    lines.send(SpokenLine {
+                        speaker: speaker.clone(),
+                        text: text.clone(),
+                        seconds: clip.seconds,
+                    });
+                }
+                TimelineAction::Audio { path, volume } if !skipped => {
+                    commands.spawn(AudioBundle {
+                        source: asset_server.load(path.clone()),
+                        settings: PlaybackSettings::DESPAWN
+                            .with_volume(Volume::new(volume.unwrap_or(1.0))),
+                    });
+                }
+                TimelineAction::Fade(alpha) => {
+                    let seconds = if skipped { 0.0 } else { clip.seconds };
+                    fade.fade_to(*alpha, seconds);
+                }
+                _ => {}
+            }
+            ClipState::Playing(Vec::new())
+        });
+
+        let ClipState::Playing(from) = state else {
+            continue;
+        };
+        match &clip.action {
+            TimelineAction::Camera { points, look_at } => {
+                for (_, transform, is_timeline_camera) in &mut cameras {
+                    if let (true, Some(mut transform)) = (is_timeline_camera, transform) {
+                        *transform = camera_pose(points, *look_at, progress);
+                    }
+                }
+            }
+            TimelineAction::Move {
+                translation,
+                rotation,
+                scale,
+                ..
+            } => {
+                for (entity, from) in from.iter() {
+                    let Ok((_, _, mut transform)) = targets.get_mut(*entity) else {
+                        continue;
+                    };
+                    let rotation = rotation.map(|degrees| {
+                        Quat::from_euler(
+                            EulerRot::YXZ,
+                            degrees.y.to_radians(),
+                            degrees.x.to_radians(),
+                            degrees.z.to_radians(),
+                        )
+                    });
+                    *transform = Transform {
+                        translation: from
+                            .translation
+                            .lerp(translation.unwrap_or(from.translation), progress),
+                        rotation: from
+                            .rotation
+                            .slerp(rotation.unwrap_or(from.rotation), progress),
+                        scale: from.scale.lerp(scale.unwrap_or(from.scale), progress),
+                    };
+                }
+            }
+            _ => {}
+        }
+        if progress >= 1.0 {
+            *state = ClipState::Done;
+        }
+    }
+
+    if playback.elapsed >= duration {
+        blocked.0.remove(BLOCK_REASON);
+        if timeline.moves_camera() {
+            use_timeline_camera(&mut cameras, false);
+        }
+        finished.send(TimelineFinished {
+            timeline: playback.timeline.clone(),
+            skipped,
+        });
+        sequencer.playing = None;
+    }
+}
+
+/// Switch between the timeline camera and the player camera, if there is a timeline camera.
+fn use_timeline_camera(cameras: &mut CameraQuery, timeline: bool) {
+    if !cameras
+        .iter()
+        .any(|(_, _, is_timeline_camera)| is_timeline_camera)
+    {
+        return;
+    }
+    for (mut camera, _, is_timeline_camera) in cameras.iter_mut() {
+        camera.is_active = is_timeline_camera == timeline;
+    }
+}
+
+/// Where a camera following a spline through `points` is at `progress`.
+fn camera_pose(points: &[Vec3], look_at: Option<Vec3>, progress: f32) -> Transform {
+    let Some((&first, rest)) = points.split_first() else {
+        return Transform::default();
+    };
+    let rest: Vec<Vec3> = rest.iter().map(|point| *point - first).collect();
+    let position = |progress: f32| first + spline_point(&rest, progress.clamp(0.0, 1.0));
+
+    let here = position(progress);
+    let target = look_at.unwrap_or_else(|| {
+        // Look along the path, or back along it at its very end.
+        let ahead = position(progress + 0.01);
+        if ahead.distance_squared(here) > f32::EPSILON {
+            ahead
+        } else {
+            here + (here - position(progress - 0.01))
+        }
+    });
+
+    let transform = Transform::from_translation(here);
+    if target.distance_squared(here) > f32::EPSILON {
+        transform.looking_at(target, Vec3::Y)
+    } else {
+        transform
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::time::TimeUpdateStrategy;
+
+    use super::*;
+
+    /// A headless app stepping 0.1 seconds a frame.
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+            .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_millis(
+                100,
+            )))
+            .init_resource::<ScreenFade>()
+            .init_resource::<InputBlocked>()
+            .init_resource::<ButtonInput<KeyCode>>()
+            .add_plugins(AstraliminalTimelinePlugin);
+        app.update();
+        app
+    }
+
+    fn timeline(skippable: bool) -> Timeline {
+        Timeline {
+            skippable,
+            tracks: vec![
+                TimelineTrack {
+                    clips: vec![TimelineClip {
+                        seconds: 1.0,
+                        action: TimelineAction::Camera {
+                            points: vec![Vec3::ZERO, Vec3::X],
+                            look_at: None,
+                        },
+                    }],
+                },
+                TimelineTrack {
+                    clips: vec![
+                        TimelineClip {
+                            seconds: 0.5,
+                            action: TimelineAction::Wait,
+                        },
+                        TimelineClip {
+                            seconds: 0.5,
+                            action: TimelineAction::Move {
+                                target: "boat".to_string(),
+                                translation: Some(Vec3::Z),
+                                rotation: None,
+                                scale: None,
+                            },
+                        },
+                        TimelineClip {
+                            seconds: 0.0,
+                            action: TimelineAction::Dialogue {
+                                speaker: "Ferryman".to_string(),
+                                text: "We're here.".to_string(),
+                            },
+                        },
+                    ],
+                },
+            ],
+            ..default()
+        }
+    }
+
+    /// Play `timeline` with a player camera, a timeline camera and a boat to move.
+    fn play(app: &mut App, timeline: Timeline) -> (Entity, Entity, Entity) {
+        let player_camera = app
+            .world
+            .spawn((Camera::default(), Transform::default(), PlayerCamera))
+            .id();
+        let timeline_camera = app
+            .world
+            .spawn((
+                Camera {
+                    is_active: false,
+                    ..default()
+                },
+                Transform::default(),
+                TimelineCamera,
+            ))
+            .id();
+        let boat = app
+            .world
+            .spawn((Name::new("boat"), Transform::default()))
+            .id();
+        let handle = app.world.resource_mut::<Assets<Timeline>>().add(timeline);
+        app.world.send_event(PlayTimeline(handle));
+        app.update();
+        (player_camera, timeline_camera, boat)
+    }
+
+    fn is_active(app: &App, camera: Entity) -> bool {
+        app.world.get::<Camera>(camera).unwrap().is_active
+    }
+
+    fn finished(app: &App) -> Vec<bool> {
+        let events = app.world.resource::<Events<TimelineFinished>>();
+        let mut reader = events.get_reader();
+        reader
+            .read(events)
+            .map(|finished| finished.skipped)
+            .collect()
+    }
+
+    #[test]
+    fn plays_to_the_end() {
+        let mut app = app();
+        let (player_camera, timeline_camera, boat) = play(&mut app, timeline(true));
+        assert!(app.world.resource::<InputBlocked>().is_blocked());
+        assert!(is_active(&app, timeline_camera));
+        assert!(!is_active(&app, player_camera));
+
+        for _ in 0..7 {
+            app.update();
+        }
+        let (_, elapsed) = app.world.resource::<Sequencer>().playing().unwrap();
+        assert!((elapsed - 0.8).abs() < 1e-4);
+        let z = app.world.get::<Transform>(boat).unwrap().translation.z;
+        assert!((z - 0.6).abs() < 1e-4);
+        let camera = app.world.get::<Transform>(timeline_camera).unwrap();
+        assert!(camera.translation.x > 0.5 && camera.translation.x < 1.0);
+
+        app.update();
+        app.update();
+        assert!(!app.world.resource::<Sequencer>().is_playing());
+        assert_eq!(finished(&app), vec![false]);
+        assert_eq!(
+            app.world.get::<Transform>(boat).unwrap().translation,
+            Vec3::Z
+        );
+        assert!(!app.world.resource::<InputBlocked>().is_blocked());
+        assert!(is_active(&app, player_camera));
+        assert!(!is_active(&app, timeline_camera));
+        let lines = app.world.resource::<Events<SpokenLine>>();
+        assert_eq!(lines.get_reader().read(lines).count(), 1);
+    }
+
+    #[test]
+    fn skipping_finishes_moves_but_says_nothing() {
+        let mut app = app();
+        let (.., boat) = play(&mut app, timeline(true));
+        app.world.send_event(SkipTimeline);
+        app.update();
+        assert!(!app.world.resource::<Sequencer>().is_playing());
+        assert_eq!(finished(&app), vec![true]);
+        assert_eq!(
+            app.world.get::<Transform>(boat).unwrap().translation,
+            Vec3::Z
+        );
+        assert!(!app.world.resource::<InputBlocked>().is_blocked());
+        let lines = app.world.resource::<Events<SpokenLine>>();
+        assert_eq!(lines.get_reader().read(lines).count(), 0);
+    }
+
+    #[test]
+    fn unskippable_timelines_keep_playing() {
+        let mut app = app();
+        play(&mut app, timeline(false));
+        app.world.send_event(SkipTimeline);
+        app.update();
+        assert!(app.world.resource::<Sequencer>().is_playing());
+        assert!(finished(&app).is_empty());
+    }
+}
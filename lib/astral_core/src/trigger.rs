@@ -0,0 +1,462 @@
+//! Astraliminal's Trigger plugin.
+//!
+//! A `TriggerVolume` is a sensor that reports what goes in and out of it as typed events, for
+//! level scripts, dialogue and achievements to react to. A `TriggerStory` changes the story when
+//! something enters, e.g. `set(reached_docks); unlock_achievement(ferryman)`.
+//!
+//! Triggers can be placed in Blender: add an Empty with the "Cube" display type and set custom
+//! properties on it, e.g. `trigger = "boat_deck"` and `trigger_filter = "player"`, or
+//! `trigger_effects = "set(on_boat)"`. See
+//! `TriggerExtras` for all properties.
+
+use bevy::{gltf::GltfExtras, prelude::*, utils::HashSet};
+use bevy_xpbd_3d::prelude::*;
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::{
+    expression::{Condition, Effects},
+    player::Player,
+    schedule::AstralSet,
+    story::Story,
+};
+
+/// A sensor volume that sends `TriggerEntered`, `TriggerStayed` and `TriggerExited` for entities
+/// that pass its filter. Needs a `Collider`; `Sensor` and `CollidingEntities` are added for you.
+#[derive(Component, Reflect, Debug, Default, Clone)]
+#[reflect(Component)]
+pub struct TriggerVolume {
+    /// Id used by level data to refer to this trigger.
+    pub id: String,
+    /// Which entities the trigger reacts to.
+    pub filter: TriggerFilter,
+}
+
+/// Which entities a trigger reacts to. An entity must pass every part that is set.
+#[derive(Reflect, Debug, Default, Clone, PartialEq)]
+pub struct TriggerFilter {
+    /// Only react to the player.
+    pub player_only: bool,
+    /// Only react to entities with one of these names. Empty means any name.
+    pub names: Vec<String>,
+    /// Only react to entities with one of these tags. Empty means any tags.
+    pub tags: Vec<String>,
+    /// Only react to entities whose largest scale axis is within `min..=max`.
+    pub scale: Option<(f32, f32)>,
+}
+
+impl TriggerFilter {
+    /// Whether an entity with the given properties passes the filter.
+    pub fn matches(
+        &self,
+        is_player: bool,
+        name: Option<&Name>,
+        tags: Option<&Tags>,
+        scale: Vec3,
+    ) -> bool {
+        if self.player_only && !is_player {
+            return false;
+        }
+        if !self.names.is_empty()
+            && !name.is_some_and(|name| self.names.iter().any(|n| n == name.as_str()))
+        {
+            return false;
+        }
+        if !self.tags.is_empty()
+            && !tags.is_some_and(|tags| self.tags.iter().any(|tag| tags.contains(tag)))
+        {
+            return false;
+        }
+        if let Some((min, max)) = self.scale {
+            let scale = scale.max_element();
+            if scale < min || scale > max {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Free-form tags for filtering, e.g. `["box", "heavy"]`.
+#[derive(Component, Reflect, Debug, Default, Clone, PartialEq, Eq)]
+#[reflect(Component)]
+pub struct Tags(pub Vec<String>);
+
+impl Tags {
+    /// Whether the entity has the given tag.
+    pub fn contains(&self, tag: &str) -> bool {
+        self.0.iter().any(|t| t == tag)
+    }
+}
+
+/// Story effects of a trigger, applied every time something it reacts to enters.
+#[derive(Component, Debug, Clone)]
+pub struct TriggerStory {
+    /// The effects only apply while this holds.
+    pub condition: Option<Condition>,
+    pub effects: Effects,
+}
+
+/// The entities that passed the filter and are inside a trigger.
+#[derive(Component, Debug, Default, Clone, PartialEq, Eq)]
+pub struct TriggerOccupants(pub HashSet<Entity>);
+
+/// Sent when an entity enters a trigger.
+#[derive(Event, Debug, Clone)]
+pub struct TriggerEntered {
+    pub trigger: Entity,
+    pub id: String,
+    pub entity: Entity,
+}
+
+/// Sent every frame an entity stays in a trigger, after the frame it entered.
+#[derive(Event, Debug, Clone)]
+pub struct TriggerStayed {
+    pub trigger: Entity,
+    pub id: String,
+    pub entity: Entity,
+}
+
+/// Sent when an entity leaves a trigger, or stops passing its filter.
+#[derive(Event, Debug, Clone)]
+pub struct TriggerExited {
+    pub trigger: Entity,
+    pub id: String,
+    pub entity: Entity,
+}
+
+/// Custom properties read from Blender objects through glTF extras.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct TriggerExtras {
+    /// Id of the trigger. The object only becomes a trigger if this is set.
+    trigger: Option<String>,
+    /// `"player"` to only react to the player.
+    trigger_filter: Option<String>,
+    /// Comma-separated names to react to.
+    trigger_names: Option<String>,
+    /// Comma-separated tags to react to.
+    trigger_tags: Option<String>,
+    /// Smallest scale to react to.
+    trigger_scale_min: Option<f32>,
+    /// Largest scale to react to.
+    trigger_scale_max: Option<f32>,
+    /// Story condition for `trigger_effects`.
+    trigger_condition: Option<String>,
+    /// Story effects applied when something enters.
+    trigger_effects: Option<String>,
+    /// Comma-separated tags for the object itself.
+    tags: Option<String>,
+}
+
+/// Split a comma-separated custom property into its parts.
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+pub struct AstraliminalTriggerPlugin;
+
+impl Plugin for AstraliminalTriggerPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<TriggerVolume>()
+            .register_type::<Tags>()
+            .add_event::<TriggerEntered>()
+            .add_event::<TriggerStayed>()
+            .add_event::<TriggerExited>()
+            .add_systems(
+                FixedUpdate,
+                (
+                    (triggers_from_extras, prepare_triggers)
+                        .chain()
+                        .in_set(AstralSet::Setup),
+                    update_triggers.in_set(AstralSet::Sense),
+                    apply_trigger_story.in_set(AstralSet::React),
+                ),
+            );
+    }
+}
+
+/// Read the custom properties of a Blender object. Other plugins read their own properties from
+/// the same objects and can skip ones that fail: `triggers_from_extras` reports the errors.
+pub(crate) fn read_extras<T: DeserializeOwned>(extras: &GltfExtras) -> serde_json::Result<T> {
+    serde_json::from_str(&extras.value)
+}
+
+/// The collider of a Blender Empty with the "Cube" display type and a display size of 1, which
+/// spans -1..1 on each axis before its scale.
+pub(crate) fn cube_empty_collider() -> Collider {
+    Collider::cuboid(2.0, 2.0, 2.0)
+}
+
+/// Turn Blender objects with trigger custom properties into triggers.
+fn triggers_from_extras(
+    mut commands: Commands,
+    extras: Query<(Entity, &GltfExtras), Added<GltfExtras>>,
+) {
+    for (entity, extras) in &extras {
+        let extras: TriggerExtras = match read_extras(extras) {
+            Ok(extras) => extras,
+            Err(err) => {
+                warn!("Ignoring custom properties of {:?}: {}", entity, err);
+                continue;
+            }
+        };
+
+        if let Some(tags) = &extras.tags {
+            commands.entity(entity).insert(Tags(split_list(tags)));
+        }
+
+        let Some(id) = extras.trigger else {
+            continue;
+        };
+        let scale = match (extras.trigger_scale_min, extras.trigger_scale_max) {
+            (None, None) => None,
+            (min, max) => Some((min.unwrap_or(0.0), max.unwrap_or(f32::INFINITY))),
+        };
+        let filter = TriggerFilter {
+            player_only: extras.trigger_filter.as_deref() == Some("player"),
+            names: extras
+                .trigger_names
+                .as_deref()
+                .map(split_list)
+                .unwrap_or_default(),
+            tags: extras
+                .trigger_tags
+                .as_deref()
+                .map(split_list)
+                .unwrap_or_default(),
+            scale,
+        };
+
+        if let Some(effects) = &extras.trigger_effects {
+            let condition = extras
+                .trigger_condition
+                .as_deref()
+                .map(str::parse)
+                .transpose();
+            match (condition, effects.parse()) {
+                (Ok(condition), Ok(effects)) => {
+                    commands
+                        .entity(entity)
+                        .insert(TriggerStory { condition, effects });
+                }
+                (Err(err), _) | (_, Err(err)) => {
+                    warn!("Ignoring story of trigger {}: {}", id, err);
+                }
+            }
+        }
+
+        commands
+            .entity(entity)
+            .insert((TriggerVolume { id, filter }, cube_empty_collider()));
+    }
+}
+
+/// Add what a trigger needs to detect overlaps.
+fn prepare_triggers(
+    mut commands: Commands,
+    triggers: Query<Entity, (Added<TriggerVolume>, Without<TriggerOccupants>)>,
+) {
+    for entity in &triggers {
+        commands.entity(entity).insert((
+            Sensor,
+            CollidingEntities::default(),
+            TriggerOccupants::default(),
+        ));
+    }
+}
+
+/// Compare what is in each trigger with last frame and send the events.
+#[allow(clippy::type_complexity)]
+pub(crate) fn update_triggers(
+    mut triggers: Query<(
+        Entity,
+        &TriggerVolume,
+        &CollidingEntities,
+        &mut TriggerOccupants,
+    )>,
+    mut entered: EventWriter<TriggerEntered>,
+    mut stayed: EventWriter<TriggerStayed>,
+    mut exited: EventWriter<TriggerExited>,
+    others: Query<(Has<Player>, Option<&Name>, Option<&Tags>, &GlobalTransform)>,
+) {
+    for (trigger, volume, colliding, mut occupants) in &mut triggers {
+        let inside: HashSet<Entity> = colliding
+            .iter()
+            .copied()
+            .filter(|&entity| {
+                others
+                    .get(entity)
+                    .is_ok_and(|(is_player, name, tags, transform)| {
+                        let (scale, _, _) = transform.to_scale_rotation_translation();
+                        volume.filter.matches(is_player, name, tags, scale)
+                    })
+            })
+            .collect();
+
+        for &entity in occupants.0.difference(&inside) {
+            exited.send(TriggerExited {
+                trigger,
+                id: volume.id.clone(),
+                entity,
+            });
+        }
+        for &entity in &inside {
+            if occupants.0.contains(&entity) {
+                stayed.send(TriggerStayed {
+                    trigger,
+                    id: volume.id.clone(),
+                    entity,
+                });
+            } else {
+                entered.send(TriggerEntered {
+                    trigger,
+                    id: volume.id.clone(),
+                    entity,
+                });
+            }
+        }
+
+        if occupants.0 != inside {
+            occupants.0 = inside;
+        }
+    }
+}
+
+/// Apply the story effects of triggers something entered.
+fn apply_trigger_story(
+    mut entered: EventReader<TriggerEntered>,
+    mut story: Story,
+    triggers: Query<&TriggerStory>,
+) {
+    for event in entered.read() {
+        let Ok(trigger) = triggers.get(event.trigger) else {
+            continue;
+        };
+        if story.allows(trigger.condition.as_ref()) {
+            story.apply(&trigger.effects);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+
+    fn tags(tags: &[&str]) -> Tags {
+        Tags(tags.iter().map(|tag| tag.to_string()).collect())
+    }
+
+    #[test]
+    fn player_filter() {
+        let filter = TriggerFilter {
+            player_only: true,
+            ..default()
+        };
+        assert!(filter.matches(true, None, None, Vec3::ONE));
+        assert!(!filter.matches(false, None, None, Vec3::ONE));
+        assert!(TriggerFilter::default().matches(false, None, None, Vec3::ONE));
+    }
+
+    #[test]
+    fn name_filter() {
+        let filter = TriggerFilter {
+            names: vec!["crate".to_string(), "ball".to_string()],
+            ..default()
+        };
+        assert!(filter.matches(false, Some(&Name::new("ball")), None, Vec3::ONE));
+        assert!(!filter.matches(false, Some(&Name::new("barrel")), None, Vec3::ONE));
+        assert!(!filter.matches(false, None, None, Vec3::ONE));
+    }
+
+    #[test]
+    fn scale_filter_includes_bounds() {
+        let filter = TriggerFilter {
+            scale: Some((0.5, 2.0)),
+            ..default()
+        };
+        let matches = |scale: Vec3| filter.matches(false, None, None, scale);
+        assert!(matches(Vec3::splat(0.5)));
+        assert!(matches(Vec3::splat(2.0)));
+        assert!(matches(Vec3::ONE));
+        assert!(!matches(Vec3::splat(0.49)));
+        assert!(!matches(Vec3::splat(2.01)));
+        // The largest axis counts.
+        assert!(matches(Vec3::new(0.1, 0.1, 0.5)));
+        assert!(!matches(Vec3::new(1.0, 3.0, 1.0)));
+    }
+
+    #[test]
+    fn tag_filter() {
+        let filter = TriggerFilter {
+            tags: vec!["heavy".to_string(), "metal".to_string()],
+            ..default()
+        };
+        assert!(filter.matches(false, None, Some(&tags(&["box", "heavy"])), Vec3::ONE));
+        assert!(!filter.matches(false, None, Some(&tags(&["box"])), Vec3::ONE));
+        assert!(!filter.matches(false, None, Some(&tags(&[])), Vec3::ONE));
+        assert!(!filter.matches(false, None, None, Vec3::ONE));
+    }
+
+    #[test]
+    fn every_part_must_pass() {
+        let filter = TriggerFilter {
+            player_only: true,
+            tags: vec!["heavy".to_string()],
+            ..default()
+        };
+        let heavy = tags(&["heavy"]);
+        assert!(filter.matches(true, None, Some(&heavy), Vec3::ONE));
+        assert!(!filter.matches(false, None, Some(&heavy), Vec3::ONE));
+        assert!(!filter.matches(true, None, None, Vec3::ONE));
+    }
+
+    #[test]
+    fn lists_are_split_on_commas() {
+        assert_eq!(
+            split_list("box, heavy ,metal"),
+            vec!["box", "heavy", "metal"]
+        );
+        assert_eq!(split_list(" box,, ,"), vec!["box"]);
+        assert!(split_list("").is_empty());
+    }
+
+    #[test]
+    fn tags_and_filters_from_extras() {
+        let mut world = World::new();
+        let extras = |value: &str| GltfExtras {
+            value: value.to_string(),
+        };
+        let object = world.spawn(extras(r#"{"tags": "box, heavy"}"#)).id();
+        let trigger = world
+            .spawn(extras(
+                r#"{"trigger": "scale", "trigger_filter": "player",
+                    "trigger_tags": "heavy,metal", "trigger_scale_min": 0.5}"#,
+            ))
+            .id();
+        world.run_system_once(triggers_from_extras);
+
+        let object_tags = world.get::<Tags>(object).unwrap();
+        assert_eq!(object_tags, &tags(&["box", "heavy"]));
+        assert!(object_tags.contains("heavy"));
+        assert!(!object_tags.contains("hea"));
+        assert!(!world.entity(object).contains::<TriggerVolume>());
+
+        let volume = world.get::<TriggerVolume>(trigger).unwrap();
+        assert_eq!(volume.id, "scale");
+        assert_eq!(
+            volume.filter,
+            TriggerFilter {
+                player_only: true,
+                names: Vec::new(),
+                tags: vec!["heavy".to_string(), "metal".to_string()],
+                scale: Some((0.5, f32::INFINITY)),
+            }
+        );
+    }
+}
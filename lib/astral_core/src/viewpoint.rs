@@ -0,0 +1,81 @@
+//! Astraliminal's Viewpoint plugin.
+//!
+//! Forced perspective puzzles only line up when seen from one spot. A `Viewpoint` describes that
+//! spot and `ViewpointAligned` is kept on the entity for as long as the player camera is there.
+
+use bevy::prelude::*;
+
+use crate::{player::PlayerCamera, schedule::AstralSet};
+
+/// The place an entity is meant to be viewed from.
+#[derive(Component, Reflect, Debug, Clone)]
+#[reflect(Component)]
+pub struct Viewpoint {
+    /// World-space position the camera must be at.
+    pub position: Vec3,
+    /// How far the camera may be from `position`, in world units.
+    pub tolerance: f32,
+    /// Largest angle, in radians, between the camera's forward and the direction to the entity.
+    pub max_angle: f32,
+}
+
+impl Default for Viewpoint {
+    fn default() -> Self {
+        Self {
+            position: Vec3::ZERO,
+            tolerance: 0.25,
+            max_angle: 5.0_f32.to_radians(),
+        }
+    }
+}
+
+impl Viewpoint {
+    /// Whether a camera at `camera` lines up with this viewpoint when looking at `target`.
+    pub fn is_aligned(&self, camera: &GlobalTransform, target: Vec3) -> bool {
+        let eye = camera.translation();
+        if eye.distance(self.position) > self.tolerance {
+            return false;
+        }
+
+        let Some(to_target) = (target - eye).try_normalize() else {
+            return false;
+        };
+
+        camera.forward().angle_between(to_target) <= self.max_angle
+    }
+}
+
+/// Present on a `Viewpoint` entity while the player camera is aligned with it.
+#[derive(Component, Reflect, Debug, Default, Clone, Copy)]
+#[reflect(Component)]
+pub struct ViewpointAligned;
+
+pub struct AstraliminalViewpointPlugin;
+
+impl Plugin for AstraliminalViewpointPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Viewpoint>()
+            .register_type::<ViewpointAligned>()
+            .add_systems(FixedUpdate, update_alignment.in_set(AstralSet::Sense));
+    }
+}
+
+/// Insert or remove `ViewpointAligned` depending on where the player camera is.
+fn update_alignment(
+    mut commands: Commands,
+    camera: Query<&GlobalTransform, With<PlayerCamera>>,
+    viewpoints: Query<(Entity, &Viewpoint, &GlobalTransform, Has<ViewpointAligned>)>,
+) {
+    let Ok(camera) = camera.get_single() else {
+        return;
+    };
+
+    for (entity, viewpoint, transform, was_aligned) in &viewpoints {
+        let aligned = viewpoint.is_aligned(camera, transform.translation());
+        if aligned && !was_aligned {
+            commands.entity(entity).insert(ViewpointAligned);
+        } else if !aligned && was_aligned {
+            commands.entity(entity).remove::<ViewpointAligned>();
+        }
+    }
+}
@@ -0,0 +1,368 @@
+//! Astraliminal's Water plugin.
+//!
+//! `Water` is an analytic surface: a still level plus a sum of travelling sine waves, so its
+//! height and normal can be asked for anywhere without a mesh. `Buoyant` bodies sample it at a
+//! few points, are pushed up by the water those points displace and are slowed while submerged,
+//! which makes them bob and tilt with the waves.
+
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+
+use crate::schedule::AstralSet;
+
+/// A sine wave travelling across the water.
+#[derive(Reflect, Debug, Clone, Copy, PartialEq)]
+pub struct Wave {
+    /// Height of the crests above the still level.
+    pub amplitude: f32,
+    /// Distance between crests.
+    pub wavelength: f32,
+    /// Distance the crests travel per second.
+    pub speed: f32,
+    /// Direction the crests travel in, on the XZ plane.
+    pub direction: Vec2,
+}
+
+impl Default for Wave {
+    fn default() -> Self {
+        Self {
+            amplitude: 0.2,
+            wavelength: 10.0,
+            speed: 2.0,
+            direction: Vec2::X,
+        }
+    }
+}
+
+impl Wave {
+    /// Crests per unit of distance, in radians.
+    fn wavenumber(&self) -> f32 {
+        TAU / self.wavelength.max(f32::EPSILON)
+    }
+
+    fn phase(&self, point: Vec2, time: f32) -> f32 {
+        let along = self.direction.normalize_or_zero().dot(point);
+        self.wavenumber() * (along - self.speed * time)
+    }
+
+    /// Height above the still level at `point` on the XZ plane.
+    pub fn height(&self, point: Vec2, time: f32) -> f32 {
+        self.amplitude * self.phase(point, time).sin()
+    }
+
+    /// Rate of change of the height along X and Z at `point`.
+    pub fn slope(&self, point: Vec2, time: f32) -> Vec2 {
+        self.direction.normalize_or_zero()
+            * (self.amplitude * self.wavenumber() * self.phase(point, time).cos())
+    }
+}
+
+/// The water surface.
+#[derive(Resource, Reflect, Debug, Clone, PartialEq)]
+#[reflect(Resource)]
+pub struct Water {
+    /// Height of the surface without waves.
+    pub level: f32,
+    /// Mass of a cubic unit of water.
+    pub density: f32,
+    /// How fast submerged bodies lose their velocity, per second.
+    pub drag: f32,
+    /// How fast submerged bodies lose their angular velocity, per second.
+    pub angular_drag: f32,
+    pub waves: Vec<Wave>,
+}
+
+impl Default for Water {
+    fn default() -> Self {
+        Self {
+            level: 0.0,
+            density: 1000.0,
+            drag: 1.0,
+            angular_drag: 1.0,
+            waves: Vec::new(),
+        }
+    }
+}
+
+impl Water {
+    /// Height of the surface at `point` on the XZ plane.
+    pub fn height(&self, point: Vec2, time: f32) -> f32 {
+        self.level
+            + self
+                .waves
+                .iter()
+                .map(|wave| wave.height(point, time))
+                .sum::<f32>()
+    }
+
+    /// Upward normal of the surface at `point` on the XZ plane.
+    pub fn normal(&self, point: Vec2, time: f32) -> Vec3 {
+        let slope: Vec2 = self.waves.iter().map(|wave| wave.slope(point, time)).sum();
+        Vec3::new(-slope.x, 1.0, -slope.y).normalize()
+    }
+
+    /// How far `point` is below the surface. Negative above it.
+    pub fn depth(&self, point: Vec3, time: f32) -> f32 {
+        self.height(point.xz(), time) - point.y
+    }
+}
+
+/// A dynamic body that floats. A non-persistent `ExternalForce` is added for you.
+#[derive(Component, Reflect, Debug, Clone, PartialEq)]
+#[reflect(Component)]
+pub struct Buoyant {
+    /// Points where the water is sampled, in local space. Spread them over the hull; each
+    /// displaces an equal share of `volume`.
+    pub points: Vec<Vec3>,
+    /// Water displaced when every point is fully submerged.
+    pub volume: f32,
+    /// Depth at which a point is fully submerged.
+    pub depth: f32,
+}
+
+impl Default for Buoyant {
+    fn default() -> Self {
+        Self {
+            points: vec![Vec3::ZERO],
+            volume: 1.0,
+            depth: 1.0,
+        }
+    }
+}
+
+impl Buoyant {
+    /// How much of a point at `depth` below the surface is submerged, from 0 to 1.
+    pub fn submersion(&self, depth: f32) -> f32 {
+        (depth / self.depth.max(f32::EPSILON)).clamp(0.0, 1.0)
+    }
+
+    /// The world position of each point of a body at `position` and `rotation`, and the force the
+    /// water puts on it.
+    pub fn forces(
+        &self,
+        position: Vec3,
+        rotation: Quat,
+        water: &Water,
+        time: f32,
+        gravity: Vec3,
+    ) -> Vec<(Vec3, Vec3)> {
+        let share = self.volume / self.points.len().max(1) as f32;
+        self.points
+            .iter()
+            .map(|&local| {
+                let point = position + rotation * local;
+                let submersion = self.submersion(water.depth(point, time));
+                (point, -gravity * water.density * share * submersion)
+            })
+            .collect()
+    }
+
+    /// How much of the body is submerged, from 0 to 1.
+    pub fn submerged(&self, position: Vec3, rotation: Quat, water: &Water, time: f32) -> f32 {
+        if self.points.is_empty() {
+            return 0.0;
+        }
+        let total: f32 = self
+            .points
+            .iter()
+            .map(|&local| self.submersion(water.depth(position + rotation * local, time)))
+            .sum();
+        total / self.points.len() as f32
+    }
+}
+
+pub struct AstraliminalWaterPlugin;
+
+impl Plugin for AstraliminalWaterPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Wave>()
+            .register_type::<Water>()
+            .register_type::<Buoyant>()
+            .init_resource::<Water>()
+            .add_systems(
+                FixedUpdate,
+                (
+                    prepare_buoyant.in_set(AstralSet::Setup),
+                    float.in_set(AstralSet::Move),
+                ),
+            );
+    }
+}
+
+/// Give new buoyant bodies a force for the water to push with.
+fn prepare_buoyant(
+    mut commands: Commands,
+    bodies: Query<Entity, (Added<Buoyant>, Without<ExternalForce>)>,
+) {
+    for entity in &bodies {
+        commands
+            .entity(entity)
+            .insert(ExternalForce::ZERO.with_persistence(false));
+    }
+}
+
+/// Push buoyant bodies up and slow them down where they are under water.
+#[allow(clippy::type_complexity)]
+fn float(
+    water: Res<Water>,
+    gravity: Res<Gravity>,
+    time: Res<Time>,
+    mut bodies: Query<(
+        &Buoyant,
+        &Position,
+        &Rotation,
+        Option<&CenterOfMass>,
+        &mut ExternalForce,
+        &mut LinearVelocity,
+        &mut AngularVelocity,
+    )>,
+) {
+    let elapsed = time.elapsed_seconds();
+    let delta = time.delta_seconds();
+    for (buoyant, position, rotation, center, mut force, mut linear, mut angular) in &mut bodies {
+        let center = rotation.0 * center.map_or(Vec3::ZERO, |center| center.0);
+        for (point, push) in buoyant.forces(position.0, rotation.0, &water, elapsed, gravity.0) {
+            force.apply_force_at_point(push, point - position.0, center);
+        }
+
+        let submerged = buoyant.submerged(position.0, rotation.0, &water, elapsed);
+        linear.0 /= 1.0 + water.drag * submerged * delta;
+        angular.0 /= 1.0 + water.angular_drag * submerged * delta;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRAVITY: Vec3 = Vec3::new(0.0, -9.81, 0.0);
+
+    fn waves() -> Water {
+        Water {
+            level: 1.0,
+            waves: vec![
+                Wave::default(),
+                Wave {
+                    amplitude: 0.1,
+                    wavelength: 3.0,
+                    speed: 1.0,
+                    direction: Vec2::new(1.0, 2.0),
+                },
+            ],
+            ..default()
+        }
+    }
+
+    #[test]
+    fn still_water_is_flat() {
+        let water = Water::default();
+        for point in [Vec2::ZERO, Vec2::new(12.5, -3.0), Vec2::splat(-100.0)] {
+            assert_eq!(water.height(point, 7.0), 0.0);
+            assert_eq!(water.normal(point, 7.0), Vec3::Y);
+        }
+        assert_eq!(water.depth(Vec3::new(1.0, -2.0, 3.0), 0.0), 2.0);
+    }
+
+    #[test]
+    fn waves_repeat_and_travel() {
+        let wave = Wave::default();
+        let point = Vec2::new(1.3, 4.0);
+        assert!((wave.height(point, 0.0) - wave.height(point + Vec2::X * 10.0, 0.0)).abs() < 1e-4);
+        // The crest moves `speed` units along `direction` every second.
+        assert!((wave.height(point, 0.0) - wave.height(point + Vec2::X * 2.0, 1.0)).abs() < 1e-4);
+        // Crests run along Z, so moving along them doesn't change the height.
+        assert!((wave.height(point, 0.0) - wave.height(point + Vec2::Y * 5.0, 0.0)).abs() < 1e-5);
+
+        let water = waves();
+        for i in 0..100 {
+            let point = Vec2::new(i as f32 * 0.37, i as f32 * -0.21);
+            let offset = water.height(point, i as f32 * 0.1) - water.level;
+            assert!(offset.abs() <= 0.3 + 1e-5);
+        }
+    }
+
+    #[test]
+    fn normal_follows_slope() {
+        let water = waves();
+        let step = 1e-3;
+        for i in 0..20 {
+            let point = Vec2::new(i as f32 * 0.9, i as f32 * 0.4);
+            let time = i as f32 * 0.3;
+            let dx = (water.height(point + Vec2::X * step, time)
+                - water.height(point - Vec2::X * step, time))
+                / (2.0 * step);
+            let dz = (water.height(point + Vec2::Y * step, time)
+                - water.height(point - Vec2::Y * step, time))
+                / (2.0 * step);
+            let expected = Vec3::new(-dx, 1.0, -dz).normalize();
+            let normal = water.normal(point, time);
+            assert!(normal.abs_diff_eq(expected, 1e-2), "{normal} != {expected}");
+        }
+    }
+
+    #[test]
+    fn buoyancy_grows_with_submersion() {
+        let water = Water::default();
+        let buoyant = Buoyant {
+            points: vec![Vec3::new(-1.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0)],
+            volume: 2.0,
+            depth: 1.0,
+        };
+        let lift = |y: f32| -> f32 {
+            buoyant
+                .forces(Vec3::Y * y, Quat::IDENTITY, &water, 0.0, GRAVITY)
+                .iter()
+                .map(|(_, force)| force.y)
+                .sum()
+        };
+
+        let full = water.density * 9.81 * buoyant.volume;
+        assert_eq!(lift(0.5), 0.0);
+        assert!((lift(-0.5) - full / 2.0).abs() < 1e-2);
+        assert!((lift(-1.0) - full).abs() < 1e-2);
+        assert!((lift(-10.0) - full).abs() < 1e-2);
+        assert_eq!(
+            buoyant.submerged(Vec3::Y * -0.25, Quat::IDENTITY, &water, 0.0),
+            0.25
+        );
+
+        // Tilted into the water, only the lower side is pushed.
+        let tilted = Quat::from_rotation_z(0.3);
+        let forces = buoyant.forces(Vec3::ZERO, tilted, &water, 0.0, GRAVITY);
+        let (low, high) = if forces[0].0.y < forces[1].0.y {
+            (forces[0].1, forces[1].1)
+        } else {
+            (forces[1].1, forces[0].1)
+        };
+        assert!(low.y > 0.0);
+        assert_eq!(high, Vec3::ZERO);
+    }
+
+    #[test]
+    fn floating_body_settles_where_it_displaces_its_mass() {
+        let water = Water::default();
+        let buoyant = Buoyant {
+            points: vec![Vec3::ZERO],
+            volume: 1.0,
+            depth: 1.0,
+        };
+        // Half as dense as water, so it should float half submerged.
+        let mass = 500.0;
+        let (mut y, mut velocity) = (2.0_f32, 0.0_f32);
+        let delta = 1.0 / 60.0;
+        for _ in 0..60 * 30 {
+            let lift: f32 = buoyant
+                .forces(Vec3::Y * y, Quat::IDENTITY, &water, 0.0, GRAVITY)
+                .iter()
+                .map(|(_, force)| force.y)
+                .sum();
+            velocity += (lift / mass + GRAVITY.y) * delta;
+            let submerged = buoyant.submerged(Vec3::Y * y, Quat::IDENTITY, &water, 0.0);
+            velocity /= 1.0 + water.drag * submerged * delta;
+            y += velocity * delta;
+        }
+        assert!((y + 0.5).abs() < 1e-2, "settled at {y}");
+    }
+}
@@ -1,16 +1,22 @@
 //! Astraliminal's Window plugin.
 
+use std::time::Duration;
+
 use bevy::{
-    app::AppExit,
+    app::{AppExit, ScheduleRunnerPlugin},
     core::FrameCount,
     prelude::*,
-    window::{Cursor, CursorGrabMode, Window, WindowMode, WindowPlugin, WindowResolution},
+    render::{settings::WgpuSettings, RenderPlugin},
+    window::{
+        Cursor, CursorGrabMode, ExitCondition, Window, WindowMode, WindowPlugin, WindowResolution,
+    },
+    winit::WinitPlugin,
 };
 
 /// Astraliminal's version.
-const ASTRAL_VERSION: &str = env!("CARGO_PKG_VERSION");
+pub(crate) const ASTRAL_VERSION: &str = env!("CARGO_PKG_VERSION");
 /// Astraliminal's compile datetime. See `../build.rs`.
-const ASTRAL_COMPILE_DATETIME: &str = env!("ASTRAL_COMPILE_DATETIME");
+pub(crate) const ASTRAL_COMPILE_DATETIME: &str = env!("ASTRAL_COMPILE_DATETIME");
 /// Default window height
 const WINDOW_HEIGHT: f32 = 768.0;
 /// Default window width
@@ -54,6 +60,33 @@ impl Plugin for AstraliminalWindowPlugin {
     }
 }
 
+/// Runs the game without a window or GPU, as fast as possible, e.g. to play back a replay on a
+/// build server. Use instead of `AstraliminalWindowPlugin`.
+pub struct AstraliminalHeadlessPlugin;
+
+impl Plugin for AstraliminalHeadlessPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((
+            DefaultPlugins
+                .set(WindowPlugin {
+                    primary_window: None,
+                    exit_condition: ExitCondition::DontExit,
+                    ..default()
+                })
+                .set(RenderPlugin {
+                    render_creation: WgpuSettings {
+                        backends: None,
+                        ..default()
+                    }
+                    .into(),
+                    ..default()
+                })
+                .disable::<WinitPlugin>(),
+            ScheduleRunnerPlugin::run_loop(Duration::ZERO),
+        ));
+    }
+}
+
 /// Make the window visible. Depends on the frame count when starting up. This is to get
 /// rid of the annoying white window at startup.
 fn make_visible(mut window: Query<&mut Window>, frames: Res<FrameCount>) {